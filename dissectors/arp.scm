@@ -0,0 +1,24 @@
+(define (dissect-arp bv pinfo)
+  (let* ((op (uint16-be bv 6))
+         (sha (mac-addr bv 8))
+         (spa (ipv4-addr bv 14))
+         (tha (mac-addr bv 18))
+         (tpa (ipv4-addr bv 24))
+         (arp (add-subtree pinfo #f "arp" "Address Resolution Protocol"
+                           (if (= op 1) "request" "reply") 0 28)))
+    (set-proto pinfo "ARP")
+    (set-src pinfo spa)
+    (set-dst pinfo tpa)
+    (set-info pinfo (if (= op 1)
+                        (string-append "Who has " tpa "? Tell " spa)
+                        (string-append spa " is at " sha)))
+    (add-field pinfo arp "arp.hw.type" "Hardware type" (uint16-be bv 0) 0 2)
+    (add-field pinfo arp "arp.proto.type" "Protocol type" (uint16-be bv 2) 2 2)
+    (add-field pinfo arp "arp.opcode" "Opcode" op 6 2)
+    (add-field pinfo arp "arp.src.hw_mac" "Sender MAC address" sha 8 6)
+    (add-field pinfo arp "arp.src.proto_ipv4" "Sender IP address" spa 14 4)
+    (add-field pinfo arp "arp.dst.hw_mac" "Target MAC address" tha 18 6)
+    (add-field pinfo arp "arp.dst.proto_ipv4" "Target IP address" tpa 24 4)))
+
+(register-dissector 'ethertype #x0806 dissect-arp)
+
@@ -0,0 +1,59 @@
+// Capture sources shared by the window and the headless mode.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use pcap;
+use time;
+
+use wire_shake_core::pcapfile::{self, FrameHeader, LINKTYPE_ETHERNET};
+
+pub fn frame_header(hdr: &pcap::PacketHeader) -> FrameHeader {
+    FrameHeader {
+        ts: time::Timespec::new(hdr.ts.tv_sec as i64, (hdr.ts.tv_usec * 1000) as i32),
+        caplen: hdr.caplen,
+        len: hdr.len,
+    }
+}
+
+// Live packets are stamped to the microsecond.
+pub fn device_link(cap: &pcap::Capture<pcap::Active>) -> pcapfile::Link {
+    pcapfile::Link { linktype: cap.get_datalink().0 as u32, ts_units: 1_000_000 }
+}
+
+fn string_to_dev(s: String) -> Option<pcap::Capture<pcap::Inactive>> {
+    if let Ok(mut l) = pcap::Device::list() {
+        if let Some(i) = l.iter().position(|ref d| d.name == s) {
+            if let Ok(cap) = pcap::Capture::from_device(l.remove(i)) {
+                return Some(cap.promisc(true).timeout(300));
+            }
+        }
+    }
+    return None;
+}
+
+pub fn open_device(if_name: &str) -> Result<pcap::Capture<pcap::Active>, String> {
+    match string_to_dev(if_name.to_string()) {
+        Some(dev) => dev.open().map_err(|e| format!("{}: {}", if_name, e)),
+        None => Err(format!("{}: no such device", if_name)),
+    }
+}
+
+pub fn apply_capture_filter(cap: &mut pcap::Capture<pcap::Active>, filter: &str) -> Result<(), String> {
+    if filter.is_empty() {
+        return Ok(());
+    }
+    cap.filter(filter).map_err(|e| format!("Invalid capture filter \"{}\": {}", filter, e))
+}
+
+// pcapng files may carry no interface description before the first
+// packet, so a missing link-layer type is taken to be Ethernet.
+pub fn open_file(path: &Path) -> Result<pcapfile::Reader<BufReader<File>>, String> {
+    let rdr = pcapfile::open(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    match rdr.linktype() {
+        Some(LINKTYPE_ETHERNET) | None => Ok(rdr),
+        Some(l) => Err(format!("{}: unsupported link-layer type {}", path.display(), l)),
+    }
+}
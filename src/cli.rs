@@ -0,0 +1,357 @@
+// Headless mode: `wire_shake --cli` prints one line per packet, the full
+// detail tree with -V, or an export format with -T, instead of opening a
+// window.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use pcap;
+
+use wire_shake_core::disctr::{dissect, DissectorTable, FrameQueue, PacketInfo};
+use wire_shake_core::proto_tree::{ProtoTree, FieldNode};
+use wire_shake_core::conversation::ConversationTable;
+use wire_shake_core::pcapfile::FrameHeader;
+use wire_shake_core::dfilter;
+use wire_shake_core::export::{self, Exporter, Summary};
+use capture::{self, frame_header, open_device, apply_capture_filter};
+
+const USAGE: &'static str = "\
+Usage: wire_shake --cli (-i INTERFACE | -r FILE) [OPTION]...
+
+  -i INTERFACE   capture live from INTERFACE
+  -r FILE        read packets from a pcap or pcapng FILE
+  -f FILTER      capture filter (BPF syntax), live capture only
+  -Y FILTER      display filter; only matching packets are printed
+  -c COUNT       stop after reading COUNT packets
+  -V             print the packet detail tree instead of a summary line
+  -T FORMAT      write csv, json or pdml instead of text
+  --plugin-dir DIR
+                 also load dissector plugins from DIR
+  -h, --help     show this help";
+
+#[derive(Clone)]
+enum Source {
+    Interface(String),
+    File(PathBuf),
+}
+
+struct Options {
+    source: Source,
+    capture_filter: String,
+    display_filter: Option<dfilter::Filter>,
+    count: Option<u32>,
+    verbose: bool,
+    export: Option<export::Format>,
+}
+
+fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let mut iface = None;
+    let mut file = None;
+    let mut capture_filter = String::new();
+    let mut display_filter = None;
+    let mut count = None;
+    let mut verbose = false;
+    let mut export = None;
+
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().ok_or(format!("option {} requires an argument", arg));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-i" => iface = Some(value()?.clone()),
+            "-r" => file = Some(PathBuf::from(value()?)),
+            "-f" => capture_filter = value()?.clone(),
+            "-Y" => {
+                let text = value()?;
+                let f = dfilter::Filter::parse(text.trim())
+                    .map_err(|e| format!("invalid display filter \"{}\": {}", text, e))?;
+                display_filter = Some(f);
+            },
+            "-c" => {
+                let text = value()?;
+                match text.parse::<u32>() {
+                    Ok(n) if n > 0 => count = Some(n),
+                    _ => return Err(format!("invalid packet count \"{}\"", text)),
+                }
+            },
+            "-V" => verbose = true,
+            "-T" => {
+                let text = value()?;
+                match text.as_str() {
+                    "text" => export = None,
+                    name => match export::Format::from_name(name) {
+                        Some(f) => export = Some(f),
+                        None => return Err(format!("unknown output format \"{}\"", text)),
+                    },
+                }
+            },
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    let source = match (iface, file) {
+        (Some(i), None) => Source::Interface(i),
+        (None, Some(f)) => {
+            if !capture_filter.is_empty() {
+                return Err(String::from("capture filters only apply to live captures; use -Y with -r"));
+            }
+            Source::File(f)
+        },
+        (Some(_), Some(_)) => return Err(String::from("-i and -r cannot be used together")),
+        (None, None) => return Err(String::from("either -i or -r is required")),
+    };
+
+    Ok(Some(Options {
+        source: source,
+        capture_filter: capture_filter,
+        display_filter: display_filter,
+        count: count,
+        verbose: verbose,
+        export: export,
+    }))
+}
+
+// The packet list columns: No., Time, Source, Destination, Protocol,
+// Length and Info.
+fn summary_line(s: &Summary) -> String {
+    format!("{:>5} {} {:<15} {:<15} {:<8} {:>5} {}",
+            s.num, s.time, s.source, s.destination, s.protocol, s.length, s.info)
+}
+
+fn write_tree<W: Write>(out: &mut W, nodes: &[FieldNode], depth: usize) -> io::Result<()> {
+    for node in nodes.iter().filter(|n| !n.hidden) {
+        writeln!(out, "{:width$}{}", "", node.display_text(), width = depth * 4)?;
+        write_tree(out, &node.children, depth + 1)?;
+    }
+    Ok(())
+}
+
+enum Output<W: Write> {
+    Text(W),
+    Export(Exporter<W>),
+}
+
+struct Printer<W: Write> {
+    out: Output<W>,
+    opts: Options,
+    disct_tbl: Rc<RefCell<DissectorTable>>,
+    convs: Rc<RefCell<ConversationTable>>,
+    // Fragments wait here until they can name the frame that reassembled
+    // them.
+    queue: FrameQueue<Vec<u8>>,
+    // Set once the reader of our output has gone away, e.g. `| head`.
+    closed: bool,
+}
+
+impl<W: Write> Printer<W> {
+    fn new(out: Output<W>, opts: Options, disct_tbl: Rc<RefCell<DissectorTable>>) -> Printer<W> {
+        let convs = Rc::new(RefCell::new(ConversationTable::new()));
+        Printer {
+            out: out,
+            opts: opts,
+            disct_tbl: disct_tbl,
+            convs: convs.clone(),
+            queue: FrameQueue::new(convs),
+            closed: false,
+        }
+    }
+
+    fn packet(&mut self, n: u32, hdr: FrameHeader, data: Vec<u8>) -> Result<(), String> {
+        let res = self.print_packet(n, hdr, data);
+        self.check(res)
+    }
+
+    fn check(&mut self, res: io::Result<()>) -> Result<(), String> {
+        match res {
+            Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                self.closed = true;
+                Ok(())
+            },
+            res => res.map_err(|e| e.to_string()),
+        }
+    }
+
+    fn print_packet(&mut self, n: u32, hdr: FrameHeader, data: Vec<u8>) -> io::Result<()> {
+        let raw = data.clone();
+        let (tree, pinfo) = dissect(n, hdr, data, self.disct_tbl.clone(), self.convs.clone());
+        for w in &pinfo.warnings {
+            eprintln!("Frame {}: {}", n, w);
+        }
+        for (tree, pinfo, raw) in self.queue.push(tree, pinfo, raw) {
+            self.print(&tree, &pinfo, &raw)?;
+        }
+        Ok(())
+    }
+
+    fn print(&mut self, tree: &ProtoTree, pinfo: &PacketInfo, raw: &[u8]) -> io::Result<()> {
+        if let Some(ref f) = self.opts.display_filter {
+            if !f.matches(tree) {
+                return Ok(());
+            }
+        }
+
+        match self.out {
+            Output::Export(ref mut e) => e.packet(&Summary::from(pinfo), tree, raw),
+            Output::Text(ref mut w) => {
+                if self.opts.verbose {
+                    write_tree(w, &tree.nodes, 0)?;
+                    writeln!(w)?;
+                } else {
+                    writeln!(w, "{}", summary_line(&Summary::from(pinfo)))?;
+                }
+                w.flush()
+            },
+        }
+    }
+
+    fn finish(mut self) -> Result<(), String> {
+        for (tree, pinfo, raw) in self.queue.finish() {
+            if self.closed {
+                break;
+            }
+            let res = self.print(&tree, &pinfo, &raw);
+            self.check(res)?;
+        }
+        if self.closed {
+            return Ok(());
+        }
+        match self.out {
+            Output::Export(e) => match e.finish() {
+                Err(ref e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e.to_string()),
+                _ => Ok(()),
+            },
+            Output::Text(_) => Ok(()),
+        }
+    }
+
+    fn done(&self, n: u32) -> bool {
+        self.closed || self.opts.count.map_or(false, |c| n > c)
+    }
+}
+
+fn capture_live<W: Write>(p: &mut Printer<W>, if_name: &str) -> Result<(), String> {
+    let mut cap = open_device(if_name)?;
+    apply_capture_filter(&mut cap, p.opts.capture_filter.trim())?;
+
+    let mut n = 1;
+    while !p.done(n) {
+        match cap.next() {
+            Ok(pkt) => {
+                let hdr = frame_header(pkt.header);
+                let data = pkt.data.to_vec();
+                p.packet(n, hdr, data)?;
+                n += 1;
+            },
+            Err(pcap::Error::TimeoutExpired) => {},
+            Err(e) => return Err(format!("{}: {}", if_name, e)),
+        }
+    }
+    Ok(())
+}
+
+fn read_file<W: Write>(p: &mut Printer<W>, path: &Path) -> Result<(), String> {
+    let rdr = capture::open_file(path)?;
+
+    let mut n = 1;
+    for rec in rdr {
+        if p.done(n) {
+            break;
+        }
+        let (hdr, data) = rec.map_err(|e| format!("{}: record {}: {}", path.display(), n, e))?;
+        p.packet(n, hdr, data)?;
+        n += 1;
+    }
+    Ok(())
+}
+
+// Returns the process exit status.
+pub fn run(args: &[String], disct_tbl: Rc<RefCell<DissectorTable>>) -> i32 {
+    let opts = match parse_args(args) {
+        Ok(Some(opts)) => opts,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return 0;
+        },
+        Err(e) => {
+            eprintln!("wire_shake: {}\n{}", e, USAGE);
+            return 2;
+        },
+    };
+
+    let source = opts.source.clone();
+    let stdout = io::stdout();
+    let out = match opts.export {
+        Some(format) => match Exporter::new(stdout.lock(), format) {
+            Ok(e) => Output::Export(e),
+            Err(e) => {
+                eprintln!("wire_shake: {}", e);
+                return 1;
+            },
+        },
+        None => Output::Text(stdout.lock()),
+    };
+    let mut p = Printer::new(out, opts, disct_tbl);
+
+    let res = match source {
+        Source::Interface(ref i) => capture_live(&mut p, i),
+        Source::File(ref f) => read_file(&mut p, f),
+    }.and_then(|_| p.finish());
+
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("wire_shake: {}", e);
+            1
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Timespec;
+
+    // An Ethernet/IPv4 fragment of a UDP datagram from 10.0.0.1 to
+    // 10.0.0.2, `off` bytes into it.
+    fn fragment(off: usize, more: bool, payload: &[u8]) -> Vec<u8> {
+        let flags_off = (off / 8) as u16 | if more { 0x2000 } else { 0 };
+        let mut f = vec![0u8; 14];
+        f[12] = 0x08;
+        f.extend(&[0x45, 0, 0, 20 + payload.len() as u8, 0, 7, (flags_off >> 8) as u8, flags_off as u8,
+                   64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        f.extend(payload);
+        f
+    }
+
+    fn print(args: &[&str]) -> Vec<String> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let opts = parse_args(&args).unwrap().unwrap();
+        let frames = vec![
+            fragment(0, true, &[0, 53, 0, 53, 0, 16, 0, 0]),
+            fragment(8, false, b"abcdefgh"),
+        ];
+        let mut out = Vec::new();
+        {
+            let mut p = Printer::new(Output::Text(&mut out), opts, Rc::new(RefCell::new(DissectorTable::new())));
+            for (i, f) in frames.into_iter().enumerate() {
+                let hdr = FrameHeader { ts: Timespec::new(0, 0), caplen: f.len() as u32, len: f.len() as u32 };
+                p.packet(i as u32 + 1, hdr, f).unwrap();
+            }
+            p.finish().unwrap();
+        }
+        String::from_utf8(out).unwrap().lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn fragments_name_the_frame_that_reassembled_them() {
+        let lines = print(&["-r", "capture.pcap"]);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(" Fragmented IP protocol (reassembled in #2)"), "{}", lines[0]);
+
+        let lines = print(&["-r", "capture.pcap", "-Y", "frame.info contains \"reassembled in #2\""]);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].trim_left().starts_with("1 "));
+    }
+}
@@ -0,0 +1,413 @@
+extern crate gtk;
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+use gtk::{
+    WindowExt, WidgetExt, ButtonExt, LabelExt,
+    ComboBoxExt, ComboBoxTextExt, EntryExt,
+    TextBufferExt, TextIterExt, DialogExt,
+    FileChooserExt
+};
+
+use wire_shake_core::proto_tree::{ProtoTree, FieldValue};
+
+const FOLLOW_HEX_BYTES_PER_LINE: usize = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum StreamProto {
+    Tcp,
+    Udp,
+}
+
+impl StreamProto {
+    fn name(&self) -> &'static str {
+        match *self {
+            StreamProto::Tcp => "tcp",
+            StreamProto::Udp => "udp",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match *self {
+            StreamProto::Tcp => "TCP",
+            StreamProto::Udp => "UDP",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    Hex,
+    Raw,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Direction {
+    Both,
+    ClientToServer,
+    ServerToClient,
+}
+
+impl Direction {
+    fn shows(&self, from_client: bool) -> bool {
+        match *self {
+            Direction::Both => true,
+            Direction::ClientToServer => from_client,
+            Direction::ServerToClient => !from_client,
+        }
+    }
+}
+
+struct Chunk {
+    from_client: bool,
+    data: Vec<u8>,
+}
+
+pub struct Stream {
+    proto: StreamProto,
+    index: u32,
+    client: String,
+    server: String,
+    chunks: Vec<Chunk>,
+}
+
+impl Stream {
+    fn bytes(&self, dir: Direction) -> usize {
+        self.chunks.iter().filter(|c| dir.shows(c.from_client)).map(|c| c.data.len()).sum()
+    }
+}
+
+fn endpoint(tree: &ProtoTree, proto: StreamProto) -> Option<String> {
+    let addr = tree.find("ip.src").or_else(|| tree.find("ipv6.src"))?;
+    let port = tree.find(&format!("{}.srcport", proto.name()))?;
+    Some(match addr.typed {
+        FieldValue::Ipv6(ref a) => format!("[{}]:{}", a, port.typed),
+        ref a => format!("{}:{}", a, port.typed),
+    })
+}
+
+pub fn stream_index(tree: &ProtoTree, proto: StreamProto) -> Option<u32> {
+    match tree.find(&format!("{}.stream", proto.name()))?.typed {
+        FieldValue::UInt(n) => Some(n as u32),
+        _ => None,
+    }
+}
+
+// Payload from one packet; `seq` is the relative sequence number for TCP.
+struct Segment {
+    from_client: bool,
+    seq: u64,
+    data: Vec<u8>,
+}
+
+fn tcp_seq(tree: &ProtoTree) -> u64 {
+    match tree.find("tcp.seq").map(|n| &n.typed) {
+        Some(&FieldValue::UInt(n)) => n,
+        _ => 0,
+    }
+}
+
+// Out-of-order segments are put back in sequence order within their own
+// direction, while the turns between directions stay in capture order.
+// UDP datagrams all have sequence 0, so the stable sort leaves them as
+// captured.
+fn order_segments(segs: Vec<Segment>) -> Vec<Segment> {
+    let turns: Vec<bool> = segs.iter().map(|s| s.from_client).collect();
+    let (mut to_server, mut to_client): (Vec<Segment>, Vec<Segment>) = segs.into_iter().partition(|s| s.from_client);
+    to_server.sort_by_key(|s| s.seq);
+    to_client.sort_by_key(|s| s.seq);
+    let mut to_server = to_server.into_iter();
+    let mut to_client = to_client.into_iter();
+    turns.into_iter()
+        .filter_map(|from_client| if from_client { to_server.next() } else { to_client.next() })
+        .collect()
+}
+
+// The client is whoever sent the first packet of the stream. Consecutive
+// payloads in the same direction are merged into one chunk.
+pub fn collect<'a, I>(proto: StreamProto, index: u32, trees: I) -> Option<Stream>
+    where I: Iterator<Item = &'a ProtoTree>
+{
+    let payload_field = format!("{}.payload", proto.name());
+    let mut client: Option<String> = None;
+    let mut server: Option<String> = None;
+    let mut segs: Vec<Segment> = Vec::new();
+
+    for tree in trees {
+        if stream_index(tree, proto) != Some(index) {
+            continue;
+        }
+        if tree.find("tcp.analysis.retransmission").is_some() {
+            continue;
+        }
+        let src = match endpoint(tree, proto) {
+            Some(src) => src,
+            None => continue,
+        };
+        if client.is_none() {
+            client = Some(src.clone());
+        }
+        let from_client = client.as_ref() == Some(&src);
+        if !from_client && server.is_none() {
+            server = Some(src);
+        }
+
+        if let Some(node) = tree.find(&payload_field) {
+            if let FieldValue::Bytes(ref b) = node.typed {
+                let seq = if proto == StreamProto::Tcp { tcp_seq(tree) } else { 0 };
+                segs.push(Segment { from_client: from_client, seq: seq, data: b.clone() });
+            }
+        }
+    }
+
+    let mut chunks: Vec<Chunk> = Vec::new();
+    for seg in order_segments(segs) {
+        if chunks.last().map_or(false, |c| c.from_client == seg.from_client) {
+            chunks.last_mut().unwrap().data.extend_from_slice(&seg.data);
+        } else {
+            chunks.push(Chunk { from_client: seg.from_client, data: seg.data });
+        }
+    }
+
+    client.map(|client| Stream {
+        proto: proto,
+        index: index,
+        client: client,
+        server: server.unwrap_or_default(),
+        chunks: chunks,
+    })
+}
+
+fn render(data: &[u8], format: Format, offset: usize, indent: bool) -> String {
+    let mut out = String::new();
+    match format {
+        Format::Ascii => {
+            for &b in data {
+                match b {
+                    b'\n' | b'\t' => out.push(b as char),
+                    b'\r' => {},
+                    0x20..=0x7e => out.push(b as char),
+                    _ => out.push('.'),
+                }
+            }
+            if !out.ends_with('\n') {
+                out.push('\n');
+            }
+        },
+        Format::Hex => {
+            for (i, line) in data.chunks(FOLLOW_HEX_BYTES_PER_LINE).enumerate() {
+                if indent {
+                    out.push_str("    ");
+                }
+                out.push_str(&format!("{:08X}  ", offset + i * FOLLOW_HEX_BYTES_PER_LINE));
+                for (j, b) in line.iter().enumerate() {
+                    out.push_str(&format!("{:02x} ", b));
+                    if j == 7 { out.push(' '); }
+                }
+                for j in line.len()..FOLLOW_HEX_BYTES_PER_LINE {
+                    out.push_str("   ");
+                    if j == 7 { out.push(' '); }
+                }
+                out.push(' ');
+                for &b in line {
+                    out.push(if b >= 0x20 && b < 0x7f { b as char } else { '.' });
+                }
+                out.push('\n');
+            }
+        },
+        Format::Raw => {
+            for b in data {
+                out.push_str(&format!("{:02x}", b));
+            }
+            out.push('\n');
+        },
+    }
+    out
+}
+
+fn fill_buffer(buf: &gtk::TextBuffer, stream: &Stream, format: Format, dir: Direction) {
+    buf.set_text("");
+    let (mut client_off, mut server_off) = (0, 0);
+    for chunk in stream.chunks.iter().filter(|c| dir.shows(c.from_client)) {
+        let off = if chunk.from_client { &mut client_off } else { &mut server_off };
+        let text = render(&chunk.data, format, *off, !chunk.from_client);
+        *off += chunk.data.len();
+
+        let start = buf.get_end_iter().get_offset();
+        buf.insert(&mut buf.get_end_iter(), &text);
+        buf.apply_tag_by_name(if chunk.from_client { "client" } else { "server" },
+                              &buf.get_iter_at_offset(start), &buf.get_end_iter());
+    }
+}
+
+fn save(path: &Path, buf: &gtk::TextBuffer, stream: &Stream, format: Format, dir: Direction) -> Result<(), String> {
+    let res = File::create(path).and_then(|mut f| {
+        if format == Format::Raw {
+            for chunk in stream.chunks.iter().filter(|c| dir.shows(c.from_client)) {
+                f.write_all(&chunk.data)?;
+            }
+            Ok(())
+        } else {
+            let text = buf.get_text(&buf.get_start_iter(), &buf.get_end_iter(), false).unwrap_or_default();
+            f.write_all(text.as_bytes())
+        }
+    });
+    res.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn choose_save_file(win: &gtk::Window) -> Option<::std::path::PathBuf> {
+    let dialog = gtk::FileChooserDialog::new(Some("Save Stream Content As"), Some(win),
+                                             gtk::FileChooserAction::Save);
+    dialog.add_buttons(&[("_Cancel", gtk::ResponseType::Cancel.into()),
+                         ("_Save", gtk::ResponseType::Accept.into())]);
+    dialog.set_do_overwrite_confirmation(true);
+
+    let path = if dialog.run() == gtk::ResponseType::Accept.into() {
+        dialog.get_filename()
+    } else {
+        None
+    };
+    dialog.destroy();
+    path
+}
+
+fn show_error(win: &gtk::Window, msg: &str) {
+    let dialog = gtk::MessageDialog::new(Some(win), gtk::DIALOG_MODAL,
+                                         gtk::MessageType::Error, gtk::ButtonsType::Close,
+                                         msg);
+    dialog.run();
+    dialog.destroy();
+}
+
+pub fn show(parent: &gtk::ApplicationWindow, stream: Stream, filter_entry: &gtk::Entry) {
+    let builder = gtk::Builder::new_from_file(Path::new("/usr/share/wire_shake/ui/follow.ui"));
+    let win: gtk::Window = builder.get_object("follow-window").unwrap();
+    let buf: gtk::TextBuffer = builder.get_object("follow-buffer").unwrap();
+    let dir_combo: gtk::ComboBoxText = builder.get_object("follow-direction").unwrap();
+    let fmt_combo: gtk::ComboBoxText = builder.get_object("follow-format").unwrap();
+    let summary: gtk::Label = builder.get_object("follow-summary").unwrap();
+
+    let filter = format!("{}.stream eq {}", stream.proto.name(), stream.index);
+    win.set_transient_for(Some(parent));
+    win.set_title(&format!("Follow {} Stream ({})", stream.proto.label(), filter));
+
+    dir_combo.append_text(&format!("Entire conversation ({} bytes)", stream.bytes(Direction::Both)));
+    dir_combo.append_text(&format!("{} \u{2192} {} ({} bytes)", stream.client, stream.server,
+                                   stream.bytes(Direction::ClientToServer)));
+    dir_combo.append_text(&format!("{} \u{2192} {} ({} bytes)", stream.server, stream.client,
+                                   stream.bytes(Direction::ServerToClient)));
+    dir_combo.set_active(0);
+    fmt_combo.set_active(0);
+
+    summary.set_text(&format!("{} client bytes (red), {} server bytes (blue), {} turn(s).",
+                              stream.bytes(Direction::ClientToServer),
+                              stream.bytes(Direction::ServerToClient),
+                              stream.chunks.len()));
+
+    let stream = Rc::new(stream);
+    let selection = {
+        let dir_combo = dir_combo.clone();
+        let fmt_combo = fmt_combo.clone();
+        move || {
+            let format = match fmt_combo.get_active() {
+                1 => Format::Hex,
+                2 => Format::Raw,
+                _ => Format::Ascii,
+            };
+            let dir = match dir_combo.get_active() {
+                1 => Direction::ClientToServer,
+                2 => Direction::ServerToClient,
+                _ => Direction::Both,
+            };
+            (format, dir)
+        }
+    };
+    let selection = Rc::new(selection);
+
+    let refresh = {
+        let buf = buf.clone();
+        let stream = stream.clone();
+        let selection = selection.clone();
+        Rc::new(move || {
+            let (format, dir) = selection();
+            fill_buffer(&buf, &stream, format, dir);
+        })
+    };
+    refresh();
+    {
+        let refresh = refresh.clone();
+        dir_combo.connect_changed(move |_| refresh());
+    }
+    fmt_combo.connect_changed(move |_| refresh());
+
+    let filter_button: gtk::Button = builder.get_object("follow-filter-button").unwrap();
+    {
+        let filter_entry = filter_entry.clone();
+        filter_button.connect_clicked(move |_| {
+            filter_entry.set_text(&filter);
+            filter_entry.activate();
+        });
+    }
+
+    let save_button: gtk::Button = builder.get_object("follow-save-button").unwrap();
+    {
+        let win = win.clone();
+        save_button.connect_clicked(move |_| {
+            if let Some(path) = choose_save_file(&win) {
+                let (format, dir) = selection();
+                if let Err(e) = save(&path, &buf, &stream, format, dir) {
+                    show_error(&win, &e);
+                }
+            }
+        });
+    }
+
+    let close_button: gtk::Button = builder.get_object("follow-close-button").unwrap();
+    {
+        let win = win.clone();
+        close_button.connect_clicked(move |_| win.destroy());
+    }
+
+    win.show_all();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wire_shake_core::proto_tree::FieldNode;
+
+    fn segment(src: &str, port: u64, seq: u64, payload: &[u8]) -> ProtoTree {
+        let mut tree = ProtoTree::new();
+        tree.add(FieldNode::generated("ip.src", "Source", FieldValue::Ipv4(src.parse().unwrap())));
+        tree.add(FieldNode::generated("tcp.srcport", "Source Port", FieldValue::UInt(port)));
+        tree.add(FieldNode::generated("tcp.stream", "Stream index", FieldValue::UInt(0)));
+        tree.add(FieldNode::generated("tcp.seq", "Sequence number", FieldValue::UInt(seq)));
+        tree.add(FieldNode::generated("tcp.payload", "TCP payload", FieldValue::Bytes(payload.to_vec())));
+        tree
+    }
+
+    #[test]
+    fn out_of_order_segments_are_put_back_in_sequence() {
+        let trees = vec![
+            segment("10.0.0.1", 1025, 1, b"GET "),
+            segment("10.0.0.1", 1025, 9, b"HTTP"),
+            segment("10.0.0.1", 1025, 5, b"/ ? "),
+            segment("10.0.0.2", 80, 1, b"200 OK"),
+            segment("10.0.0.1", 1025, 13, b"\r\n"),
+        ];
+        let stream = collect(StreamProto::Tcp, 0, trees.iter()).unwrap();
+        assert_eq!(stream.client, "10.0.0.1:1025");
+        assert_eq!(stream.server, "10.0.0.2:80");
+        let chunks: Vec<(bool, &[u8])> = stream.chunks.iter().map(|c| (c.from_client, &c.data[..])).collect();
+        assert_eq!(chunks, vec![
+            (true, &b"GET / ? HTTP"[..]),
+            (false, &b"200 OK"[..]),
+            (true, &b"\r\n"[..]),
+        ]);
+        assert_eq!(stream.bytes(Direction::ServerToClient), 6);
+    }
+}
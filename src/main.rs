@@ -1,40 +1,30 @@
 extern crate gtk;
 extern crate gio;
 extern crate pcap;
-extern crate libc;
-extern crate byteorder;
 extern crate time;
-extern crate sexp;
-extern crate guile_sys;
+extern crate wire_shake_core;
 
 use std::env::Args;
+use std::path::PathBuf;
+use std::process;
 use std::rc::Rc;
 use std::cell::RefCell;
 
 use gio::{
-    SimpleActionExt, ActionMapExt, ApplicationExt
+    SimpleActionExt, ActionMapExt, ApplicationExt, FileExt
 };
 
 use gtk::{
     WidgetExt, GtkApplicationExt
 };
 
-use std::ffi::CStr;
-use std::ffi::CString;
-use libc::c_void;
-
-use guile_sys::{
-    SCM, scm_c_define_gsubr, scm_from_pointer,
-    scm_c_define, scm_with_guile, scm_c_primitive_load
-};
-
 mod win;
-mod disctr;
+mod follow;
+mod capture;
+mod cli;
 
-use disctr::{
-    DissectorTable, set_dissector, set_info,
-    set_proto,
-};
+use wire_shake_core::{plugin, scheme};
+use wire_shake_core::DissectorTable;
 
 fn init_actions(app: &gtk::Application) {
     let quit_action = gio::SimpleAction::new("quit", None);
@@ -51,26 +41,34 @@ fn init_actions(app: &gtk::Application) {
 
 fn init_accels(app: &gtk::Application) {
     app.add_accelerator("<Ctrl>q", "app.quit", None);
+    app.add_accelerator("<Ctrl>o", "win.open", None);
+    app.add_accelerator("<Ctrl>s", "win.save", None);
+    app.add_accelerator("<Shift><Ctrl>s", "win.save-as", None);
+    app.add_accelerator("<Shift><Ctrl><Alt>t", "win.follow-tcp-stream", None);
+    app.add_accelerator("<Shift><Ctrl><Alt>u", "win.follow-udp-stream", None);
+    app.add_accelerator("<Shift><Ctrl>r", "win.reload-dissectors", None);
 }
 
-unsafe extern "C" fn init_guile(dissector_tbl: *mut c_void) -> *mut c_void {
-    let prc = set_dissector as *mut fn(SCM, SCM, SCM, SCM) -> SCM as *mut c_void;
-    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"set-dissector\0").unwrap().as_ptr(), 4, 0, 0, prc);
-
-    let prc = set_proto as *mut fn(SCM, SCM) -> SCM as *mut c_void;
-    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"set-proto\0").unwrap().as_ptr(), 2, 0, 0, prc);
-
-    let prc = set_info as *mut fn(SCM, SCM) -> SCM as *mut c_void;
-    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"set-info\0").unwrap().as_ptr(), 2, 0, 0, prc);
-
-    let dsctr_tbl = scm_from_pointer(dissector_tbl, None);
-    scm_c_define(CStr::from_bytes_with_nul(b"dissector-table\0").unwrap().as_ptr(), dsctr_tbl);
-
-    dissector_tbl
+// Pulls `--plugin-dir DIR` and `--plugin-dir=DIR` out of the arguments,
+// which GApplication would otherwise reject.
+fn plugin_dir_args(mut args: Args) -> (Vec<String>, Vec<PathBuf>) {
+    let mut rest = Vec::new();
+    let mut dirs = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--plugin-dir" {
+            if let Some(dir) = args.next() {
+                dirs.push(PathBuf::from(dir));
+            }
+        } else if arg.starts_with("--plugin-dir=") {
+            dirs.push(PathBuf::from(&arg["--plugin-dir=".len()..]));
+        } else {
+            rest.push(arg);
+        }
+    }
+    (rest, dirs)
 }
 
-
-fn run(args: Args) {
+fn run(args: Vec<String>, disct_tbl: Rc<RefCell<DissectorTable>>, plugin_dirs: Rc<Vec<PathBuf>>) {
     match gtk::Application::new("com.github.koji-m.wire_shake", gio::APPLICATION_HANDLES_OPEN) {
         Ok(app) => {
             {
@@ -80,22 +78,27 @@ fn run(args: Args) {
                 });
             }
 
-            let mut disct_tbl = DissectorTable::new();
-            unsafe {
-                scm_with_guile(Some(init_guile), &mut disct_tbl as *mut _ as *mut c_void);
-                scm_c_primitive_load(CString::new("src/dissector.scm").unwrap().as_ptr());
-            }
-
-            let disct_tbl = Rc::new(RefCell::new(disct_tbl));
             {
+                let disct_tbl = disct_tbl.clone();
+                let plugin_dirs = plugin_dirs.clone();
                 app.connect_activate(move |app| {
-                    let w = win::create(app, disct_tbl.clone());
+                    let w = win::create(app, disct_tbl.clone(), plugin_dirs.clone(), None);
                     w.show_all();
                 });
             }
 
+            {
+                app.connect_open(move |app, files, _| {
+                    for f in files {
+                        if let Some(path) = f.get_path() {
+                            let w = win::create(app, disct_tbl.clone(), plugin_dirs.clone(), Some(&path));
+                            w.show_all();
+                        }
+                    }
+                });
+            }
+
 
-            let args: Vec<String> = args.collect();
             let argv: Vec<&str> = args.iter().map(|s| s.as_ref()).collect();
 
             app.run(argv.as_slice());
@@ -108,6 +111,21 @@ fn run(args: Args) {
 }
 
 fn main() {
-    run(std::env::args());
+    let (mut args, extra_dirs) = plugin_dir_args(std::env::args());
+
+    let disct_tbl = Rc::new(RefCell::new(DissectorTable::new()));
+    scheme::init(&disct_tbl);
+    let plugin_dirs = Rc::new(plugin::plugin_dirs(&extra_dirs));
+    for e in plugin::load_plugins(&plugin_dirs) {
+        eprintln!("failed to load dissector plugin {}", e);
+    }
+
+    // Headless mode never touches GTK, so it works without a display.
+    if let Some(i) = args.iter().position(|a| a == "--cli") {
+        args.remove(i);
+        process::exit(cli::run(&args[1..], disct_tbl));
+    }
+
+    run(args, disct_tbl, plugin_dirs);
 }
 
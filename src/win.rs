@@ -2,20 +2,17 @@ extern crate gtk;
 extern crate gio;
 extern crate pcap;
 
-use std::path::Path;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::sync::mpsc;
 
 use std::rc::Rc;
-use std::cell::RefCell;
-
-use std::ffi::CString;
-use guile_sys::{
-    scm_variable_ref, scm_c_lookup,
-};
+use std::cell::{Cell, RefCell};
 
 use gio::{
-    SimpleActionExt, ActionMapExt
+    SimpleActionExt, ActionMapExt, ActionExt
 };
 
 use gtk::{
@@ -23,17 +20,27 @@ use gtk::{
     ListStoreExt, TreeModelExt,
     ListStoreExtManual, TreeViewExt,
     TreeStoreExt, TreeStoreExtManual,
-    TreeSelectionExt, Cast
+    TreeSelectionExt, DialogExt,
+    FileChooserExt, FileFilterExt, WidgetExt,
+    ComboBoxExt, EntryExt, EditableSignals,
+    StyleContextExt, TreeModelFilterExt,
+    TextBufferExt, TextIterExt,
+    ContainerExt, ToggleButtonExt, BoxExt, ExpanderExt
 };
 
 
 use time;
-use sexp;
-use sexp::{Sexp, Atom};
 
-use disctr::{
-    PacketInfo, dissect, DissectorTable
+use wire_shake_core::disctr::{
+    PacketInfo, dissect, DissectorTable, mark_reassembled_in
 };
+use wire_shake_core::proto_tree::{ProtoTree, FieldNode};
+use wire_shake_core::conversation::ConversationTable;
+use wire_shake_core::pcapfile::{self, FrameHeader};
+use wire_shake_core::{plugin, dfilter};
+use wire_shake_core::export::{self, Exporter, Summary};
+use follow;
+use capture::{self, frame_header, open_device, apply_capture_filter};
 
 const NUMBER_COLUMN: u32 = 0;
 const TIME_COLUMN: u32 = 1;
@@ -42,183 +49,896 @@ const DST_COLUMN: u32 = 3;
 const PROTO_COLUMN: u32 = 4;
 const LEN_COLUMN: u32 = 5;
 const INFO_COLUMN: u32 = 6;
-const DATA_COLUMN: u32 = 7;
 
 const DETAIL_COLUMN: u32 = 0;
+const OFFSET_COLUMN: u32 = 1;
+const LENGTH_COLUMN: u32 = 2;
+
+const HEX_BYTES_PER_LINE: usize = 16;
+const HEX_ADDR_WIDTH: usize = 6;
+const HEX_ASCII_START: usize = HEX_ADDR_WIDTH + HEX_BYTES_PER_LINE * 3 + 1;
+const HEX_LINE_WIDTH: usize = HEX_ASCII_START + HEX_BYTES_PER_LINE + 1;
+
+struct Frame {
+    hdr: FrameHeader,
+    data: Vec<u8>,
+    tree: ProtoTree,
+}
+
+type Frames = Rc<RefCell<Vec<Frame>>>;
+
+// Bumped whenever a file is opened or a capture started. Packets the
+// reader thread is still sending from an earlier source carry an older
+// value and are dropped.
+type LoadGen = Rc<Cell<u32>>;
+
+fn next_load(gen: &LoadGen) -> u32 {
+    gen.set(gen.get().wrapping_add(1));
+    gen.get()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PacketRange {
+    All,
+    Displayed,
+    Selected,
+}
 
 enum Ctrl {
-    StartCapture(pcap::Capture<pcap::Active>),
+    StartCapture(pcap::Capture<pcap::Active>, u32),
     StopCapture,
+    OpenFile(pcapfile::Reader<BufReader<File>>, u32),
     CaptureStarted,
     CaptureStopped,
 }
 
-fn string_to_dev(s: String) -> Option<pcap::Capture<pcap::Inactive>> {
-    if let Ok(mut l) = pcap::Device::list() {
-        if let Some(i) = l.iter().position(|ref d| d.name == s) {
-            if let Ok(cap) = pcap::Capture::from_device(l.remove(i)) {
-                return Some(cap.promisc(true).timeout(300));
-            }
+fn set_entry_error(entry: &gtk::Entry, err: Option<&str>) {
+    if let Some(ctx) = entry.get_style_context() {
+        if err.is_some() {
+            ctx.add_class("error");
+        } else {
+            ctx.remove_class("error");
         }
     }
-    return None;
+    entry.set_tooltip_text(err);
 }
 
-fn output_packet(tree: String, pinfo: PacketInfo, store: gtk::TreeModel) {
-    let store = store.downcast::<gtk::ListStore>().ok().unwrap();
-    let itr = store.append();
+fn show_error(win: &gtk::ApplicationWindow, msg: &str) {
+    let dialog = gtk::MessageDialog::new(Some(win), gtk::DIALOG_MODAL,
+                                         gtk::MessageType::Error, gtk::ButtonsType::Close,
+                                         msg);
+    dialog.run();
+    dialog.destroy();
+}
+
+fn choose_open_file(win: &gtk::ApplicationWindow) -> Option<PathBuf> {
+    let dialog = gtk::FileChooserDialog::new(Some("Open Capture File"), Some(win),
+                                             gtk::FileChooserAction::Open);
+    dialog.add_buttons(&[("_Cancel", gtk::ResponseType::Cancel.into()),
+                         ("_Open", gtk::ResponseType::Accept.into())]);
+
+    let filter = gtk::FileFilter::new();
+    filter.set_name("Capture files (*.pcap, *.pcapng)");
+    filter.add_pattern("*.pcap");
+    filter.add_pattern("*.pcapng");
+    filter.add_pattern("*.cap");
+    dialog.add_filter(&filter);
+
+    let all = gtk::FileFilter::new();
+    all.set_name("All files");
+    all.add_pattern("*");
+    dialog.add_filter(&all);
+
+    let path = if dialog.run() == gtk::ResponseType::Accept.into() {
+        dialog.get_filename()
+    } else {
+        None
+    };
+    dialog.destroy();
+    path
+}
 
+fn choose_save_file(win: &gtk::ApplicationWindow, title: &str, name: &str, with_range: bool) -> Option<(PathBuf, PacketRange)> {
+    let dialog = gtk::FileChooserDialog::new(Some(title), Some(win),
+                                             gtk::FileChooserAction::Save);
+    dialog.add_buttons(&[("_Cancel", gtk::ResponseType::Cancel.into()),
+                         ("_Save", gtk::ResponseType::Accept.into())]);
+    dialog.set_do_overwrite_confirmation(true);
+    dialog.set_current_name(name);
+
+    let range_combo = gtk::ComboBoxText::new();
+    if with_range {
+        range_combo.append(None, "All packets");
+        range_combo.append(None, "Displayed packets");
+        range_combo.append(None, "Selected packet");
+        range_combo.set_active(1);
+        dialog.set_extra_widget(&range_combo);
+    }
+
+    let res = if dialog.run() == gtk::ResponseType::Accept.into() {
+        let range = match range_combo.get_active() {
+            1 => PacketRange::Displayed,
+            2 => PacketRange::Selected,
+            _ => PacketRange::All,
+        };
+        dialog.get_filename().map(|path| (path, range))
+    } else {
+        None
+    };
+    dialog.destroy();
+    res
+}
+
+fn open_file(path: &Path, main_tx: &mpsc::Sender<Ctrl>, store: &gtk::ListStore, frames: &Frames,
+             link: &Cell<pcapfile::Link>, load_gen: &LoadGen) -> Result<(), String> {
+    let rdr = capture::open_file(path)?;
+    link.set(rdr.link().unwrap_or(pcapfile::ETHERNET));
+
+    store.clear();
+    frames.borrow_mut().clear();
+    main_tx.send(Ctrl::OpenFile(rdr, next_load(load_gen))).unwrap();
+    Ok(())
+}
+
+fn packet_numbers(range: PacketRange, list_v: &gtk::TreeView) -> Vec<u32> {
+    let mut nums = Vec::new();
+    match range {
+        PacketRange::Selected => {
+            if let Some((model, itr)) = list_v.get_selection().get_selected() {
+                if let Some(n) = model.get_value(&itr, NUMBER_COLUMN as i32).get::<u32>() {
+                    nums.push(n);
+                }
+            }
+        },
+        _ => {
+            let model = list_v.get_model().unwrap();
+            if let Some(itr) = model.get_iter_first() {
+                loop {
+                    if let Some(n) = model.get_value(&itr, NUMBER_COLUMN as i32).get::<u32>() {
+                        nums.push(n);
+                    }
+                    if !model.iter_next(&itr) { break; }
+                }
+            }
+        },
+    }
+    nums
+}
+
+// Frames are saved with the link-layer type and timestamp resolution of
+// the file or device they came from.
+fn save_packets(path: &Path, range: PacketRange, frames: &Frames, link: &pcapfile::Link,
+                list_v: &gtk::TreeView) -> Result<(), String> {
+    let frames = frames.borrow();
+    let format = pcapfile::Format::from_path(path);
+    let res = match range {
+        PacketRange::All => pcapfile::write(path, format, link,
+                                            frames.iter().map(|f| (&f.hdr, &f.data[..]))),
+        _ => {
+            let nums = packet_numbers(range, list_v);
+            pcapfile::write(path, format, link,
+                            nums.iter()
+                                .filter_map(|&n| frames.get(n as usize - 1))
+                                .map(|f| (&f.hdr, &f.data[..])))
+        },
+    };
+    res.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Exports the chosen packets with the columns as currently shown in the
+// packet list.
+fn export_packets(path: &Path, format: export::Format, range: PacketRange, frames: &Frames,
+                  list_v: &gtk::TreeView, store: &gtk::ListStore) -> Result<(), String> {
+    let frames = frames.borrow();
+    let nums = match range {
+        PacketRange::All => (1..frames.len() as u32 + 1).collect(),
+        _ => packet_numbers(range, list_v),
+    };
+
+    let column = |itr: &gtk::TreeIter, col: u32| {
+        store.get_value(itr, col as i32).get::<String>().unwrap_or_default()
+    };
+
+    let res = File::create(path).and_then(|f| {
+        let mut e = Exporter::new(BufWriter::new(f), format)?;
+        for n in nums {
+            let frame = match frames.get(n as usize - 1) {
+                Some(frame) => frame,
+                None => continue,
+            };
+            let summary = match store.iter_nth_child(None, n as i32 - 1) {
+                Some(itr) => Summary {
+                    num: n,
+                    time: column(&itr, TIME_COLUMN),
+                    source: column(&itr, SRC_COLUMN),
+                    destination: column(&itr, DST_COLUMN),
+                    protocol: column(&itr, PROTO_COLUMN),
+                    length: frame.hdr.len,
+                    info: column(&itr, INFO_COLUMN),
+                },
+                None => continue,
+            };
+            e.packet(&summary, &frame.tree, &frame.data)?;
+        }
+        e.finish().map(|_| ())
+    });
+    res.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn output_packet(pinfo: PacketInfo, store: &gtk::ListStore) {
     let time = time::strftime("%F %T", &time::at(pinfo.time)).unwrap();
 
-    store.set(&itr,
-              &[NUMBER_COLUMN, TIME_COLUMN, SRC_COLUMN, DST_COLUMN, PROTO_COLUMN,
-                LEN_COLUMN, INFO_COLUMN, DATA_COLUMN],
-              &[&pinfo.num, &time, &pinfo.net_src, &pinfo.net_dst, &pinfo.proto,
-                &pinfo.len, &pinfo.info, &tree]);
+    store.insert_with_values(None,
+                             &[NUMBER_COLUMN, TIME_COLUMN, SRC_COLUMN, DST_COLUMN, PROTO_COLUMN,
+                               LEN_COLUMN, INFO_COLUMN],
+                             &[&pinfo.num, &time, &pinfo.net_src, &pinfo.net_dst, &pinfo.proto,
+                               &pinfo.len, &pinfo.info]);
+}
+
+// The collapsible panel under the packet panes where dissector errors
+// are reported. It opens itself on the first warning.
+#[derive(Clone)]
+struct DissectorLog {
+    buf: gtk::TextBuffer,
+    expander: gtk::Expander,
+}
+
+impl DissectorLog {
+    fn new(builder: &gtk::Builder) -> DissectorLog {
+        DissectorLog {
+            buf: builder.get_object("dissector-log-buffer").unwrap(),
+            expander: builder.get_object("dissector-log-expander").unwrap(),
+        }
+    }
+
+    fn warn(&self, msg: &str) {
+        self.buf.insert(&mut self.buf.get_end_iter(), &format!("{}\n", msg));
+        self.expander.set_expanded(true);
+    }
+
+    fn packet_warnings(&self, pinfo: &PacketInfo) {
+        for w in &pinfo.warnings {
+            self.warn(&format!("Frame {}: {}", pinfo.num, w));
+        }
+    }
 }
 
-fn init_action(win: &gtk::ApplicationWindow, builder: &gtk::Builder, disct_tbl: Rc<RefCell<DissectorTable>>) {
-    use self::Ctrl::{StartCapture, StopCapture, CaptureStarted, CaptureStopped};
+fn set_packet_row(pinfo: PacketInfo, store: &gtk::ListStore, itr: &gtk::TreeIter) {
+    store.set(itr, &[SRC_COLUMN, DST_COLUMN, PROTO_COLUMN, INFO_COLUMN],
+              &[&pinfo.net_src, &pinfo.net_dst, &pinfo.proto, &pinfo.info]);
+}
+
+// Labels fragments whose datagram was completed by the frame just
+// dissected. Their rows are already shown, so unlike the CLI's
+// FrameQueue nothing is held back.
+fn mark_fragments(convs: &Rc<RefCell<ConversationTable>>, frames: &Frames, store: &gtk::ListStore) {
+    for (frag, reasm) in convs.borrow_mut().take_reassembled() {
+        let mut info = None;
+        if let Some(frame) = frames.borrow_mut().get_mut(frag as usize - 1) {
+            mark_reassembled_in(&mut frame.tree, &mut info, reasm);
+        }
+        if let (Some(itr), Some(info)) = (store.iter_nth_child(None, frag as i32 - 1), info) {
+            store.set(&itr, &[INFO_COLUMN], &[&info]);
+        }
+    }
+}
+
+// Runs every retained frame through the dissectors again, in order, and
+// updates the packet list rows in place.
+// The new trees may match the display filter differently, so the list is
+// filtered again afterwards.
+fn redissect(frames: &Frames, store: &gtk::ListStore, filter_model: &gtk::TreeModelFilter,
+             disct_tbl: &Rc<RefCell<DissectorTable>>, convs: &Rc<RefCell<ConversationTable>>,
+             log: &DissectorLog) {
+    convs.borrow_mut().clear();
+    let count = frames.borrow().len();
+    for i in 0..count {
+        let (hdr, data) = {
+            let frames = frames.borrow();
+            (frames[i].hdr.clone(), frames[i].data.clone())
+        };
+        let (tree, pinfo) = dissect(i as u32 + 1, hdr, data, disct_tbl.clone(), convs.clone());
+        frames.borrow_mut()[i].tree = tree;
+        log.packet_warnings(&pinfo);
+        if let Some(itr) = store.iter_nth_child(None, i as i32) {
+            set_packet_row(pinfo, store, &itr);
+        }
+        mark_fragments(convs, frames, store);
+    }
+    filter_model.refilter();
+}
+
+fn init_display_filter(builder: &gtk::Builder, lst_store: &gtk::ListStore, frames: Frames) -> gtk::TreeModelFilter {
+    let lst_v: gtk::TreeView = builder.get_object("list_view").unwrap();
+    let entry: gtk::Entry = builder.get_object("display-filter-entry").unwrap();
+    let cur_filter: Rc<RefCell<Option<dfilter::Filter>>> = Rc::new(RefCell::new(None));
+
+    let filter_model = gtk::TreeModelFilter::new(lst_store, None);
+    {
+        let cur_filter = cur_filter.clone();
+        filter_model.set_visible_func(move |model, itr| {
+            let n = model.get_value(itr, NUMBER_COLUMN as i32).get::<u32>().unwrap_or(0);
+            match (n, &*cur_filter.borrow()) {
+                (0, _) | (_, &None) => true,
+                (n, &Some(ref f)) => {
+                    match frames.borrow().get(n as usize - 1) {
+                        Some(frame) => f.matches(&frame.tree),
+                        None => true,
+                    }
+                },
+            }
+        });
+    }
+    lst_v.set_model(Some(&filter_model));
+    let model = filter_model.clone();
+
+    entry.connect_changed(move |entry| {
+        let text = entry.get_text().unwrap_or_default();
+        match dfilter::Filter::parse(text.trim()) {
+            Err(ref e) if !text.trim().is_empty() => set_entry_error(entry, Some(&e.to_string())),
+            _ => set_entry_error(entry, None),
+        }
+    });
+
+    entry.connect_activate(move |entry| {
+        let text = entry.get_text().unwrap_or_default();
+        if text.trim().is_empty() {
+            *cur_filter.borrow_mut() = None;
+        } else {
+            match dfilter::Filter::parse(text.trim()) {
+                Ok(f) => *cur_filter.borrow_mut() = Some(f),
+                Err(e) => {
+                    let start = (text.len() - text.trim_left().len() + e.pos) as i32;
+                    set_entry_error(entry, Some(&e.to_string()));
+                    entry.select_region(start, start + e.len.max(1) as i32);
+                    return;
+                },
+            }
+        }
+        filter_model.refilter();
+    });
+    model
+}
+
+fn follow_stream(win: &gtk::ApplicationWindow, proto: follow::StreamProto, frames: &Frames,
+                 list_v: &gtk::TreeView, filter_entry: &gtk::Entry) {
+    let n = match list_v.get_selection().get_selected() {
+        Some((model, itr)) => model.get_value(&itr, NUMBER_COLUMN as i32).get::<u32>().unwrap_or(0),
+        None => return,
+    };
+    let frames = frames.borrow();
+    let stream = frames.get((n as usize).wrapping_sub(1))
+        .and_then(|f| follow::stream_index(&f.tree, proto))
+        .and_then(|idx| follow::collect(proto, idx, frames.iter().map(|f| &f.tree)));
+    match stream {
+        Some(stream) => follow::show(win, stream, filter_entry),
+        None => show_error(win, "The selected packet does not belong to a stream of that protocol."),
+    }
+}
+
+// Toggles take effect for packets dissected from now on.
+fn show_heuristics(win: &gtk::ApplicationWindow, disct_tbl: &Rc<RefCell<DissectorTable>>) {
+    let dialog = gtk::Dialog::new();
+    dialog.set_title("Enabled Heuristic Dissectors");
+    dialog.set_transient_for(Some(win));
+    dialog.set_modal(true);
+    dialog.add_button("_Close", gtk::ResponseType::Close.into());
+
+    let area = dialog.get_content_area();
+    area.set_spacing(6);
+    area.set_border_width(6);
+    let heuristics: Vec<(String, String, i32, bool)> = disct_tbl.borrow().heuristics().iter()
+        .map(|h| (h.table.clone(), h.name.clone(), h.priority, h.enabled))
+        .collect();
+    if heuristics.is_empty() {
+        area.add(&gtk::Label::new(Some("No heuristic dissectors are registered.")));
+    }
+    for (table, name, priority, enabled) in heuristics {
+        let check = gtk::CheckButton::new_with_label(&format!("{} ({}, priority {})", name, table, priority));
+        check.set_active(enabled);
+        let disct_tbl = disct_tbl.clone();
+        check.connect_toggled(move |check| {
+            disct_tbl.borrow_mut().set_heuristic_enabled(&table, &name, check.get_active());
+        });
+        area.add(&check);
+    }
+
+    dialog.show_all();
+    dialog.run();
+    dialog.destroy();
+}
+
+fn init_action(win: &gtk::ApplicationWindow, builder: &gtk::Builder, disct_tbl: Rc<RefCell<DissectorTable>>, plugin_dirs: Rc<Vec<PathBuf>>, frames: Frames, path: Option<&Path>) {
+    use self::Ctrl::{StartCapture, StopCapture, OpenFile, CaptureStarted, CaptureStopped};
 
     let start_capture_action = gio::SimpleAction::new("start-capture", None);
     let stop_capture_action = gio::SimpleAction::new("stop-capture", None);
+    let open_action = gio::SimpleAction::new("open", None);
+    let save_action = gio::SimpleAction::new("save", None);
+    let save_as_action = gio::SimpleAction::new("save-as", None);
+    let export_action = gio::SimpleAction::new("export-packets", None);
+    let export_csv_action = gio::SimpleAction::new("export-csv", None);
+    let export_json_action = gio::SimpleAction::new("export-json", None);
+    let export_pdml_action = gio::SimpleAction::new("export-pdml", None);
+    let follow_tcp_action = gio::SimpleAction::new("follow-tcp-stream", None);
+    let follow_udp_action = gio::SimpleAction::new("follow-udp-stream", None);
+    let heuristics_action = gio::SimpleAction::new("enabled-heuristics", None);
+    let reload_action = gio::SimpleAction::new("reload-dissectors", None);
     stop_capture_action.set_enabled(false);
 
+    let cur_path: Rc<RefCell<Option<PathBuf>>> = Rc::new(RefCell::new(None));
+    let link = Rc::new(Cell::new(pcapfile::ETHERNET));
+    let load_gen: LoadGen = Rc::new(Cell::new(0));
+    let lst_v: gtk::TreeView = builder.get_object("list_view").unwrap();
+
     let (main_tx, cap_rx) = mpsc::channel();
     let (start_cap_tx, start_main_rx) = mpsc::channel();
     let (stop_cap_tx, stop_main_rx) = mpsc::channel();
 
+    let lst_store: gtk::ListStore = builder.get_object("list-store").unwrap();
+    let cap_filter_entry: gtk::Entry = builder.get_object("capture-filter-entry").unwrap();
+
     {
-        let lst_store: gtk::ListStore = builder.get_object("list-store").unwrap();
+        let lst_store = lst_store.clone();
         let stop_capture_action = stop_capture_action.clone();
+        let open_action = open_action.clone();
         let if_combo: gtk::ComboBoxText = builder.get_object("if-combobox").unwrap();
+        let filter_entry = cap_filter_entry.clone();
         let main_tx = mpsc::Sender::clone(&main_tx);
+        let frames = frames.clone();
+        let cur_path = cur_path.clone();
+        let link = link.clone();
+        let load_gen = load_gen.clone();
+        let win = win.clone();
         start_capture_action.connect_activate(move |act, _| {
-            lst_store.clear();
             if let Some(if_name) = if_combo.get_active_text() {
-                if let Ok(cap) = string_to_dev(if_name).unwrap().open() {
-                    main_tx.send(StartCapture(cap)).unwrap();
+                let mut cap = match open_device(&if_name) {
+                    Ok(cap) => cap,
+                    Err(e) => {
+                        show_error(&win, &e);
+                        return;
+                    },
+                };
+
+                let filter = filter_entry.get_text().unwrap_or_default();
+                if let Err(e) = apply_capture_filter(&mut cap, filter.trim()) {
+                    set_entry_error(&filter_entry, Some(&e));
+                    show_error(&win, &e);
+                    return;
+                }
 
-                    if let Ok(CaptureStarted) = start_main_rx.recv() {
-                        act.set_enabled(false);
-                        stop_capture_action.set_enabled(true);
-                    }
+                lst_store.clear();
+                frames.borrow_mut().clear();
+                *cur_path.borrow_mut() = None;
+                link.set(capture::device_link(&cap));
+                main_tx.send(StartCapture(cap, next_load(&load_gen))).unwrap();
+
+                if let Ok(CaptureStarted) = start_main_rx.recv() {
+                    act.set_enabled(false);
+                    open_action.set_enabled(false);
+                    filter_entry.set_sensitive(false);
+                    stop_capture_action.set_enabled(true);
                 }
             }
         });
     }
 
+    {
+        cap_filter_entry.connect_changed(move |entry| {
+            set_entry_error(entry, None);
+        });
+        let start_capture_action = start_capture_action.clone();
+        cap_filter_entry.connect_activate(move |_| {
+            if start_capture_action.get_enabled() {
+                start_capture_action.activate(None);
+            }
+        });
+    }
+
     {
         let start_capture_action = start_capture_action.clone();
+        let open_action = open_action.clone();
+        let filter_entry = cap_filter_entry.clone();
+        let main_tx = mpsc::Sender::clone(&main_tx);
         stop_capture_action.connect_activate(move |act, _| {
             main_tx.send(StopCapture).unwrap();
             if let Ok(CaptureStopped) = stop_main_rx.recv() {
                 act.set_enabled(false);
                 start_capture_action.set_enabled(true);
+                open_action.set_enabled(true);
+                filter_entry.set_sensitive(true);
+            }
+        });
+    }
+
+    {
+        let win = win.clone();
+        let lst_store = lst_store.clone();
+        let main_tx = mpsc::Sender::clone(&main_tx);
+        let frames = frames.clone();
+        let cur_path = cur_path.clone();
+        let link = link.clone();
+        let load_gen = load_gen.clone();
+        open_action.connect_activate(move |_, _| {
+            if let Some(path) = choose_open_file(&win) {
+                match open_file(&path, &main_tx, &lst_store, &frames, &link, &load_gen) {
+                    Ok(_) => *cur_path.borrow_mut() = Some(path),
+                    Err(e) => show_error(&win, &e),
+                }
             }
         });
     }
 
+    {
+        let win = win.clone();
+        let frames = frames.clone();
+        let cur_path = cur_path.clone();
+        let link = link.clone();
+        let lst_v = lst_v.clone();
+        save_as_action.connect_activate(move |_, _| {
+            if let Some((path, _)) = choose_save_file(&win, "Save Capture File As", "capture.pcapng", false) {
+                match save_packets(&path, PacketRange::All, &frames, &link.get(), &lst_v) {
+                    Ok(_) => *cur_path.borrow_mut() = Some(path),
+                    Err(e) => show_error(&win, &e),
+                }
+            }
+        });
+    }
+
+    {
+        let win = win.clone();
+        let frames = frames.clone();
+        let cur_path = cur_path.clone();
+        let link = link.clone();
+        let lst_v = lst_v.clone();
+        let save_as_action = save_as_action.clone();
+        save_action.connect_activate(move |_, _| {
+            let path = cur_path.borrow().clone();
+            match path {
+                Some(path) => {
+                    if let Err(e) = save_packets(&path, PacketRange::All, &frames, &link.get(), &lst_v) {
+                        show_error(&win, &e);
+                    }
+                },
+                None => save_as_action.activate(None),
+            }
+        });
+    }
+
+    {
+        let win = win.clone();
+        let frames = frames.clone();
+        let link = link.clone();
+        let lst_v = lst_v.clone();
+        export_action.connect_activate(move |_, _| {
+            if let Some((path, range)) = choose_save_file(&win, "Export Specified Packets", "capture.pcapng", true) {
+                if let Err(e) = save_packets(&path, range, &frames, &link.get(), &lst_v) {
+                    show_error(&win, &e);
+                }
+            }
+        });
+    }
+
+    for &(action, format, title, name) in &[
+        (&export_csv_action, export::Format::Csv, "Export Packet List as CSV", "packets.csv"),
+        (&export_json_action, export::Format::Json, "Export Packet Dissections as JSON", "packets.json"),
+        (&export_pdml_action, export::Format::Pdml, "Export Packet Dissections as PDML", "packets.pdml"),
+    ] {
+        let win = win.clone();
+        let frames = frames.clone();
+        let lst_v = lst_v.clone();
+        let lst_store = lst_store.clone();
+        action.connect_activate(move |_, _| {
+            if let Some((path, range)) = choose_save_file(&win, title, name, true) {
+                if let Err(e) = export_packets(&path, format, range, &frames, &lst_v, &lst_store) {
+                    show_error(&win, &e);
+                }
+            }
+        });
+    }
+
+    let dsp_filter_entry: gtk::Entry = builder.get_object("display-filter-entry").unwrap();
+
+    {
+        let win = win.clone();
+        let frames = frames.clone();
+        let lst_v = lst_v.clone();
+        let filter_entry = dsp_filter_entry.clone();
+        follow_tcp_action.connect_activate(move |_, _| {
+            follow_stream(&win, follow::StreamProto::Tcp, &frames, &lst_v, &filter_entry);
+        });
+    }
+
+    {
+        let win = win.clone();
+        let frames = frames.clone();
+        let lst_v = lst_v.clone();
+        let filter_entry = dsp_filter_entry.clone();
+        follow_udp_action.connect_activate(move |_, _| {
+            follow_stream(&win, follow::StreamProto::Udp, &frames, &lst_v, &filter_entry);
+        });
+    }
+
+    {
+        let win = win.clone();
+        let disct_tbl = disct_tbl.clone();
+        heuristics_action.connect_activate(move |_, _| {
+            show_heuristics(&win, &disct_tbl);
+        });
+    }
+
     let (pkt_tx, pkt_rx) = mpsc::channel();
 
     thread::spawn(move || {
         let mut n: u32;
         while let Ok(msg) = cap_rx.recv() {
-            if let StartCapture(mut cap) = msg {
-                n= 1;
-                start_cap_tx.send(CaptureStarted).unwrap();
-                loop {
-                    if let Ok(pkt) = cap.next() {
-                        pkt_tx.send((n, pkt.header.clone(), pkt.data.to_vec())).unwrap();
-                        n+=1;
-                        if let Ok(StopCapture) = cap_rx.try_recv() {
-                            break;
+            match msg {
+                StartCapture(mut cap, gen) => {
+                    n= 1;
+                    start_cap_tx.send(CaptureStarted).unwrap();
+                    loop {
+                        if let Ok(pkt) = cap.next() {
+                            pkt_tx.send((gen, n, Ok((frame_header(pkt.header), pkt.data.to_vec())))).unwrap();
+                            n+=1;
+                            if let Ok(StopCapture) = cap_rx.try_recv() {
+                                break;
+                            }
+                        } else {
+                            if let Ok(StopCapture) = cap_rx.try_recv() {
+                                break;
+                            }
                         }
-                    } else {
-                        if let Ok(StopCapture) = cap_rx.try_recv() {
+                    }
+                    stop_cap_tx.send(CaptureStopped).unwrap();
+                },
+                OpenFile(rdr, gen) => {
+                    n = 1;
+                    // A damaged file is shown up to the first bad record,
+                    // whose error is passed on.
+                    for rec in rdr {
+                        let bad = rec.is_err();
+                        pkt_tx.send((gen, n, rec.map_err(|e| e.to_string()))).unwrap();
+                        if bad {
                             break;
                         }
+                        n+=1;
                     }
-                }
-                stop_cap_tx.send(CaptureStopped).unwrap();
+                },
+                _ => {},
             }
         }
     });
 
-    let lst_v: gtk::TreeView = builder.get_object("list_view").unwrap();
-    let store = lst_v.get_model().unwrap();
+    let filter_model = init_display_filter(builder, &lst_store, frames.clone());
+
+    let convs = Rc::new(RefCell::new(ConversationTable::new()));
+    let log = DissectorLog::new(builder);
 
-    let write_proc;
-    unsafe {
-        write_proc = scm_variable_ref(scm_c_lookup(CString::new("write").unwrap().as_ptr()));
+    {
+        let disct_tbl = disct_tbl.clone();
+        let frames = frames.clone();
+        let lst_store = lst_store.clone();
+        let lst_v = lst_v.clone();
+        let convs = convs.clone();
+        let log = log.clone();
+        let filter_model = filter_model.clone();
+        reload_action.connect_activate(move |_, _| {
+            disct_tbl.borrow_mut().clear();
+            for e in plugin::load_plugins(&plugin_dirs) {
+                log.warn(&format!("Failed to load dissector plugin {}", e));
+            }
+            redissect(&frames, &lst_store, &filter_model, &disct_tbl, &convs, &log);
+
+            // Reselect so the detail and hex panes show the new tree.
+            let select = lst_v.get_selection();
+            if let Some((_, itr)) = select.get_selected() {
+                select.unselect_all();
+                select.select_iter(&itr);
+            }
+        });
     }
 
-    gtk::timeout_add(300, move || {
-        while let Ok((n, hdr, data)) = pkt_rx.try_recv() {
-            let (tree, pinfo) = dissect(n, hdr, data, disct_tbl.clone(), write_proc);
-            output_packet(tree, pinfo, store.clone());
-        }
-        gtk::Continue(true)
-    });
+    {
+        let win = win.clone();
+        let frames = frames.clone();
+        let lst_store = lst_store.clone();
+        let load_gen = load_gen.clone();
+        gtk::timeout_add(300, move || {
+            while let Ok((gen, n, rec)) = pkt_rx.try_recv() {
+                if gen != load_gen.get() {
+                    continue;
+                }
+                let (hdr, data) = match rec {
+                    Ok(rec) => rec,
+                    Err(e) => {
+                        show_error(&win, &format!("Record {} could not be read: {}\n\
+                                                   Only the {} packets before it are shown.", n, e, n - 1));
+                        continue;
+                    },
+                };
+                // Every capture or file restarts numbering at 1.
+                if n == 1 {
+                    convs.borrow_mut().clear();
+                }
+                let raw = data.clone();
+                let (tree, pinfo) = dissect(n, hdr, data, disct_tbl.clone(), convs.clone());
+                frames.borrow_mut().push(Frame { hdr: hdr, data: raw, tree: tree });
+                log.packet_warnings(&pinfo);
+                output_packet(pinfo, &lst_store);
+                mark_fragments(&convs, &frames, &lst_store);
+            }
+            gtk::Continue(true)
+        });
+    }
 
     win.add_action(&start_capture_action);
     win.add_action(&stop_capture_action);
+    win.add_action(&open_action);
+    win.add_action(&save_action);
+    win.add_action(&save_as_action);
+    win.add_action(&export_action);
+    win.add_action(&export_csv_action);
+    win.add_action(&export_json_action);
+    win.add_action(&export_pdml_action);
+    win.add_action(&follow_tcp_action);
+    win.add_action(&follow_udp_action);
+    win.add_action(&heuristics_action);
+    win.add_action(&reload_action);
+
+    if let Some(path) = path {
+        match open_file(path, &main_tx, &lst_store, &frames, &link, &load_gen) {
+            Ok(_) => *cur_path.borrow_mut() = Some(path.to_path_buf()),
+            Err(e) => show_error(win, &e),
+        }
+    }
 }
 
-fn parse_lbl_val(sxp: &Sexp) -> String {
-    if let &Sexp::List(ref kv_lst) = sxp {
-        if let &Sexp::Atom(ref k) = &kv_lst[0] {
-            if let &Atom::S(ref lbl_str) = k {
-                if let Sexp::Atom(ref v) = kv_lst[1] {
-                    if let &Atom::S(ref val_str) = v {
-                        return format!("{}: {}", lbl_str, val_str);
-                    }
-                }
-            }
+fn set_detail_tree(nodes: &[FieldNode], store: &gtk::TreeStore, parent_itr: Option<&gtk::TreeIter>) {
+    for node in nodes.iter().filter(|n| !n.hidden) {
+        let itr = store.append(parent_itr);
+        let (off, len) = match node.offset {
+            Some(off) => (off as i32, node.len as i32),
+            None => (-1, 0),
+        };
+        store.set(&itr, &[DETAIL_COLUMN, OFFSET_COLUMN, LENGTH_COLUMN],
+                  &[&node.display_text(), &off, &len]);
+
+        set_detail_tree(&node.children, store, Some(&itr));
+    }
+}
+
+fn set_detail_pane(store: gtk::TreeStore, tree: &ProtoTree) {
+    store.clear();
+    set_detail_tree(&tree.nodes, &store, None);
+}
+
+fn hex_dump(data: &[u8]) -> String {
+    let mut dump = String::with_capacity((data.len() / HEX_BYTES_PER_LINE + 1) * HEX_LINE_WIDTH);
+    for (i, line) in data.chunks(HEX_BYTES_PER_LINE).enumerate() {
+        dump.push_str(&format!("{:04x}  ", i * HEX_BYTES_PER_LINE));
+        for b in line {
+            dump.push_str(&format!("{:02x} ", b));
+        }
+        for _ in line.len()..HEX_BYTES_PER_LINE {
+            dump.push_str("   ");
+        }
+        dump.push(' ');
+        for &b in line {
+            dump.push(if b >= 0x20 && b < 0x7f { b as char } else { '.' });
         }
+        dump.push('\n');
     }
-    String::from("** Parse Error **")
+    dump
 }
 
-fn set_detail_tree(sxp: &Sexp, store: gtk::TreeStore, parent_itr: Option<&gtk::TreeIter>) {
+fn hex_text_to_byte(pos: usize, data_len: usize) -> Option<usize> {
+    let line = pos / HEX_LINE_WIDTH;
+    let col = pos % HEX_LINE_WIDTH;
+    let idx = if col >= HEX_ADDR_WIDTH && col < HEX_ASCII_START - 1 {
+        let rel = col - HEX_ADDR_WIDTH;
+        if rel % 3 == 2 { return None; }
+        rel / 3
+    } else if col >= HEX_ASCII_START && col < HEX_ASCII_START + HEX_BYTES_PER_LINE {
+        col - HEX_ASCII_START
+    } else {
+        return None;
+    };
+    let byte = line * HEX_BYTES_PER_LINE + idx;
+    if byte < data_len { Some(byte) } else { None }
+}
 
-    if let &Sexp::List(ref lst) = sxp {
-        if lst.len() != 3 { return; }
-        let itr = store.append(parent_itr);
-        let disp_str = parse_lbl_val(&lst[0]);
-        store.set(&itr, &[DETAIL_COLUMN], &[&disp_str]);
+fn highlight_bytes(buf: &gtk::TextBuffer, off: i32, len: i32) {
+    buf.remove_tag_by_name("highlight", &buf.get_start_iter(), &buf.get_end_iter());
+    if off < 0 || len <= 0 { return; }
+
+    let (start, end) = (off as usize, (off + len) as usize);
+    let mut b = start;
+    while b < end {
+        let line = b / HEX_BYTES_PER_LINE;
+        let line_end = ::std::cmp::min(end, (line + 1) * HEX_BYTES_PER_LINE);
+        let (c0, c1) = (b % HEX_BYTES_PER_LINE, (line_end - 1) % HEX_BYTES_PER_LINE + 1);
+        let base = line * HEX_LINE_WIDTH;
 
-        set_detail_tree(&lst[1], store.clone(), Some(&itr));
+        let hex_start = (base + HEX_ADDR_WIDTH + c0 * 3) as i32;
+        let hex_end = (base + HEX_ADDR_WIDTH + c1 * 3 - 1) as i32;
+        buf.apply_tag_by_name("highlight", &buf.get_iter_at_offset(hex_start), &buf.get_iter_at_offset(hex_end));
 
-        set_detail_tree(&lst[2], store, parent_itr);
+        let ascii_start = (base + HEX_ASCII_START + c0) as i32;
+        let ascii_end = (base + HEX_ASCII_START + c1) as i32;
+        buf.apply_tag_by_name("highlight", &buf.get_iter_at_offset(ascii_start), &buf.get_iter_at_offset(ascii_end));
+
+        b = line_end;
     }
 }
 
-fn set_detail_pane(store: gtk::TreeStore, tree: String) {
-    store.clear();
-
-    match sexp::parse(&tree) {
-        Ok(sxp) => set_detail_tree(&sxp, store, None),
-        Err(e) => println!("Error: {}", e),
+fn find_field(model: &gtk::TreeModel, parent: Option<&gtk::TreeIter>, pos: i32) -> Option<gtk::TreeIter> {
+    let itr = model.iter_children(parent)?;
+    loop {
+        let off = model.get_value(&itr, OFFSET_COLUMN as i32).get::<i32>().unwrap_or(-1);
+        let len = model.get_value(&itr, LENGTH_COLUMN as i32).get::<i32>().unwrap_or(0);
+        if off >= 0 && pos >= off && pos < off + len {
+            return find_field(model, Some(&itr), pos).or(Some(itr));
+        }
+        if !model.iter_next(&itr) { return None; }
     }
-    
 }
 
-fn init_list_view(builder: &gtk::Builder) {
+fn init_list_view(builder: &gtk::Builder, frames: Frames) {
     let select: gtk::TreeSelection = builder.get_object("selection").unwrap();
 
     let dtl_store: gtk::TreeStore = builder.get_object("detail-store").unwrap();
-    select.connect_changed(move |slct| {
-        if let Some((model, itr)) = slct.get_selected() {
-            let data = model.get_value(&itr, DATA_COLUMN as i32);
-            if let Some(tree) = data.get::<String>() {
-                set_detail_pane(dtl_store.clone(), tree);
+    let hex_buf: gtk::TextBuffer = builder.get_object("hex-buffer").unwrap();
+    let cur_len = Rc::new(RefCell::new(0usize));
+    {
+        let hex_buf = hex_buf.clone();
+        let cur_len = cur_len.clone();
+        select.connect_changed(move |slct| {
+            if let Some((model, itr)) = slct.get_selected() {
+                let n = model.get_value(&itr, NUMBER_COLUMN as i32).get::<u32>().unwrap_or(0);
+                if let Some(frame) = frames.borrow().get((n as usize).wrapping_sub(1)) {
+                    set_detail_pane(dtl_store.clone(), &frame.tree);
+                    hex_buf.set_text(&hex_dump(&frame.data));
+                    *cur_len.borrow_mut() = frame.data.len();
+                }
+            }
+        });
+    }
+
+    let dtl_select: gtk::TreeSelection = builder.get_object("detail-selection").unwrap();
+    {
+        let hex_buf = hex_buf.clone();
+        dtl_select.connect_changed(move |slct| {
+            match slct.get_selected() {
+                Some((model, itr)) => {
+                    let off = model.get_value(&itr, OFFSET_COLUMN as i32).get::<i32>().unwrap_or(-1);
+                    let len = model.get_value(&itr, LENGTH_COLUMN as i32).get::<i32>().unwrap_or(0);
+                    highlight_bytes(&hex_buf, off, len);
+                },
+                None => highlight_bytes(&hex_buf, -1, 0),
+            }
+        });
+    }
+
+    let hex_v: gtk::TextView = builder.get_object("hex_view").unwrap();
+    let dtl_v: gtk::TreeView = builder.get_object("detail_view").unwrap();
+    hex_v.connect_button_release_event(move |_, _| {
+        if let Some(mark) = hex_buf.get_insert() {
+            let pos = hex_buf.get_iter_at_mark(&mark).get_offset() as usize;
+            if let Some(byte) = hex_text_to_byte(pos, *cur_len.borrow()) {
+                let model = dtl_v.get_model().unwrap();
+                if let Some(itr) = find_field(&model, None, byte as i32) {
+                    if let Some(path) = model.get_path(&itr) {
+                        dtl_v.expand_to_path(&path);
+                        dtl_v.set_cursor(&path, None, false);
+                    }
+                }
             }
         }
+        gtk::Inhibit(false)
     });
 }
 
-pub fn create(app: &gtk::Application, disct_tbl: Rc<RefCell<DissectorTable>>) -> gtk::ApplicationWindow {
+pub fn create(app: &gtk::Application, disct_tbl: Rc<RefCell<DissectorTable>>, plugin_dirs: Rc<Vec<PathBuf>>, path: Option<&Path>) -> gtk::ApplicationWindow {
     let builder = gtk::Builder::new_from_file(Path::new("/usr/share/wire_shake/ui/win.ui"));
     let win: gtk::ApplicationWindow = builder.get_object("window").unwrap();
     win.set_application(Some(app));
@@ -228,9 +948,11 @@ pub fn create(app: &gtk::Application, disct_tbl: Rc<RefCell<DissectorTable>>) ->
         if_list.iter().for_each(|d| if_combo.append(None, &d.name));
     }
 
-    init_list_view(&builder);
+    let frames: Frames = Rc::new(RefCell::new(Vec::new()));
+
+    init_list_view(&builder, frames.clone());
 
-    init_action(&win, &builder, disct_tbl);
+    init_action(&win, &builder, disct_tbl, plugin_dirs, frames, path);
 
     win
 }
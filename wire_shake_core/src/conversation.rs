@@ -0,0 +1,506 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::Ipv4Addr;
+
+const TH_FIN: u16 = 0x001;
+const TH_SYN: u16 = 0x002;
+const TH_RST: u16 = 0x004;
+const TH_ACK: u16 = 0x010;
+
+// How many unacknowledged segment starts a flow remembers for telling
+// retransmissions from out-of-order data; acknowledged ones are dropped
+// as the peer's ACKs arrive.
+const TCP_MAX_SEEN: usize = 1024;
+
+// A datagram still missing fragments this many frames after its first
+// one arrived is given up on, so lost fragments don't pin memory for the
+// rest of the capture.
+const IPV4_FRAG_MAX_FRAMES: u32 = 1000;
+
+// Both directions of a connection map to the same key: the endpoint that
+// sorts first is always stored first.
+type ConvKey = (String, u16, String, u16);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TcpExpert {
+    Retransmission,
+    OutOfOrder,
+    LostSegment,
+    DuplicateAck(u32, u32),
+    ZeroWindow,
+}
+
+impl TcpExpert {
+    pub fn field(&self) -> &'static str {
+        match *self {
+            TcpExpert::Retransmission => "tcp.analysis.retransmission",
+            TcpExpert::OutOfOrder => "tcp.analysis.out_of_order",
+            TcpExpert::LostSegment => "tcp.analysis.lost_segment",
+            TcpExpert::DuplicateAck(..) => "tcp.analysis.duplicate_ack",
+            TcpExpert::ZeroWindow => "tcp.analysis.zero_window",
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match *self {
+            TcpExpert::Retransmission => String::from("This frame is a (suspected) retransmission"),
+            TcpExpert::OutOfOrder => String::from("This frame is a (suspected) out-of-order segment"),
+            TcpExpert::LostSegment => String::from("Previous segment(s) not captured (common at capture start)"),
+            TcpExpert::DuplicateAck(n, orig) => format!("Duplicate ACK #{} of frame {}", n, orig),
+            TcpExpert::ZeroWindow => String::from("TCP Zero Window segment"),
+        }
+    }
+
+    pub fn info(&self) -> String {
+        match *self {
+            TcpExpert::Retransmission => String::from("[TCP Retransmission]"),
+            TcpExpert::OutOfOrder => String::from("[TCP Out-Of-Order]"),
+            TcpExpert::LostSegment => String::from("[TCP Previous segment not captured]"),
+            TcpExpert::DuplicateAck(n, orig) => format!("[TCP Dup ACK {}#{}]", orig, n),
+            TcpExpert::ZeroWindow => String::from("[TCP ZeroWindow]"),
+        }
+    }
+}
+
+pub struct TcpSegment {
+    pub frame: u32,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u16,
+    pub win: u16,
+    pub len: u32,
+}
+
+pub struct TcpAnalysis {
+    pub stream: u32,
+    pub rel_seq: u32,
+    pub rel_ack: Option<u32>,
+    pub next_seq: Option<u32>,
+    pub experts: Vec<TcpExpert>,
+}
+
+// A PDU that an application dissector asked to see more of. `need` is the
+// total length it wants and `frames` lists (frame number, bytes) for
+// every segment appended so far.
+pub struct TcpPdu {
+    pub next_seq: u32,
+    pub need: usize,
+    pub data: Vec<u8>,
+    pub frames: Vec<(u32, usize)>,
+}
+
+#[derive(Default)]
+struct TcpFlow {
+    base_seq: Option<u32>,
+    next_seq: Option<u32>,
+    seen: VecDeque<u32>,
+    // The highest ACK the peer has sent for this direction.
+    acked: Option<u32>,
+    last_ack: Option<u32>,
+    last_win: u16,
+    last_ack_frame: u32,
+    dup_acks: u32,
+    pdu: Option<TcpPdu>,
+}
+
+struct TcpConversation {
+    stream: u32,
+    flows: [TcpFlow; 2],
+}
+
+fn conv_key(src: &str, sport: u16, dst: &str, dport: u16) -> (ConvKey, bool) {
+    let fwd = (src, sport) <= (dst, dport);
+    let key = if fwd {
+        (src.to_string(), sport, dst.to_string(), dport)
+    } else {
+        (dst.to_string(), dport, src.to_string(), sport)
+    };
+    (key, fwd)
+}
+
+// (source, destination, identification, protocol)
+pub type FragKey = (Ipv4Addr, Ipv4Addr, u16, u8);
+
+#[derive(Default)]
+struct FragBuf {
+    first_frame: u32,
+    total: Option<usize>,
+    frags: Vec<(usize, Vec<u8>)>,
+    frames: Vec<u32>,
+}
+
+impl FragBuf {
+    fn complete(&self) -> bool {
+        let total = match self.total {
+            Some(t) => t,
+            None => return false,
+        };
+        let mut ranges: Vec<(usize, usize)> = self.frags.iter().map(|&(off, ref d)| (off, off + d.len())).collect();
+        ranges.sort();
+        let mut covered = 0;
+        for (start, end) in ranges {
+            if start > covered { return false; }
+            covered = ::std::cmp::max(covered, end);
+        }
+        covered >= total
+    }
+}
+
+// A datagram rebuilt from fragments; `frags` lists (frame number,
+// offset, length) in the order the fragments arrived.
+pub struct Datagram {
+    pub data: Vec<u8>,
+    pub frags: Vec<(u32, usize, usize)>,
+}
+
+// Sequence-space comparison that survives wraparound.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+pub struct ConversationTable {
+    tcp: HashMap<ConvKey, TcpConversation>,
+    udp: HashMap<ConvKey, u32>,
+    ipv4_frags: HashMap<FragKey, FragBuf>,
+    reassembled_in: Vec<(u32, u32)>,
+}
+
+impl ConversationTable {
+    pub fn new() -> ConversationTable {
+        ConversationTable {
+            tcp: HashMap::new(),
+            udp: HashMap::new(),
+            ipv4_frags: HashMap::new(),
+            reassembled_in: Vec::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tcp.clear();
+        self.udp.clear();
+        self.ipv4_frags.clear();
+        self.reassembled_in.clear();
+    }
+
+    // Buffers one fragment and returns the whole datagram once every byte
+    // up to the last fragment has been seen. Overlapping data from later
+    // fragments wins. Frames must be fed in capture order.
+    pub fn ipv4_fragment(&mut self, key: FragKey, frame: u32, offset: usize, more: bool, data: &[u8]) -> Option<Datagram> {
+        self.expire_fragments(frame);
+        let complete = {
+            let buf = self.ipv4_frags.entry(key).or_insert_with(|| FragBuf { first_frame: frame, ..FragBuf::default() });
+            if !more {
+                buf.total = Some(offset + data.len());
+            }
+            buf.frags.push((offset, data.to_vec()));
+            buf.frames.push(frame);
+            buf.complete()
+        };
+        if !complete {
+            return None;
+        }
+
+        let buf = self.ipv4_frags.remove(&key).unwrap();
+        let mut dgram = vec![0u8; buf.total.unwrap()];
+        let mut frags = Vec::new();
+        for (&(off, ref d), &n) in buf.frags.iter().zip(buf.frames.iter()) {
+            let end = ::std::cmp::min(off + d.len(), dgram.len());
+            if off < end {
+                dgram[off..end].copy_from_slice(&d[..end - off]);
+            }
+            frags.push((n, off, d.len()));
+            if n != frame {
+                self.reassembled_in.push((n, frame));
+            }
+        }
+        Some(Datagram { data: dgram, frags: frags })
+    }
+
+    fn expire_fragments(&mut self, frame: u32) {
+        self.ipv4_frags.retain(|_, buf| frame.saturating_sub(buf.first_frame) < IPV4_FRAG_MAX_FRAMES);
+    }
+
+    // The first frame of the oldest datagram still waiting for fragments
+    // once `frame` has been seen. No frame before it will be reassembled
+    // later.
+    pub fn first_pending_fragment(&mut self, frame: u32) -> Option<u32> {
+        self.expire_fragments(frame);
+        self.ipv4_frags.values().map(|buf| buf.first_frame).min()
+    }
+
+    // (fragment frame, reassembling frame) pairs completed since the last
+    // call, so earlier frames can be labeled after the fact.
+    pub fn take_reassembled(&mut self) -> Vec<(u32, u32)> {
+        ::std::mem::replace(&mut self.reassembled_in, Vec::new())
+    }
+
+    pub fn udp_stream(&mut self, src: &str, sport: u16, dst: &str, dport: u16) -> u32 {
+        let (key, _) = conv_key(src, sport, dst, dport);
+        let next_stream = self.udp.len() as u32;
+        *self.udp.entry(key).or_insert(next_stream)
+    }
+
+    fn tcp_flow(&mut self, src: &str, sport: u16, dst: &str, dport: u16) -> Option<&mut TcpFlow> {
+        let (key, fwd) = conv_key(src, sport, dst, dport);
+        self.tcp.get_mut(&key).map(|conv| &mut conv.flows[if fwd { 0 } else { 1 }])
+    }
+
+    // Hands back the pending PDU only if `seq` continues it; anything else
+    // (retransmissions, out-of-order data) leaves it in place.
+    pub fn take_tcp_pdu(&mut self, src: &str, sport: u16, dst: &str, dport: u16, seq: u32) -> Option<TcpPdu> {
+        let flow = self.tcp_flow(src, sport, dst, dport)?;
+        if flow.pdu.as_ref().map_or(false, |pdu| pdu.next_seq == seq) {
+            flow.pdu.take()
+        } else {
+            None
+        }
+    }
+
+    pub fn keep_tcp_pdu(&mut self, src: &str, sport: u16, dst: &str, dport: u16, pdu: TcpPdu) {
+        if let Some(flow) = self.tcp_flow(src, sport, dst, dport) {
+            flow.pdu = Some(pdu);
+        }
+    }
+
+    // Segments must be fed in capture order; the analysis for each one
+    // depends on everything seen before it on the same connection.
+    pub fn tcp_segment(&mut self, src: &str, sport: u16, dst: &str, dport: u16, seg: &TcpSegment) -> TcpAnalysis {
+        let (key, fwd) = conv_key(src, sport, dst, dport);
+        let next_stream = self.tcp.len() as u32;
+        let conv = self.tcp.entry(key).or_insert_with(|| TcpConversation {
+            stream: next_stream,
+            flows: [TcpFlow::default(), TcpFlow::default()],
+        });
+        let stream = conv.stream;
+        let (a, b) = conv.flows.split_at_mut(1);
+        let (flow, rev) = if fwd { (&mut a[0], &mut b[0]) } else { (&mut b[0], &mut a[0]) };
+
+        let syn = seg.flags & TH_SYN != 0;
+        let fin = seg.flags & TH_FIN != 0;
+        let rst = seg.flags & TH_RST != 0;
+        let has_ack = seg.flags & TH_ACK != 0;
+
+        // Without a SYN the ISN is unknown, so pretend the handshake
+        // consumed the byte before the first one seen.
+        let base = *flow.base_seq.get_or_insert(if syn { seg.seq } else { seg.seq.wrapping_sub(1) });
+        if has_ack && rev.base_seq.is_none() {
+            rev.base_seq = Some(seg.ack.wrapping_sub(1));
+        }
+
+        let mut experts = Vec::new();
+        let seg_end = seg.seq.wrapping_add(seg.len + syn as u32 + fin as u32);
+
+        if seg.win == 0 && !(syn || fin || rst) {
+            experts.push(TcpExpert::ZeroWindow);
+        }
+
+        if seg_end != seg.seq {
+            if let Some(next) = flow.next_seq {
+                if seq_lt(next, seg.seq) {
+                    experts.push(TcpExpert::LostSegment);
+                } else if seq_lt(seg.seq, next) {
+                    let acked = flow.acked.map_or(false, |a| seq_lt(seg.seq, a));
+                    experts.push(if acked || flow.seen.contains(&seg.seq) {
+                        TcpExpert::Retransmission
+                    } else {
+                        TcpExpert::OutOfOrder
+                    });
+                }
+            }
+            if !flow.seen.contains(&seg.seq) {
+                if flow.seen.len() == TCP_MAX_SEEN {
+                    flow.seen.pop_front();
+                }
+                flow.seen.push_back(seg.seq);
+            }
+            if flow.next_seq.map_or(true, |next| seq_lt(next, seg_end)) {
+                flow.next_seq = Some(seg_end);
+            }
+        }
+
+        if has_ack && seg_end == seg.seq && !rst && flow.last_ack == Some(seg.ack) && flow.last_win == seg.win {
+            flow.dup_acks += 1;
+            experts.push(TcpExpert::DuplicateAck(flow.dup_acks, flow.last_ack_frame));
+        } else if has_ack {
+            flow.dup_acks = 0;
+            flow.last_ack = Some(seg.ack);
+            flow.last_ack_frame = seg.frame;
+        }
+        flow.last_win = seg.win;
+
+        if has_ack && !rst && rev.acked.map_or(true, |a| seq_lt(a, seg.ack)) {
+            rev.acked = Some(seg.ack);
+            rev.seen.retain(|&s| !seq_lt(s, seg.ack));
+        }
+
+        TcpAnalysis {
+            stream: stream,
+            rel_seq: seg.seq.wrapping_sub(base),
+            rel_ack: if has_ack { rev.base_seq.map(|b| seg.ack.wrapping_sub(b)) } else { None },
+            next_seq: if seg_end != seg.seq { Some(seg_end.wrapping_sub(base)) } else { None },
+            experts: experts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &'static str = "10.0.0.1";
+    const B: &'static str = "10.0.0.2";
+
+    fn seg(frame: u32, seq: u32, ack: u32, flags: u16, win: u16, len: u32) -> TcpSegment {
+        TcpSegment { frame: frame, seq: seq, ack: ack, flags: flags, win: win, len: len }
+    }
+
+    // Feeds one segment; `fwd` segments go from A:1025 to B:80.
+    fn feed(convs: &mut ConversationTable, fwd: bool, seg: TcpSegment) -> TcpAnalysis {
+        if fwd {
+            convs.tcp_segment(A, 1025, B, 80, &seg)
+        } else {
+            convs.tcp_segment(B, 80, A, 1025, &seg)
+        }
+    }
+
+    // A three-way handshake with ISNs 1000 (A) and 5000 (B); frames 1-3.
+    fn handshake() -> ConversationTable {
+        let mut convs = ConversationTable::new();
+        feed(&mut convs, true, seg(1, 1000, 0, TH_SYN, 1000, 0));
+        feed(&mut convs, false, seg(2, 5000, 1001, TH_SYN | TH_ACK, 1000, 0));
+        feed(&mut convs, true, seg(3, 1001, 5001, TH_ACK, 1000, 0));
+        convs
+    }
+
+    fn frag_key(id: u16) -> FragKey {
+        ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap(), id, 17)
+    }
+
+    #[test]
+    fn fragments_in_order() {
+        let mut convs = ConversationTable::new();
+        assert!(convs.ipv4_fragment(frag_key(1), 1, 0, true, b"01234567").is_none());
+        assert!(convs.ipv4_fragment(frag_key(1), 2, 8, true, b"89abcdef").is_none());
+        let dgram = convs.ipv4_fragment(frag_key(1), 3, 16, false, b"gh").unwrap();
+        assert_eq!(dgram.data, b"0123456789abcdefgh".to_vec());
+        assert_eq!(dgram.frags, vec![(1, 0, 8), (2, 8, 8), (3, 16, 2)]);
+        assert_eq!(convs.take_reassembled(), vec![(1, 3), (2, 3)]);
+        assert!(convs.ipv4_frags.is_empty());
+    }
+
+    #[test]
+    fn fragments_out_of_order() {
+        let mut convs = ConversationTable::new();
+        assert!(convs.ipv4_fragment(frag_key(1), 1, 16, false, b"gh").is_none());
+        // Another datagram's fragments don't mix in.
+        assert!(convs.ipv4_fragment(frag_key(2), 2, 0, true, b"xxxxxxxx").is_none());
+        assert!(convs.ipv4_fragment(frag_key(1), 3, 0, true, b"01234567").is_none());
+        let dgram = convs.ipv4_fragment(frag_key(1), 4, 8, true, b"89abcdef").unwrap();
+        assert_eq!(dgram.data, b"0123456789abcdefgh".to_vec());
+        assert_eq!(dgram.frags, vec![(1, 16, 2), (3, 0, 8), (4, 8, 8)]);
+        assert_eq!(convs.ipv4_frags.len(), 1);
+        assert_eq!(convs.first_pending_fragment(4), Some(2));
+        assert_eq!(convs.first_pending_fragment(2 + IPV4_FRAG_MAX_FRAMES), None);
+    }
+
+    #[test]
+    fn overlapping_fragments() {
+        let mut convs = ConversationTable::new();
+        assert!(convs.ipv4_fragment(frag_key(1), 1, 0, true, b"01234567").is_none());
+        assert!(convs.ipv4_fragment(frag_key(1), 2, 16, false, b"gh").is_none());
+        // Covers the gap and rewrites bytes the first fragment already had.
+        let dgram = convs.ipv4_fragment(frag_key(1), 3, 4, true, b"WXYZ89abcdef").unwrap();
+        assert_eq!(dgram.data, b"0123WXYZ89abcdefgh".to_vec());
+    }
+
+    #[test]
+    fn missing_last_fragment() {
+        let mut convs = ConversationTable::new();
+        assert!(convs.ipv4_fragment(frag_key(1), 1, 0, true, b"01234567").is_none());
+        assert!(convs.ipv4_fragment(frag_key(1), 2, 8, true, b"89abcdef").is_none());
+        assert!(convs.take_reassembled().is_empty());
+        assert_eq!(convs.ipv4_frags.len(), 1);
+
+        // Given up on once enough frames have gone by; the late last
+        // fragment then can't complete it.
+        assert!(convs.ipv4_fragment(frag_key(2), IPV4_FRAG_MAX_FRAMES, 0, true, b"x").is_none());
+        assert_eq!(convs.ipv4_frags.len(), 2);
+        assert!(convs.ipv4_fragment(frag_key(1), IPV4_FRAG_MAX_FRAMES + 1, 16, false, b"gh").is_none());
+        assert_eq!(convs.ipv4_frags.len(), 2);
+        assert_eq!(convs.ipv4_frags[&frag_key(1)].frags.len(), 1);
+    }
+
+    #[test]
+    fn sequence_numbers_are_relative() {
+        let mut convs = ConversationTable::new();
+        let syn = feed(&mut convs, true, seg(1, 1000, 0, TH_SYN, 1000, 0));
+        assert_eq!((syn.rel_seq, syn.rel_ack, syn.next_seq), (0, None, Some(1)));
+        let syn_ack = feed(&mut convs, false, seg(2, 5000, 1001, TH_SYN | TH_ACK, 1000, 0));
+        assert_eq!((syn_ack.rel_seq, syn_ack.rel_ack), (0, Some(1)));
+        let data = feed(&mut convs, true, seg(3, 1001, 5001, TH_ACK, 1000, 100));
+        assert_eq!((data.rel_seq, data.rel_ack, data.next_seq), (1, Some(1), Some(101)));
+        assert!(data.experts.is_empty());
+        assert_eq!(syn.stream, data.stream);
+
+        // Without a handshake the first byte seen is byte 1.
+        let mid = convs.tcp_segment(A, 2000, B, 80, &seg(4, 70000, 9000, TH_ACK, 1000, 10));
+        assert_eq!((mid.rel_seq, mid.rel_ack), (1, Some(1)));
+        assert_eq!(mid.stream, 1);
+    }
+
+    #[test]
+    fn retransmission_and_out_of_order() {
+        let mut convs = handshake();
+        assert!(feed(&mut convs, true, seg(4, 1001, 5001, TH_ACK, 1000, 100)).experts.is_empty());
+        assert!(feed(&mut convs, true, seg(5, 1101, 5001, TH_ACK, 1000, 100)).experts.is_empty());
+        let re = feed(&mut convs, true, seg(6, 1001, 5001, TH_ACK, 1000, 100));
+        assert_eq!(re.experts, vec![TcpExpert::Retransmission]);
+
+        // 1301 arrives before 1201.
+        let lost = feed(&mut convs, true, seg(7, 1301, 5001, TH_ACK, 1000, 100));
+        assert_eq!(lost.experts, vec![TcpExpert::LostSegment]);
+        let ooo = feed(&mut convs, true, seg(8, 1201, 5001, TH_ACK, 1000, 100));
+        assert_eq!(ooo.experts, vec![TcpExpert::OutOfOrder]);
+        assert_eq!(ooo.rel_seq, 201);
+    }
+
+    #[test]
+    fn duplicate_acks_and_zero_window() {
+        let mut convs = handshake();
+        feed(&mut convs, true, seg(4, 1001, 5001, TH_ACK, 1000, 100));
+        assert!(feed(&mut convs, false, seg(5, 5001, 1101, TH_ACK, 1000, 0)).experts.is_empty());
+        let dup1 = feed(&mut convs, false, seg(6, 5001, 1101, TH_ACK, 1000, 0));
+        assert_eq!(dup1.experts, vec![TcpExpert::DuplicateAck(1, 5)]);
+        let dup2 = feed(&mut convs, false, seg(7, 5001, 1101, TH_ACK, 1000, 0));
+        assert_eq!(dup2.experts, vec![TcpExpert::DuplicateAck(2, 5)]);
+
+        // A window update is not a duplicate.
+        let zero = feed(&mut convs, false, seg(8, 5001, 1101, TH_ACK, 0, 0));
+        assert_eq!(zero.experts, vec![TcpExpert::ZeroWindow]);
+        assert!(feed(&mut convs, false, seg(9, 5001, 1101, TH_ACK, 1000, 0)).experts.is_empty());
+        assert_eq!(feed(&mut convs, false, seg(10, 5001, 1101, TH_ACK, 1000, 0)).experts,
+                   vec![TcpExpert::DuplicateAck(1, 9)]);
+    }
+
+    #[test]
+    fn acknowledged_segments_are_forgotten() {
+        let mut convs = handshake();
+        let mut seq = 1001u32;
+        for n in 0..(TCP_MAX_SEEN as u32 * 4) {
+            feed(&mut convs, true, seg(4 + n * 2, seq, 5001, TH_ACK, 1000, 100));
+            seq = seq.wrapping_add(100);
+            feed(&mut convs, false, seg(5 + n * 2, 5001, seq, TH_ACK, 1000, 0));
+        }
+        assert!(convs.tcp_flow(A, 1025, B, 80).unwrap().seen.is_empty());
+
+        // Data the peer already acknowledged is still a retransmission.
+        let re = feed(&mut convs, true, seg(100000, 1001, 5001, TH_ACK, 1000, 100));
+        assert_eq!(re.experts, vec![TcpExpert::Retransmission]);
+
+        // Without ACKs, e.g. capturing one direction only, the set is capped.
+        for _ in 0..(TCP_MAX_SEEN * 2) {
+            feed(&mut convs, true, seg(100001, seq, 5001, TH_ACK, 1000, 100));
+            seq = seq.wrapping_add(100);
+        }
+        assert_eq!(convs.tcp_flow(A, 1025, B, 80).unwrap().seen.len(), TCP_MAX_SEEN);
+    }
+}
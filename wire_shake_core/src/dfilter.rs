@@ -0,0 +1,446 @@
+use std::fmt;
+use std::cmp::Ordering;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use proto_tree::{ProtoTree, FieldValue};
+
+//<expr>    := <and> (("||" | "or") <and>)*
+//<and>     := <unary> (("&&" | "and") <unary>)*
+//<unary>   := ("!" | "not") <unary> | <primary>
+//<primary> := "(" <expr> ")" | <field> [<cmp-op> <value>]
+//<cmp-op>  := "==" | "!=" | "<" | "<=" | ">" | ">=" | eq | ne | lt | le | gt | ge | contains
+//<value>   := <word> | "\"" <string> "\""
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+// A value to compare against, read at parse time as each type it could
+// be, so a field is compared by its own type.
+#[derive(Debug, Clone, PartialEq)]
+struct Literal {
+    text: String,
+    num: Option<i64>,
+    ipv4: Option<(u32, u32)>,
+    ipv6: Option<Ipv6Addr>,
+}
+
+impl Literal {
+    fn new(text: String) -> Literal {
+        Literal {
+            num: parse_num(&text),
+            ipv4: parse_ipv4_net(&text),
+            ipv6: Ipv6Addr::from_str(&text).ok(),
+            text: text,
+        }
+    }
+
+    fn is_typed(&self) -> bool {
+        self.num.is_some() || self.ipv4.is_some() || self.ipv6.is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Exists(String),
+    Cmp(String, CmpOp, Literal),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Word(String),
+    Str(String),
+    Cmp(CmpOp),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub pos: usize,
+    pub len: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (at column {})", self.msg, self.pos + 1)
+    }
+}
+
+#[derive(Debug)]
+pub struct Filter {
+    expr: Expr,
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !"()!=<>&|\"".contains(c)
+}
+
+fn tokenize(s: &str) -> Result<Vec<(Tok, usize, usize)>, ParseError> {
+    let mut toks = Vec::new();
+    let chars: Vec<(usize, char)> = s.char_indices().collect();
+    let mut i = 0;
+
+    let err = |pos: usize, len: usize, msg: &str| ParseError { pos: pos, len: len, msg: msg.to_string() };
+
+    while i < chars.len() {
+        let (pos, c) = chars[i];
+        let next = chars.get(i + 1).map(|&(_, c)| c);
+        let (tok, n) = match (c, next) {
+            (c, _) if c.is_whitespace() => { i += 1; continue; },
+            ('(', _) => (Tok::LParen, 1),
+            (')', _) => (Tok::RParen, 1),
+            ('&', Some('&')) => (Tok::And, 2),
+            ('|', Some('|')) => (Tok::Or, 2),
+            ('=', Some('=')) => (Tok::Cmp(CmpOp::Eq), 2),
+            ('!', Some('=')) => (Tok::Cmp(CmpOp::Ne), 2),
+            ('<', Some('=')) => (Tok::Cmp(CmpOp::Le), 2),
+            ('>', Some('=')) => (Tok::Cmp(CmpOp::Ge), 2),
+            ('<', _) => (Tok::Cmp(CmpOp::Lt), 1),
+            ('>', _) => (Tok::Cmp(CmpOp::Gt), 1),
+            ('!', _) => (Tok::Not, 1),
+            ('"', _) => {
+                let mut val = String::new();
+                let mut j = i + 1;
+                let mut closed = false;
+                while j < chars.len() {
+                    match chars[j].1 {
+                        '"' => { closed = true; break; },
+                        '\\' if j + 1 < chars.len() => {
+                            val.push(chars[j + 1].1);
+                            j += 2;
+                        },
+                        c => {
+                            val.push(c);
+                            j += 1;
+                        },
+                    }
+                }
+                if !closed {
+                    return Err(err(pos, s.len() - pos, "unterminated string"));
+                }
+                let end = chars[j].0 + 1;
+                toks.push((Tok::Str(val), pos, end));
+                i = j + 1;
+                continue;
+            },
+            (c, _) if is_word_char(c) => {
+                let mut j = i;
+                while j < chars.len() && is_word_char(chars[j].1) {
+                    j += 1;
+                }
+                let end = chars.get(j).map(|&(p, _)| p).unwrap_or(s.len());
+                let word = &s[pos..end];
+                let tok = match word {
+                    "and" => Tok::And,
+                    "or" => Tok::Or,
+                    "not" => Tok::Not,
+                    "eq" => Tok::Cmp(CmpOp::Eq),
+                    "ne" => Tok::Cmp(CmpOp::Ne),
+                    "lt" => Tok::Cmp(CmpOp::Lt),
+                    "le" => Tok::Cmp(CmpOp::Le),
+                    "gt" => Tok::Cmp(CmpOp::Gt),
+                    "ge" => Tok::Cmp(CmpOp::Ge),
+                    "contains" => Tok::Cmp(CmpOp::Contains),
+                    w => Tok::Word(w.to_string()),
+                };
+                toks.push((tok, pos, end));
+                i = j;
+                continue;
+            },
+            (c, _) => return Err(err(pos, c.len_utf8(), &format!("unexpected character '{}'", c))),
+        };
+        let end = pos + n;
+        toks.push((tok, pos, end));
+        i += n;
+    }
+
+    Ok(toks)
+}
+
+fn is_field_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {},
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+}
+
+struct Parser {
+    toks: Vec<(Tok, usize, usize)>,
+    idx: usize,
+    len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.idx).map(|&(ref t, _, _)| t)
+    }
+
+    fn error(&self, msg: &str) -> ParseError {
+        match self.toks.get(self.idx) {
+            Some(&(_, start, end)) => ParseError { pos: start, len: end - start, msg: msg.to_string() },
+            None => ParseError { pos: self.len, len: 0, msg: msg.to_string() },
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_and()?;
+        while let Some(&Tok::Or) = self.peek() {
+            self.idx += 1;
+            let rhs = self.parse_and()?;
+            lhs = Expr::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(&Tok::And) = self.peek() {
+            self.idx += 1;
+            let rhs = self.parse_unary()?;
+            lhs = Expr::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        if let Some(&Tok::Not) = self.peek() {
+            self.idx += 1;
+            let e = self.parse_unary()?;
+            return Ok(Expr::Not(Box::new(e)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek().cloned() {
+            Some(Tok::LParen) => {
+                self.idx += 1;
+                let e = self.parse_or()?;
+                match self.peek() {
+                    Some(&Tok::RParen) => {
+                        self.idx += 1;
+                        Ok(e)
+                    },
+                    _ => Err(self.error("expected ')'")),
+                }
+            },
+            Some(Tok::Word(field)) => {
+                if !is_field_name(&field) {
+                    return Err(self.error("expected a field name"));
+                }
+                self.idx += 1;
+                let op = match self.peek() {
+                    Some(&Tok::Cmp(op)) => op,
+                    _ => return Ok(Expr::Exists(field)),
+                };
+                self.idx += 1;
+                let (lit, quoted) = match self.peek().cloned() {
+                    Some(Tok::Word(v)) => (Literal::new(v), false),
+                    Some(Tok::Str(v)) => (Literal::new(v), true),
+                    _ => return Err(self.error("expected a value")),
+                };
+                // Only a quoted string asks for text ordering; a bare
+                // word there is a mistyped number or address.
+                let ordered = op != CmpOp::Eq && op != CmpOp::Ne && op != CmpOp::Contains;
+                if ordered && !quoted && !lit.is_typed() {
+                    return Err(self.error(&format!("\"{}\" is not a number or address", lit.text)));
+                }
+                self.idx += 1;
+                Ok(Expr::Cmp(field, op, lit))
+            },
+            Some(_) => Err(self.error("unexpected token")),
+            None => Err(self.error("unexpected end of filter")),
+        }
+    }
+}
+
+fn parse_num(s: &str) -> Option<i64> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        i64::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse::<i64>().ok()
+    }
+}
+
+fn parse_ipv4_net(s: &str) -> Option<(u32, u32)> {
+    let mut it = s.splitn(2, '/');
+    let addr = Ipv4Addr::from_str(it.next().unwrap()).ok()?;
+    let bits = match it.next() {
+        Some(b) => b.parse::<u32>().ok().and_then(|b| if b <= 32 { Some(b) } else { None })?,
+        None => 32,
+    };
+    let mask = if bits == 0 { 0 } else { !0u32 << (32 - bits) };
+    Some((u32::from(addr) & mask, mask))
+}
+
+fn holds(op: CmpOp, ord: Ordering) -> bool {
+    match op {
+        CmpOp::Eq | CmpOp::Ne => ord == Ordering::Equal,
+        CmpOp::Lt => ord == Ordering::Less,
+        CmpOp::Le => ord != Ordering::Greater,
+        CmpOp::Gt => ord == Ordering::Greater,
+        CmpOp::Ge => ord != Ordering::Less,
+        CmpOp::Contains => unreachable!(),
+    }
+}
+
+fn cmp_ipv4(a: Ipv4Addr, op: CmpOp, (net, mask): (u32, u32)) -> Ordering {
+    let a = u32::from(a);
+    if op == CmpOp::Eq || op == CmpOp::Ne {
+        if a & mask == net { Ordering::Equal } else { Ordering::Less }
+    } else {
+        a.cmp(&net)
+    }
+}
+
+// Fields whose value is text, such as those from Scheme dissectors, are
+// compared as whatever the text reads as.
+fn compare_text(val: &str, op: CmpOp, lit: &Literal) -> bool {
+    let ord = if let (Some(a), Some(b)) = (parse_num(val), lit.num) {
+        a.cmp(&b)
+    } else if let (Ok(a), Some(net)) = (Ipv4Addr::from_str(val), lit.ipv4) {
+        cmp_ipv4(a, op, net)
+    } else if let (Ok(a), Some(b)) = (Ipv6Addr::from_str(val), lit.ipv6) {
+        a.cmp(&b)
+    } else if op == CmpOp::Eq || op == CmpOp::Ne {
+        if val.eq_ignore_ascii_case(&lit.text) { Ordering::Equal } else { Ordering::Less }
+    } else {
+        val.cmp(&lit.text)
+    };
+    holds(op, ord)
+}
+
+// A typed field never matches a literal of another type.
+fn compare(val: &FieldValue, op: CmpOp, lit: &Literal) -> bool {
+    if op == CmpOp::Contains {
+        return val.to_string().contains(&lit.text);
+    }
+
+    let ord = match *val {
+        FieldValue::UInt(a) => lit.num.map(|b| (a as i128).cmp(&(b as i128))),
+        FieldValue::Int(a) => lit.num.map(|b| a.cmp(&b)),
+        FieldValue::Bool(a) => lit.num.map(|b| (a as i64).cmp(&b)),
+        FieldValue::Ipv4(a) => lit.ipv4.map(|net| cmp_ipv4(a, op, net)),
+        FieldValue::Ipv6(a) => lit.ipv6.map(|b| a.cmp(&b)),
+        _ => return compare_text(&val.to_string(), op, lit),
+    };
+    ord.map_or(false, |ord| holds(op, ord))
+}
+
+// Only the values of fields the filter names are looked at, so a large
+// payload field costs nothing unless it is compared.
+fn eval(expr: &Expr, tree: &ProtoTree) -> bool {
+    match *expr {
+        Expr::Exists(ref name) => tree.any_field(name, |_| true),
+        Expr::Cmp(ref name, CmpOp::Ne, ref lit) => {
+            let mut present = false;
+            let equal = tree.any_field(name, |n| {
+                present = true;
+                compare(&n.typed, CmpOp::Eq, lit)
+            });
+            present && !equal
+        },
+        Expr::Cmp(ref name, op, ref lit) => tree.any_field(name, |n| compare(&n.typed, op, lit)),
+        Expr::And(ref l, ref r) => eval(l, tree) && eval(r, tree),
+        Expr::Or(ref l, ref r) => eval(l, tree) || eval(r, tree),
+        Expr::Not(ref e) => !eval(e, tree),
+    }
+}
+
+impl Filter {
+    pub fn parse(s: &str) -> Result<Filter, ParseError> {
+        let toks = tokenize(s)?;
+        let mut parser = Parser { toks: toks, idx: 0, len: s.len() };
+        let expr = parser.parse_or()?;
+        if parser.idx < parser.toks.len() {
+            return Err(parser.error("unexpected token"));
+        }
+        Ok(Filter { expr: expr })
+    }
+
+    pub fn matches(&self, tree: &ProtoTree) -> bool {
+        eval(&self.expr, tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto_tree::FieldNode;
+
+    fn fields(kv: &[(&str, &str)]) -> ProtoTree {
+        let mut tree = ProtoTree::new();
+        for &(k, v) in kv {
+            tree.add(FieldNode::generated(k, k, FieldValue::Str(v.to_string())));
+        }
+        tree
+    }
+
+    #[test]
+    fn compares_numbers_and_addresses() {
+        let f = fields(&[("ip.src", "10.1.2.3"), ("tcp.port", "443"), ("tcp.port", "51000")]);
+        assert!(Filter::parse("ip.src == 10.1.0.0/16 && tcp.port == 0x1bb").unwrap().matches(&f));
+        assert!(Filter::parse("tcp.port > 50000").unwrap().matches(&f));
+        assert!(!Filter::parse("tcp.port != 443").unwrap().matches(&f));
+        assert!(!Filter::parse("udp || ip.src == 10.2.0.0/16").unwrap().matches(&f));
+        assert!(Filter::parse("!(udp) and tcp.port").unwrap().matches(&f));
+    }
+
+    #[test]
+    fn finds_nested_fields() {
+        let mut tree = ProtoTree::new();
+        tree.add(FieldNode::proto("tcp", "Transmission Control Protocol", String::new(), 0, 20))
+            .add(FieldNode::new("tcp.flags", "Flags", FieldValue::UInt(0x12), 13, 1))
+            .add(FieldNode::new("tcp.flags.syn", "SYN", FieldValue::Bool(true), 13, 1));
+        assert!(Filter::parse("tcp.flags.syn == 1 && tcp.flags == 0x12").unwrap().matches(&tree));
+        assert!(!Filter::parse("tcp.flags.ack").unwrap().matches(&tree));
+    }
+
+    #[test]
+    fn compares_by_field_type() {
+        let mut tree = ProtoTree::new();
+        tree.add(FieldNode::generated("tcp.port", "Port", FieldValue::UInt(443)));
+        tree.add(FieldNode::generated("ip.src", "Source", FieldValue::Ipv4(Ipv4Addr::new(10, 0, 0, 1))));
+        tree.add(FieldNode::generated("http.host", "Host", FieldValue::Str(String::from("443"))));
+        assert!(!Filter::parse("ip.src == 443").unwrap().matches(&tree));
+        assert!(!Filter::parse("tcp.port == 10.0.0.1").unwrap().matches(&tree));
+        assert!(Filter::parse("http.host == 0x1bb").unwrap().matches(&tree));
+        assert!(!Filter::parse("tcp.port < \"5\"").unwrap().matches(&tree));
+    }
+
+    #[test]
+    fn rejects_untyped_ordering_literals() {
+        let e = Filter::parse("tcp.port > abc").err().unwrap();
+        assert_eq!(e.pos, 11);
+        assert_eq!(e.msg, "\"abc\" is not a number or address");
+        assert!(Filter::parse("ip.src >= 10.0.0.1").is_ok());
+        assert!(Filter::parse("http.host > \"abc\"").is_ok());
+        assert!(Filter::parse("tcp.port == abc").is_ok());
+    }
+
+    #[test]
+    fn reports_error_position() {
+        let e = Filter::parse("tcp.port == ").err().unwrap();
+        assert_eq!(e.pos, 12);
+        let e = Filter::parse("(tcp").err().unwrap();
+        assert_eq!(e.msg, "expected ')'");
+    }
+}
@@ -0,0 +1,3137 @@
+use std::io::Cursor;
+use std::collections::{HashMap, HashSet, VecDeque};
+use byteorder::{ReadBytesExt, ByteOrder, BigEndian};
+use time;
+use time::{Timespec};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::ffi::CString;
+use libc::{c_void, c_char};
+use guile_sys::{
+    SCM, scm_to_pointer, scm_symbol_to_string, scm_to_locale_string,
+    scm_to_uint16, scm_to_uint32, scm_to_int32, scm_to_int64,
+    scm_from_pointer, scm_from_uint32, scm_is_integer, scm_is_string,
+    scm_pointer_to_bytevector, scm_bytevector_to_pointer, scm_c_bytevector_length,
+    scm_from_int32, scm_from_utf8_symbol, scm_call_2, scm_object_to_string,
+    scm_variable_ref, scm_c_lookup, scm_misc_error, scm_list_1, scm_list_3, scm_from_locale_string,
+    scm_gc_protect_object, scm_gc_unprotect_object, scm_is_unsigned_integer
+};
+
+use pcapfile::FrameHeader;
+use proto_tree::{ProtoTree, FieldNode, FieldValue};
+use scheme::catch_all;
+use conversation::{ConversationTable, TcpSegment, TcpExpert, TcpPdu};
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+const ETHER_HDR_LEN: usize = 14;
+const IPV4_HDR_LEN: usize = 20;
+const IPV6_HDR_LEN: usize = 40;
+const TCP_HDR_LEN: usize = 20;
+const UDP_HDR_LEN: usize = 8;
+const ICMP_HDR_LEN: usize = 4;
+
+const IPPROTO_ICMP: u8 = 1; 
+const IPPROTO_TCP: u8 = 6; 
+const IPPROTO_UDP: u8 = 17; 
+const IPPROTO_HOPOPTS: u8 = 0;
+const IPPROTO_ROUTING: u8 = 43;
+const IPPROTO_FRAGMENT: u8 = 44;
+const IPPROTO_ESP: u8 = 50;
+const IPPROTO_AH: u8 = 51;
+const IPPROTO_ICMPV6: u8 = 58;
+const IPPROTO_NONE: u8 = 59;
+const IPPROTO_DSTOPTS: u8 = 60;
+
+const ICMP_ECHO_REPLY: u8 = 0; 
+const ICMP_DST_UNREACH: u8 = 3; 
+const ICMP_ECHO_REQUEST: u8 = 8; 
+const ICMP_NET_UNREACH: u8 = 0; 
+const ICMP_HOST_UNREACH: u8 = 1; 
+const ICMP_PROTO_UNREACH: u8 = 2; 
+const ICMP_PORT_UNREACH: u8 = 3; 
+const ICMP_FRAG_NEED: u8 = 4; 
+const ICMP_SRC_RT_FAIL: u8 = 5; 
+
+const ICMP6_DST_UNREACH: u8 = 1;
+const ICMP6_PACKET_TOO_BIG: u8 = 2;
+const ICMP6_TIME_EXCEEDED: u8 = 3;
+const ICMP6_PARAM_PROB: u8 = 4;
+const ICMP6_ECHO_REQUEST: u8 = 128;
+const ICMP6_ECHO_REPLY: u8 = 129;
+const ND_ROUTER_SOLICIT: u8 = 133;
+const ND_ROUTER_ADVERT: u8 = 134;
+const ND_NEIGHBOR_SOLICIT: u8 = 135;
+const ND_NEIGHBOR_ADVERT: u8 = 136;
+const ND_REDIRECT: u8 = 137;
+
+const ND_OPT_SOURCE_LINKADDR: u8 = 1;
+const ND_OPT_TARGET_LINKADDR: u8 = 2;
+const ND_OPT_PREFIX_INFORMATION: u8 = 3;
+const ND_OPT_REDIRECTED_HEADER: u8 = 4;
+const ND_OPT_MTU: u8 = 5;
+
+
+pub struct PacketInfo {
+    pub num: u32,
+    pub time: Timespec,
+    pub len: u32,
+    pub net_src: Option<String>,
+    pub net_dst: Option<String>,
+    pub src_port: Option<u16>,
+    pub dst_port: Option<u16>,
+    pub proto: Option<String>,
+    pub info: Option<String>,
+    pub desegment_len: Option<usize>,
+    // One's complement sum of the network addresses, for the transport
+    // pseudo-header checksums.
+    pub addr_sum: u32,
+    // Nodes added by Scheme dissectors through `add-field` and
+    // `add-subtree`, with the index of their parent. A node's index is the
+    // handle Scheme uses to add children to it.
+    app_nodes: Vec<(Option<usize>, FieldNode)>,
+    // Address and length of the bytes the running Scheme dissector was
+    // handed, so a bytevector it slices from them can be placed.
+    scheme_data: Option<(usize, usize)>,
+    // Errors raised by Scheme dissectors while dissecting this packet.
+    pub warnings: Vec<String>,
+    // What a dissector needs to hand off to the next one, so the chain
+    // only has to pass the pinfo along.
+    disct_tbl: Rc<RefCell<DissectorTable>>,
+    convs: Rc<RefCell<ConversationTable>>,
+}
+
+impl PacketInfo {
+    // Assembles the nodes added since `mark` into a tree and drops them
+    // from the list.
+    fn take_app_tree(&mut self, mark: usize) -> ProtoTree {
+        let mut tree = ProtoTree::new();
+        while self.app_nodes.len() > mark {
+            let (parent, node) = self.app_nodes.pop().unwrap();
+            match parent {
+                Some(p) if p >= mark && p < self.app_nodes.len() => self.app_nodes[p].1.children.insert(0, node),
+                _ => tree.nodes.insert(0, node),
+            }
+        }
+        tree
+    }
+}
+
+// The bytes handed to a dissector. `offset` is where they start in the
+// frame, so nodes can point back into the hex pane.
+pub struct Tvb<'a> {
+    data: &'a mut [u8],
+    offset: usize,
+}
+
+impl<'a> Tvb<'a> {
+    pub fn new(data: &'a mut [u8], offset: usize) -> Tvb<'a> {
+        Tvb { data: data, offset: offset }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn data(&mut self) -> &mut [u8] {
+        self.data
+    }
+
+    pub fn subset(&mut self, start: usize, end: usize) -> Tvb {
+        Tvb { data: &mut self.data[start..end], offset: self.offset + start }
+    }
+}
+
+pub trait Dissector {
+    // Shown in the Protocol column, e.g. "TCP".
+    fn name(&self) -> &str;
+    // Appends to `tree` and fills in pinfo; whatever comes next is found
+    // through the dissector tables.
+    fn dissect(&self, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree);
+}
+
+#[derive(Clone)]
+pub enum DissectorHandle {
+    Native(Rc<dyn Dissector>),
+    Scheme(SCM),
+}
+
+impl DissectorHandle {
+    pub fn call(&self, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+        match *self {
+            DissectorHandle::Native(ref d) => {
+                pinfo.proto = Some(d.name().to_string());
+                d.dissect(tvb, pinfo, tree);
+            },
+            DissectorHandle::Scheme(dsctr) => {
+                let mut app = call_scheme_dissector(dsctr, tvb.data(), pinfo);
+                app.shift_offsets(tvb.offset());
+                tree.append(app);
+            },
+        }
+    }
+}
+
+// A payload dissector tried when no port dissector matches. Higher
+// priorities run first; ties keep registration order.
+pub struct HeuristicDissector {
+    pub name: String,
+    pub table: String,
+    pub priority: i32,
+    pub enabled: bool,
+    dsctr: SCM,
+}
+
+const DISSECTOR_TABLES: [&str; 4] = ["ethertype", "ip.proto", "tcp.port", "udp.port"];
+
+pub struct DissectorTable {
+    tables: HashMap<&'static str, HashMap<u32, DissectorHandle>>,
+    heuristics: Vec<HeuristicDissector>,
+    // (table, name) of heuristics the user switched off; kept across
+    // reloads so re-registering does not turn them back on.
+    disabled_heuristics: HashSet<(String, String)>,
+}
+
+impl DissectorTable {
+    pub fn new() -> Self {
+        let mut tbl = DissectorTable {
+            tables: DISSECTOR_TABLES.iter().map(|&t| (t, HashMap::new())).collect(),
+            heuristics: Vec::new(),
+            disabled_heuristics: HashSet::new(),
+        };
+        tbl.register_builtins();
+        tbl
+    }
+
+    fn register_builtins(&mut self) {
+        self.register("ethertype", ETHERTYPE_IPV4 as u32, DissectorHandle::Native(Rc::new(Ipv4)));
+        self.register("ethertype", ETHERTYPE_IPV6 as u32, DissectorHandle::Native(Rc::new(Ipv6)));
+        self.register("ip.proto", IPPROTO_ICMP as u32, DissectorHandle::Native(Rc::new(Icmp)));
+        self.register("ip.proto", IPPROTO_TCP as u32, DissectorHandle::Native(Rc::new(Tcp)));
+        self.register("ip.proto", IPPROTO_UDP as u32, DissectorHandle::Native(Rc::new(Udp)));
+        self.register("ip.proto", IPPROTO_ICMPV6 as u32, DissectorHandle::Native(Rc::new(Icmpv6)));
+    }
+
+    // Drops every plugin registration so plugins can be loaded again; the
+    // built-in dissectors are put back.
+    pub fn clear(&mut self) {
+        for t in self.tables.values_mut() {
+            for (_, dsctr) in t.drain() {
+                if let DissectorHandle::Scheme(prc) = dsctr {
+                    unsafe { scm_gc_unprotect_object(prc); }
+                }
+            }
+        }
+        for h in self.heuristics.drain(..) {
+            unsafe { scm_gc_unprotect_object(h.dsctr); }
+        }
+        self.register_builtins();
+    }
+
+    // Replaces whatever was registered under `key`, built-ins included.
+    // Returns false if there is no table called `table`. Scheme procedures
+    // are kept alive while registered, since Guile cannot see the
+    // references held here.
+    pub fn register(&mut self, table: &str, key: u32, dsctr: DissectorHandle) -> bool {
+        match self.tables.get_mut(table) {
+            Some(t) => {
+                if let DissectorHandle::Scheme(prc) = dsctr {
+                    unsafe { scm_gc_protect_object(prc); }
+                }
+                if let Some(DissectorHandle::Scheme(old)) = t.insert(key, dsctr) {
+                    unsafe { scm_gc_unprotect_object(old); }
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn get(&self, table: &str, key: u32) -> Option<DissectorHandle> {
+        self.tables.get(table).and_then(|t| t.get(&key)).cloned()
+    }
+
+    fn enabled_heuristics(&self, table: &str) -> Vec<SCM> {
+        self.heuristics.iter()
+            .filter(|h| h.enabled && h.table == table)
+            .map(|h| h.dsctr)
+            .collect()
+    }
+
+    // Registering a name again replaces the earlier procedure but keeps
+    // whether the user had it enabled. The table keeps its procedures
+    // alive; Guile cannot see references held on the Rust side.
+    fn set_heuristic(&mut self, table: &str, name: &str, priority: i32, disct_proc: SCM) {
+        if let Some(i) = self.heuristics.iter().position(|h| h.table == table && h.name == name) {
+            let old = self.heuristics.remove(i);
+            unsafe { scm_gc_unprotect_object(old.dsctr); }
+        }
+        unsafe { scm_gc_protect_object(disct_proc); }
+        let enabled = !self.disabled_heuristics.contains(&(table.to_string(), name.to_string()));
+        let pos = self.heuristics.iter().position(|h| h.priority < priority).unwrap_or(self.heuristics.len());
+        self.heuristics.insert(pos, HeuristicDissector {
+            name: name.to_string(),
+            table: table.to_string(),
+            priority: priority,
+            enabled: enabled,
+            dsctr: disct_proc,
+        });
+    }
+
+    pub fn heuristics(&self) -> &[HeuristicDissector] {
+        &self.heuristics
+    }
+
+    pub fn set_heuristic_enabled(&mut self, table: &str, name: &str, enabled: bool) {
+        let key = (table.to_string(), name.to_string());
+        if enabled {
+            self.disabled_heuristics.remove(&key);
+        } else {
+            self.disabled_heuristics.insert(key);
+        }
+        for h in self.heuristics.iter_mut().filter(|h| h.table == table && h.name == name) {
+            h.enabled = enabled;
+        }
+    }
+}
+
+// Older plugins use the short names.
+fn table_name(name: &str) -> Option<&'static str> {
+    match name {
+        "ethertype" | "net" => Some("ethertype"),
+        "ip.proto" | "transport" => Some("ip.proto"),
+        "tcp.port" | "tcp" => Some("tcp.port"),
+        "udp.port" | "udp" => Some("udp.port"),
+        _ => None,
+    }
+}
+
+// The largest key each table can match: ports and ethertypes are 16
+// bits, IP protocol numbers 8.
+fn table_key_max(table: &str) -> u32 {
+    match table {
+        "ip.proto" => 0xff,
+        _ => 0xffff,
+    }
+}
+
+// A Scheme error to raise with `raise_error`. The names and format are
+// NUL-terminated and the arguments already Scheme values, so nothing
+// here needs dropping.
+struct SchemeError {
+    subr: &'static [u8],
+    fmt: &'static [u8],
+    args: SCM,
+}
+
+// Raises a Scheme error, which stops the plugin being loaded and is
+// reported with the plugin's other load errors. It longjmps straight
+// out of the caller, so must only be called once every Rust value there
+// that owns memory has been dropped.
+unsafe fn raise_error(err: SchemeError) {
+    scm_misc_error(err.subr.as_ptr() as *const c_char, err.fmt.as_ptr() as *const c_char, err.args);
+}
+
+unsafe fn scm_str(s: &str) -> SCM {
+    let s = CString::new(s).unwrap();
+    scm_from_locale_string(s.as_ptr())
+}
+
+pub(crate) extern "C" fn set_dissector(tbl: SCM, tbl_type: SCM, num: SCM, prc: SCM) -> SCM{
+    let err = unsafe {
+        let table = (scm_to_pointer(tbl) as *mut DissectorTable).as_mut().unwrap();
+
+        let name = table_name(&scm_string(scm_symbol_to_string(tbl_type)));
+        match name {
+            Some(name) if scm_is_unsigned_integer(num, 0, table_key_max(name) as _) != 0 => {
+                table.register(name, scm_to_uint32(num), DissectorHandle::Scheme(prc));
+                None
+            },
+            Some(name) => Some(SchemeError {
+                subr: b"set-dissector\0",
+                fmt: b"~A key ~S is out of range 0 to ~A\0",
+                args: scm_list_3(scm_str(name), num, scm_from_uint32(table_key_max(name))),
+            }),
+            None => Some(SchemeError {
+                subr: b"set-dissector\0",
+                fmt: b"no dissector table called ~A\0",
+                args: scm_list_1(tbl_type),
+            }),
+        }
+    };
+    if let Some(err) = err {
+        unsafe { raise_error(err); }
+    }
+    tbl
+}
+
+// Heuristics run on TCP and UDP payloads and take the same table names
+// as `set_dissector`'s port tables; they are kept under "tcp" and "udp".
+fn heuristic_table_name(name: &str) -> Option<&'static str> {
+    match table_name(name) {
+        Some("tcp.port") => Some("tcp"),
+        Some("udp.port") => Some("udp"),
+        _ => None,
+    }
+}
+
+pub(crate) extern "C" fn set_heuristic_dissector(tbl: SCM, tbl_type: SCM, name: SCM, priority: SCM, prc: SCM) -> SCM{
+    let err = unsafe {
+        let table = (scm_to_pointer(tbl) as *mut DissectorTable).as_mut().unwrap();
+        // Converted while no String is alive, as a bad priority raises.
+        let priority = scm_to_int32(priority);
+
+        let heur_table = heuristic_table_name(&scm_string(scm_symbol_to_string(tbl_type)));
+        match heur_table {
+            Some(heur_table) => {
+                table.set_heuristic(heur_table, &scm_string(name), priority, prc);
+                None
+            },
+            None => Some(SchemeError {
+                subr: b"set-heuristic-dissector\0",
+                fmt: b"no heuristic dissector table called ~A\0",
+                args: scm_list_1(tbl_type),
+            }),
+        }
+    };
+    if let Some(err) = err {
+        unsafe { raise_error(err); }
+    }
+    tbl
+}
+
+pub(crate) extern "C" fn set_proto(pinfo: SCM, proto: SCM) -> SCM{
+    {
+        unsafe {
+            let pinfo = scm_to_pointer(pinfo) as *mut PacketInfo;
+            let pinfo = pinfo.as_mut().unwrap();
+            let proto = CString::from_raw(scm_to_locale_string(proto)).into_string().unwrap();
+            pinfo.proto = Some(proto);
+        }
+    }
+    pinfo
+}
+
+pub(crate) extern "C" fn set_info(pinfo: SCM, info: SCM) -> SCM{
+    {
+        unsafe {
+            let pinfo = scm_to_pointer(pinfo) as *mut PacketInfo;
+            let pinfo = pinfo.as_mut().unwrap();
+            let info = CString::from_raw(scm_to_locale_string(info)).into_string().unwrap();
+            pinfo.info = Some(info);
+        }
+    }
+    pinfo
+}
+
+pub(crate) extern "C" fn set_desegment_len(pinfo: SCM, len: SCM) -> SCM{
+    {
+        unsafe {
+            let pinfo = scm_to_pointer(pinfo) as *mut PacketInfo;
+            let pinfo = pinfo.as_mut().unwrap();
+            pinfo.desegment_len = Some(scm_to_uint32(len) as usize);
+        }
+    }
+    pinfo
+}
+
+unsafe fn scm_string(s: SCM) -> String {
+    CString::from_raw(scm_to_locale_string(s)).into_string().unwrap()
+}
+
+// `parent` is #f for a top-level node; `offset` and `len` are #f for
+// generated fields.
+unsafe fn add_app_node(pinfo: SCM, parent: SCM, name: SCM, label: SCM, value: FieldValue, offset: SCM, len: SCM) -> SCM {
+    let pinfo = (scm_to_pointer(pinfo) as *mut PacketInfo).as_mut().unwrap();
+    let parent = if scm_is_integer(parent) != 0 { Some(scm_to_uint32(parent) as usize) } else { None };
+    let mut node = if scm_is_integer(offset) != 0 {
+        let len = if scm_is_integer(len) != 0 { scm_to_uint32(len) as usize } else { 0 };
+        FieldNode::new(&scm_string(name), &scm_string(label), value, scm_to_uint32(offset) as usize, len)
+    } else {
+        FieldNode::generated(&scm_string(name), &scm_string(label), value)
+    };
+    if node.typed == FieldValue::None {
+        node.value = String::new();
+    }
+    pinfo.app_nodes.push((parent, node));
+    scm_from_uint32((pinfo.app_nodes.len() - 1) as u32)
+}
+
+unsafe fn scm_field_value(value: SCM) -> FieldValue {
+    if scm_is_string(value) != 0 {
+        FieldValue::Str(scm_string(value))
+    } else if scm_is_integer(value) != 0 {
+        match scm_to_int64(value) {
+            v if v < 0 => FieldValue::Int(v),
+            v => FieldValue::UInt(v as u64),
+        }
+    } else {
+        FieldValue::None
+    }
+}
+
+pub(crate) extern "C" fn add_field(pinfo: SCM, parent: SCM, name: SCM, label: SCM, value: SCM, offset: SCM, len: SCM) -> SCM {
+    unsafe {
+        let value = scm_field_value(value);
+        add_app_node(pinfo, parent, name, label, value, offset, len)
+    }
+}
+
+pub(crate) extern "C" fn add_subtree(pinfo: SCM, parent: SCM, name: SCM, label: SCM, summary: SCM, offset: SCM, len: SCM) -> SCM {
+    unsafe {
+        let id = add_app_node(pinfo, parent, name, label, FieldValue::None, offset, len);
+        let p = (scm_to_pointer(pinfo) as *mut PacketInfo).as_mut().unwrap();
+        if scm_is_string(summary) != 0 {
+            p.app_nodes.last_mut().unwrap().1.value = scm_string(summary);
+        }
+        id
+    }
+}
+
+pub(crate) extern "C" fn set_src(pinfo: SCM, addr: SCM) -> SCM {
+    unsafe {
+        let p = (scm_to_pointer(pinfo) as *mut PacketInfo).as_mut().unwrap();
+        p.net_src = Some(scm_string(addr));
+    }
+    pinfo
+}
+
+pub(crate) extern "C" fn set_dst(pinfo: SCM, addr: SCM) -> SCM {
+    unsafe {
+        let p = (scm_to_pointer(pinfo) as *mut PacketInfo).as_mut().unwrap();
+        p.net_dst = Some(scm_string(addr));
+    }
+    pinfo
+}
+
+pub(crate) extern "C" fn set_ports(pinfo: SCM, src_port: SCM, dst_port: SCM) -> SCM {
+    unsafe {
+        let p = (scm_to_pointer(pinfo) as *mut PacketInfo).as_mut().unwrap();
+        p.src_port = Some(scm_to_uint16(src_port));
+        p.dst_port = Some(scm_to_uint16(dst_port));
+    }
+    pinfo
+}
+
+// Where the `data_len` bytes at `ptr` start within `outer`, if they are a
+// view into it.
+fn view_offset(outer: Option<(usize, usize)>, ptr: usize, data_len: usize) -> Option<usize> {
+    let (start, len) = outer?;
+    if ptr >= start && ptr + data_len <= start + len {
+        Some(ptr - start)
+    } else {
+        None
+    }
+}
+
+// Runs the dissector registered under `key` in `table` on `data`, which
+// starts `offset` bytes into what the calling Scheme dissector was
+// handed. Its nodes are added at the top level, after the caller's, with
+// offsets relative to the caller's bytes like the caller's own.
+fn call_next(pinfo: &mut PacketInfo, table: &str, key: u32, data: &mut [u8], offset: usize) -> bool {
+    let dsctr = match pinfo.disct_tbl.borrow().get(table, key) {
+        Some(d) => d,
+        None => return false,
+    };
+    let mut tree = ProtoTree::new();
+    dsctr.call(&mut Tvb::new(data, offset), pinfo, &mut tree);
+    for node in tree.nodes {
+        pinfo.app_nodes.push((None, node));
+    }
+    true
+}
+
+// Hands `bv` to the dissector registered under `key` in `tbl_type`.
+// `offset` is #f when Scheme left it out, in which case `bv` is placed by
+// where its storage lies in the caller's bytes. Only a view into those
+// bytes is dissected in place; anything else is copied first. Returns 1
+// if a dissector was found and 0 otherwise.
+pub(crate) extern "C" fn call_next_dissector(tbl_type: SCM, key: SCM, bv: SCM, pinfo: SCM, offset: SCM) -> SCM {
+    unsafe {
+        // Everything that can raise a Scheme error on bad arguments comes
+        // first: it longjmps out of here, past anything left to drop.
+        let p = (scm_to_pointer(pinfo) as *mut PacketInfo).as_mut().unwrap();
+        let key = scm_to_uint32(key);
+        let explicit_offset = if scm_is_integer(offset) != 0 { Some(scm_to_uint32(offset) as usize) } else { None };
+        let len = scm_c_bytevector_length(bv) as usize;
+        let ptr = scm_to_pointer(scm_bytevector_to_pointer(bv, scm_from_int32(0))) as *mut u8;
+        let outer_len = p.scheme_data.map_or(0, |(_, outer_len)| outer_len);
+        if let Some(offset) = explicit_offset {
+            if offset > outer_len || len > outer_len - offset {
+                raise_error(SchemeError {
+                    subr: b"call-next-dissector\0",
+                    fmt: b"offset ~A and length ~A run past the ~A bytes being dissected\0",
+                    args: scm_list_3(scm_from_uint32(offset as u32), scm_from_uint32(len as u32),
+                                     scm_from_uint32(outer_len as u32)),
+                });
+            }
+        }
+
+        let name = table_name(&scm_string(scm_symbol_to_string(tbl_type)));
+        let name = match name {
+            Some(name) => name,
+            None => return scm_from_int32(0),
+        };
+
+        let view = view_offset(p.scheme_data, ptr as usize, len);
+        let offset = explicit_offset.or(view).unwrap_or(0);
+        let found = if view.is_some() {
+            call_next(p, name, key, ::std::slice::from_raw_parts_mut(ptr, len), offset)
+        } else {
+            let mut copy = ::std::slice::from_raw_parts(ptr, len).to_vec();
+            call_next(p, name, key, &mut copy, offset)
+        };
+
+        scm_from_int32(found as i32)
+    }
+}
+
+fn to_bytevector(bytes: &mut [u8]) -> SCM {
+    let v_ptr = bytes.as_mut_ptr() as *mut c_void;
+    unsafe {
+        let scm_ptr = scm_from_pointer(v_ptr, None);
+        let cstr = CString::new("u8").unwrap();
+        scm_pointer_to_bytevector(scm_ptr,
+                                  scm_from_int32(bytes.len() as i32),
+                                  scm_from_int32(0),
+                                  scm_from_utf8_symbol(cstr.as_ptr()))
+    }
+}
+
+// Any error raised by the dissector is caught and returned as its
+// printed message.
+fn call_scheme(dsctr: SCM, data: &mut [u8], pinfo: &mut PacketInfo) -> Result<String, String> {
+    let write_proc = unsafe { scm_variable_ref(scm_c_lookup(CString::new("write").unwrap().as_ptr())) };
+    let pinfo_ptr = unsafe { scm_from_pointer(pinfo as *mut _ as *mut c_void, None) };
+    let outer = ::std::mem::replace(&mut pinfo.scheme_data, Some((data.as_ptr() as usize, data.len())));
+    let bv = to_bytevector(data);
+    let res = catch_all(|| unsafe {
+        scm_object_to_string(scm_call_2(dsctr, bv, pinfo_ptr), write_proc)
+    });
+    pinfo.scheme_data = outer;
+    Ok(unsafe { scm_string(res?) })
+}
+
+// On error the nodes added before the failure are kept and followed by
+// a "Dissector error" node.
+fn call_scheme_dissector(dsctr: SCM, data: &mut [u8], pinfo: &mut PacketInfo) -> ProtoTree {
+    let mark = pinfo.app_nodes.len();
+    match call_scheme(dsctr, data, pinfo) {
+        Ok(res) => scheme_tree(&res, pinfo, mark),
+        Err(e) => {
+            let mut tree = pinfo.take_app_tree(mark);
+            tree.add(FieldNode::generated("_ws.dissector_error", "Dissector error", FieldValue::Str(e.clone())));
+            pinfo.warnings.push(format!("Dissector error: {}", e));
+            tree
+        },
+    }
+}
+
+// A heuristic dissector returns #f to decline the payload; anything it
+// set on pinfo before declining is rolled back. One that raises an error
+// is logged and treated as declining, so the others still get a turn.
+fn call_heuristic_dissector(dsctr: SCM, data: &mut [u8], pinfo: &mut PacketInfo) -> Option<ProtoTree> {
+    let (proto, info) = (pinfo.proto.clone(), pinfo.info.clone());
+    let (net_src, net_dst) = (pinfo.net_src.clone(), pinfo.net_dst.clone());
+    let (src_port, dst_port) = (pinfo.src_port, pinfo.dst_port);
+    let mark = pinfo.app_nodes.len();
+    let claimed = match call_scheme(dsctr, data, pinfo) {
+        Ok(res) => if res == "#f" { None } else { Some(res) },
+        Err(e) => {
+            pinfo.warnings.push(format!("Heuristic dissector error: {}", e));
+            None
+        },
+    };
+    match claimed {
+        Some(res) => Some(scheme_tree(&res, pinfo, mark)),
+        None => {
+            pinfo.proto = proto;
+            pinfo.info = info;
+            pinfo.net_src = net_src;
+            pinfo.net_dst = net_dst;
+            pinfo.src_port = src_port;
+            pinfo.dst_port = dst_port;
+            pinfo.desegment_len = None;
+            pinfo.app_nodes.truncate(mark);
+            None
+        },
+    }
+}
+
+// Dissectors that built their tree with `add-field`/`add-subtree` have
+// it taken from pinfo; older ones return it as an s-expression. One that
+// returns an s-expression and also calls `call-next-dissector` gets both,
+// its own nodes first.
+fn scheme_tree(res: &str, pinfo: &mut PacketInfo, mark: usize) -> ProtoTree {
+    let app = pinfo.take_app_tree(mark);
+    let returned = if app.nodes.is_empty() || (res.starts_with('(') && res != "()") {
+        ProtoTree::from_sexp(res)
+    } else {
+        Ok(ProtoTree::new())
+    };
+    let mut tree = match returned {
+        Ok(tree) => tree,
+        Err(e) => {
+            let mut tree = ProtoTree::new();
+            tree.add(FieldNode::text("** Parse Error **", e));
+            tree
+        },
+    };
+    tree.nodes.extend(app.nodes);
+    if let (Some(node), Some(proto)) = (tree.nodes.first_mut(), pinfo.proto.as_ref()) {
+        if node.name.is_empty() { node.name = proto.to_lowercase(); }
+    }
+    name_fields(&mut tree.nodes, "");
+    tree
+}
+
+// S-expression nodes have a label but no name; each is named after its
+// parent and its label ("Opcode" under "arp" becomes "arp.opcode") so
+// display filters can reach it.
+fn name_fields(nodes: &mut [FieldNode], parent: &str) {
+    for node in nodes {
+        if node.name.is_empty() {
+            let label: String = node.label.chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+                .collect();
+            node.name = if parent.is_empty() { label } else { format!("{}.{}", parent, label) };
+        }
+        name_fields(&mut node.children, &node.name);
+    }
+}
+
+fn malformed_node(proto: &str, offset: usize, need: usize, have: usize) -> FieldNode {
+    FieldNode::new("_ws.malformed", "Malformed/Truncated packet", FieldValue::None, offset, have)
+        .display(format!("{} header needs {} bytes but only {} remain at offset {}",
+                         proto, need, have, offset))
+}
+
+fn malformed(proto: &str, offset: usize, need: usize, data: &[u8], pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+    let node = malformed_node(proto, offset, need, data.len());
+    let msg = node.value.clone();
+    malformed_msg(proto, offset, data.len(), msg, pinfo, tree)
+}
+
+fn malformed_msg(proto: &str, offset: usize, len: usize, msg: String, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+    tree.add(FieldNode::new("_ws.malformed", "Malformed/Truncated packet", FieldValue::None, offset, len)
+             .display(msg));
+    pinfo.proto = Some(proto.to_string());
+    pinfo.info = Some(format!("[Malformed Packet: {}]", proto));
+}
+
+fn port_fields(node: &mut FieldNode, proto: &str, offset: usize, src_port: u16, dst_port: u16) {
+    node.add(FieldNode::new(&format!("{}.srcport", proto), "Source Port",
+                            FieldValue::UInt(src_port as u64), offset, 2));
+    node.add(FieldNode::new(&format!("{}.dstport", proto), "Destination Port",
+                            FieldValue::UInt(dst_port as u64), offset + 2, 2));
+    node.add(FieldNode::new(&format!("{}.port", proto), "Source or Destination Port",
+                            FieldValue::UInt(src_port as u64), offset, 2).hide());
+    node.add(FieldNode::new(&format!("{}.port", proto), "Source or Destination Port",
+                            FieldValue::UInt(dst_port as u64), offset + 2, 2).hide());
+}
+
+const TCPOPT_EOL: u8 = 0;
+const TCPOPT_NOP: u8 = 1;
+const TCPOPT_MSS: u8 = 2;
+const TCPOPT_WSCALE: u8 = 3;
+const TCPOPT_SACK_PERM: u8 = 4;
+const TCPOPT_SACK: u8 = 5;
+const TCPOPT_TIMESTAMP: u8 = 8;
+
+const TCP_FLAGS: [(u16, &'static str, &'static str); 9] = [
+    (0x100, "ns", "Nonce"),
+    (0x080, "cwr", "Congestion Window Reduced (CWR)"),
+    (0x040, "ece", "ECN-Echo"),
+    (0x020, "urg", "Urgent"),
+    (0x010, "ack", "Acknowledgment"),
+    (0x008, "push", "Push"),
+    (0x004, "reset", "Reset"),
+    (0x002, "syn", "Syn"),
+    (0x001, "fin", "Fin"),
+];
+
+fn tcp_flags_str(flags: u16) -> String {
+    let names: Vec<&str> = [(0x002, "SYN"), (0x001, "FIN"), (0x004, "RST"), (0x008, "PSH"),
+                            (0x010, "ACK"), (0x020, "URG"), (0x040, "ECE"), (0x080, "CWR"), (0x100, "NS")]
+        .iter()
+        .filter(|&&(bit, _)| flags & bit != 0)
+        .map(|&(_, n)| n)
+        .collect();
+    names.join(", ")
+}
+
+fn tcpopt_name(kind: u8) -> (&'static str, &'static str) {
+    match kind {
+        TCPOPT_EOL => ("tcp.options.eol", "End of Option List (EOL)"),
+        TCPOPT_NOP => ("tcp.options.nop", "No-Operation (NOP)"),
+        TCPOPT_MSS => ("tcp.options.mss", "Maximum segment size"),
+        TCPOPT_WSCALE => ("tcp.options.wscale", "Window scale"),
+        TCPOPT_SACK_PERM => ("tcp.options.sack_perm", "SACK permitted"),
+        TCPOPT_SACK => ("tcp.options.sack", "SACK"),
+        TCPOPT_TIMESTAMP => ("tcp.options.timestamp", "Timestamps"),
+        _ => ("tcp.options.unknown", "Unknown"),
+    }
+}
+
+// Returns the options node and the summary Wireshark appends to the Info
+// column for SYN segments (MSS, WS, SACK_PERM, TSval/TSecr).
+fn dissect_tcp_options(data: &[u8], offset: usize) -> (FieldNode, Vec<String>) {
+    let mut opts = FieldNode::new("tcp.options", "Options", FieldValue::Bytes(data.to_vec()), offset, data.len())
+        .display(format!("({} bytes)", data.len()));
+    let mut summary = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let kind = data[i];
+        let opt_off = offset + i;
+        let (name, label) = tcpopt_name(kind);
+        if kind == TCPOPT_EOL || kind == TCPOPT_NOP {
+            opts.add(FieldNode::new(name, label, FieldValue::UInt(kind as u64), opt_off, 1)
+                     .display(String::new()));
+            if kind == TCPOPT_EOL { break; }
+            i += 1;
+            continue;
+        }
+        let len = match data.get(i + 1) {
+            Some(&l) if l >= 2 && i + l as usize <= data.len() => l as usize,
+            _ => {
+                opts.add(malformed_node("TCP option", opt_off, 2, data.len() - i));
+                break;
+            },
+        };
+        let val = &data[i + 2..i + len];
+        let mut opt = FieldNode::new(name, label, FieldValue::Bytes(val.to_vec()), opt_off, len)
+            .display(String::new());
+        opt.add(FieldNode::new("tcp.option_kind", "Kind", FieldValue::UInt(kind as u64), opt_off, 1)
+                .display(format!("{} ({})", label, kind)));
+        opt.add(FieldNode::new("tcp.option_len", "Length", FieldValue::UInt(len as u64), opt_off + 1, 1));
+        match kind {
+            TCPOPT_MSS if len == 4 => {
+                let mss = BigEndian::read_u16(val);
+                opt.value = format!("{} bytes", mss);
+                opt.add(FieldNode::new("tcp.options.mss_val", "MSS Value", FieldValue::UInt(mss as u64), opt_off + 2, 2));
+                summary.push(format!("MSS={}", mss));
+            },
+            TCPOPT_WSCALE if len == 3 => {
+                let shift = val[0];
+                let mult = 1u32 << ::std::cmp::min(shift, 14);
+                opt.value = format!("{} (multiply by {})", shift, mult);
+                opt.add(FieldNode::new("tcp.options.wscale.shift", "Shift count", FieldValue::UInt(shift as u64),
+                                       opt_off + 2, 1));
+                opt.add(FieldNode::generated("tcp.options.wscale.multiplier", "Multiplier", FieldValue::UInt(mult as u64)));
+                summary.push(format!("WS={}", mult));
+            },
+            TCPOPT_SACK_PERM if len == 2 => {
+                summary.push(String::from("SACK_PERM"));
+            },
+            TCPOPT_SACK if (len - 2) % 8 == 0 => {
+                let mut blocks = Vec::new();
+                for (n, b) in val.chunks(8).enumerate() {
+                    let le = BigEndian::read_u32(&b[0..4]);
+                    let re = BigEndian::read_u32(&b[4..8]);
+                    let b_off = opt_off + 2 + n * 8;
+                    opt.add(FieldNode::new("tcp.options.sack_le", "left edge", FieldValue::UInt(le as u64), b_off, 4));
+                    opt.add(FieldNode::new("tcp.options.sack_re", "right edge", FieldValue::UInt(re as u64), b_off + 4, 4));
+                    blocks.push(format!("{}-{}", le, re));
+                }
+                opt.value = blocks.join(" ");
+            },
+            TCPOPT_TIMESTAMP if len == 10 => {
+                let tsval = BigEndian::read_u32(&val[0..4]);
+                let tsecr = BigEndian::read_u32(&val[4..8]);
+                opt.value = format!("TSval {}, TSecr {}", tsval, tsecr);
+                opt.add(FieldNode::new("tcp.options.timestamp.tsval", "Timestamp value", FieldValue::UInt(tsval as u64),
+                                       opt_off + 2, 4));
+                opt.add(FieldNode::new("tcp.options.timestamp.tsecr", "Timestamp echo reply",
+                                       FieldValue::UInt(tsecr as u64), opt_off + 6, 4));
+                summary.push(format!("TSval={} TSecr={}", tsval, tsecr));
+            },
+            TCPOPT_MSS | TCPOPT_WSCALE | TCPOPT_SACK_PERM | TCPOPT_SACK | TCPOPT_TIMESTAMP => {
+                opt.add(FieldNode::new("_ws.malformed", "Malformed/Truncated packet", FieldValue::None, opt_off, len)
+                        .display(format!("{} option has invalid length {}", label, len)));
+            },
+            _ => {
+                opt.value = FieldValue::Bytes(val.to_vec()).to_string();
+            },
+        }
+        opts.add(opt);
+        i += len;
+    }
+    (opts, summary)
+}
+
+// The lower port is usually the well-known one, so it is tried first.
+fn port_dissector(tbl: &DissectorTable, table: &str, src_port: u16, dst_port: u16) -> Option<DissectorHandle> {
+    let (low, high) = if src_port <= dst_port { (src_port, dst_port) } else { (dst_port, src_port) };
+    tbl.get(table, low as u32).or_else(|| tbl.get(table, high as u32))
+}
+
+// Heuristics are only consulted when neither port has a dissector. The
+// tree's offsets are relative to `data`.
+fn dissect_app_payload(dsctr: Option<DissectorHandle>, heuristics: &[SCM], data: &mut [u8], pinfo: &mut PacketInfo) -> Option<ProtoTree> {
+    if let Some(dsctr) = dsctr {
+        let mut tree = ProtoTree::new();
+        dsctr.call(&mut Tvb::new(data, 0), pinfo, &mut tree);
+        return Some(tree);
+    }
+    for &heur in heuristics {
+        if let Some(tree) = call_heuristic_dissector(heur, data, pinfo) {
+            return Some(tree);
+        }
+    }
+    None
+}
+
+// Returns the application tree, if a dissector claimed the payload, and
+// how many more bytes it asked for via `set-desegment-len`.
+fn dissect_tcp_payload(data: &mut [u8], src_port: u16, dst_port: u16, pinfo: &mut PacketInfo) -> (Option<ProtoTree>, Option<usize>) {
+    let (dsctr, heuristics) = {
+        let tbl = pinfo.disct_tbl.borrow();
+        (port_dissector(&tbl, "tcp.port", src_port, dst_port), tbl.enabled_heuristics("tcp"))
+    };
+    pinfo.desegment_len = None;
+    let tree = dissect_app_payload(dsctr, &heuristics, data, pinfo);
+    (tree, pinfo.desegment_len.take())
+}
+
+fn reassembled_node(pdu: &TcpPdu) -> FieldNode {
+    let frames: Vec<String> = pdu.frames.iter().map(|&(n, len)| format!("#{}({})", n, len)).collect();
+    let mut node = FieldNode::text(&format!("[{} Reassembled TCP Segments ({} bytes)", pdu.frames.len(), pdu.data.len()),
+                                   format!("{}]", frames.join(", ")));
+    let mut start = 0;
+    for &(n, len) in &pdu.frames {
+        node.add(FieldNode::generated("tcp.segment", "Frame", FieldValue::UInt(n as u64))
+                 .display(format!("{}, payload: {}-{} ({} bytes)", n, start, start + len - 1, len)));
+        start += len;
+    }
+    node.add(FieldNode::generated("tcp.reassembled.length", "Reassembled TCP length",
+                                  FieldValue::UInt(pdu.data.len() as u64)));
+    node
+}
+
+struct Tcp;
+
+impl Dissector for Tcp {
+    fn name(&self) -> &str { "TCP" }
+    fn dissect(&self, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+        dissect_tcp(tvb, pinfo, tree)
+    }
+}
+
+fn dissect_tcp(tvb: &mut Tvb, pinfo: &mut PacketInfo, out: &mut ProtoTree) {
+    let offset = tvb.offset();
+    let addr_sum = pinfo.addr_sum;
+    let convs = pinfo.convs.clone();
+    let data = tvb.data();
+    if data.len() < TCP_HDR_LEN {
+        return malformed("TCP", offset, TCP_HDR_LEN, data, pinfo, out);
+    }
+
+    let src_port;
+    let dst_port;
+    let mut rdr = Cursor::new(&data[0..4]);
+    src_port = rdr.read_u16::<BigEndian>().unwrap();
+    dst_port = rdr.read_u16::<BigEndian>().unwrap();
+
+    let doff = ((data[12] >> 4) as usize) * 4;
+    if doff < TCP_HDR_LEN {
+        return malformed_msg("TCP", offset, TCP_HDR_LEN,
+                             format!("TCP header length {} is less than {}", doff, TCP_HDR_LEN),
+                             pinfo, out);
+    }
+    if data.len() < doff {
+        return malformed("TCP", offset, doff, data, pinfo, out);
+    }
+
+    let seq = BigEndian::read_u32(&data[4..8]);
+    let ack = BigEndian::read_u32(&data[8..12]);
+    let flags = BigEndian::read_u16(&data[12..14]) & 0x01ff;
+    let win = BigEndian::read_u16(&data[14..16]);
+    let cksum = BigEndian::read_u16(&data[16..18]);
+    let urg = BigEndian::read_u16(&data[18..20]);
+    // The slice ends where the IP header says the datagram ends, so
+    // whatever follows the TCP header is the segment payload.
+    let seg_len = data.len() - doff;
+    let pseudo = addr_sum + (data.len() as u32 >> 16) + (data.len() as u32 & 0xffff) + IPPROTO_TCP as u32;
+    let calc_cksum = inet_checksum(ones_sum(&data[18..], ones_sum(&data[..16], pseudo)));
+    let flags_str = tcp_flags_str(flags);
+
+    // Whatever carried the segment may have set no network addresses (a
+    // Scheme dissector handing on a payload, say); the conversation is
+    // then told apart by its ports alone.
+    let src = pinfo.net_src.clone().unwrap_or_default();
+    let dst = pinfo.net_dst.clone().unwrap_or_default();
+    let seg = TcpSegment { frame: pinfo.num, seq: seq, ack: ack, flags: flags, win: win, len: seg_len as u32 };
+    let a = convs.borrow_mut().tcp_segment(&src, src_port, &dst, dst_port, &seg);
+    let rel_seq = a.rel_seq;
+    let rel_ack = a.rel_ack.unwrap_or(ack);
+
+    let mut tree = ProtoTree::new();
+    let opt_summary;
+    {
+        let tcp = tree.add(FieldNode::proto("tcp", "Transmission Control Protocol",
+                                            format!("Src Port: {}, Dst Port: {}, Seq: {}, Ack: {}, Len: {}",
+                                                    src_port, dst_port, rel_seq, rel_ack, seg_len),
+                                            offset, doff));
+        port_fields(tcp, "tcp", offset, src_port, dst_port);
+        tcp.add(FieldNode::generated("tcp.stream", "Stream index", FieldValue::UInt(a.stream as u64)));
+        tcp.add(FieldNode::generated("tcp.len", "TCP Segment Len", FieldValue::UInt(seg_len as u64)));
+        tcp.add(FieldNode::new("tcp.seq", "Sequence number", FieldValue::UInt(a.rel_seq as u64), offset + 4, 4)
+                .display(format!("{}    (relative sequence number)", a.rel_seq)));
+        tcp.add(FieldNode::new("tcp.seq_raw", "Sequence number (raw)", FieldValue::UInt(seq as u64),
+                               offset + 4, 4));
+        if let Some(next) = a.next_seq {
+            tcp.add(FieldNode::generated("tcp.nxtseq", "Next sequence number", FieldValue::UInt(next as u64))
+                    .display(format!("{}    (relative sequence number)", next)));
+        }
+        tcp.add(FieldNode::new("tcp.ack", "Acknowledgment number", FieldValue::UInt(rel_ack as u64), offset + 8, 4)
+                .display(match a.rel_ack {
+                    Some(rel) => format!("{}    (relative ack number)", rel),
+                    None => ack.to_string(),
+                }));
+        tcp.add(FieldNode::new("tcp.ack_raw", "Acknowledgment number (raw)", FieldValue::UInt(ack as u64),
+                               offset + 8, 4));
+        tcp.add(FieldNode::new("tcp.hdr_len", "Header Length", FieldValue::UInt(doff as u64), offset + 12, 1)
+                .display(format!("{} bytes ({})", doff, doff / 4)));
+        {
+            let f = tcp.add(FieldNode::new("tcp.flags", "Flags", FieldValue::UInt(flags as u64), offset + 12, 2)
+                            .display(format!("0x{:03x} ({})", flags, flags_str)));
+            for &(bit, name, label) in TCP_FLAGS.iter() {
+                f.add(flag_node(&format!("tcp.flags.{}", name), label, flags & bit != 0,
+                                offset + if bit == 0x100 { 12 } else { 13 }));
+            }
+        }
+        tcp.add(FieldNode::new("tcp.window_size_value", "Window size value", FieldValue::UInt(win as u64),
+                               offset + 14, 2));
+        tcp.add(checksum_node("tcp.checksum", "Checksum", cksum, calc_cksum, offset + 16));
+        tcp.add(FieldNode::new("tcp.urgent_pointer", "Urgent pointer", FieldValue::UInt(urg as u64), offset + 18, 2));
+        if doff > TCP_HDR_LEN {
+            let (opts, summary) = dissect_tcp_options(&data[TCP_HDR_LEN..doff], offset + TCP_HDR_LEN);
+            tcp.add(opts);
+            opt_summary = summary;
+        } else {
+            opt_summary = Vec::new();
+        }
+        if !a.experts.is_empty() {
+            let an = tcp.add(FieldNode::text("[SEQ/ACK analysis]", String::new()));
+            for e in &a.experts {
+                an.add(FieldNode::generated(e.field(), &e.label(), FieldValue::None).display(String::new()));
+            }
+        }
+    }
+    if seg_len > 0 {
+        tree.add(FieldNode::new("tcp.payload", "TCP payload", FieldValue::Bytes(data[doff..].to_vec()),
+                                offset + doff, seg_len)
+                 .display(format!("{} bytes", seg_len)));
+    }
+
+    let mut tcp_val = String::new();
+    for e in &a.experts {
+        tcp_val.push_str(&e.info());
+        tcp_val.push(' ');
+    }
+    tcp_val.push_str(&format!("{} -> {} [{}] Seq={}", src_port, dst_port, flags_str, rel_seq));
+    if flags & 0x010 != 0 {
+        tcp_val.push_str(&format!(" Ack={}", rel_ack));
+    }
+    tcp_val.push_str(&format!(" Win={} Len={}", win, seg_len));
+    if flags & 0x020 != 0 {
+        tcp_val.push_str(&format!(" Urg={}", urg));
+    }
+    for s in &opt_summary {
+        tcp_val.push(' ');
+        tcp_val.push_str(s);
+    }
+
+    pinfo.info = Some(tcp_val.clone());
+    pinfo.src_port = Some(src_port);
+    pinfo.dst_port = Some(dst_port);
+
+    // Retransmitted and out-of-order data would corrupt a PDU being
+    // reassembled, so only in-order segments reach the application layer.
+    let in_order = !a.experts.iter().any(|e| *e == TcpExpert::Retransmission || *e == TcpExpert::OutOfOrder);
+    if seg_len > 0 && in_order {
+        let seg_end = seq.wrapping_add(seg_len as u32);
+        let pending = convs.borrow_mut().take_tcp_pdu(&src, src_port, &dst, dst_port, seq);
+        let (app, more, mut pdu) = match pending {
+            Some(mut pdu) => {
+                pdu.data.extend_from_slice(&data[doff..]);
+                pdu.frames.push((pinfo.num, seg_len));
+                pdu.next_seq = seg_end;
+                if pdu.data.len() < pdu.need {
+                    let short = pdu.need - pdu.data.len();
+                    (None, Some(short), pdu)
+                } else {
+                    let (app, more) = dissect_tcp_payload(&mut pdu.data, src_port, dst_port, pinfo);
+                    (app, more, pdu)
+                }
+            },
+            None => {
+                let (app, more) = dissect_tcp_payload(&mut data[doff..], src_port, dst_port, pinfo);
+                let pdu = TcpPdu {
+                    next_seq: seg_end,
+                    need: seg_len,
+                    data: data[doff..].to_vec(),
+                    frames: vec![(pinfo.num, seg_len)],
+                };
+                (app, more, pdu)
+            },
+        };
+
+        match (app, more) {
+            (_, Some(n)) => {
+                pdu.need = pdu.data.len() + n;
+                convs.borrow_mut().keep_tcp_pdu(&src, src_port, &dst, dst_port, pdu);
+                tree.add(FieldNode::text("[TCP segment of a reassembled PDU]", String::new()));
+                pinfo.proto = Some(String::from("TCP"));
+                pinfo.info = Some(format!("{} [TCP segment of a reassembled PDU]", tcp_val));
+            },
+            (Some(mut app), None) => {
+                if pdu.frames.len() > 1 {
+                    tree.add(reassembled_node(&pdu));
+                    app.clear_offsets();
+                } else {
+                    app.shift_offsets(offset + doff);
+                }
+                tree.append(app);
+            },
+            (None, None) => {},
+        }
+    }
+
+    out.append(tree);
+}
+
+struct Udp;
+
+impl Dissector for Udp {
+    fn name(&self) -> &str { "UDP" }
+    fn dissect(&self, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+        dissect_udp(tvb, pinfo, tree)
+    }
+}
+
+fn dissect_udp(tvb: &mut Tvb, pinfo: &mut PacketInfo, out: &mut ProtoTree) {
+    let offset = tvb.offset();
+    let convs = pinfo.convs.clone();
+    let data = tvb.data();
+    if data.len() < UDP_HDR_LEN {
+        return malformed("UDP", offset, UDP_HDR_LEN, data, pinfo, out);
+    }
+
+    let src_port;
+    let dst_port;
+    let mut rdr = Cursor::new(&data[0..4]);
+    src_port = rdr.read_u16::<BigEndian>().unwrap();
+    dst_port = rdr.read_u16::<BigEndian>().unwrap();
+    let udp_val = format!("UDP {} -> {}", src_port, dst_port);
+
+    let stream = match (pinfo.net_src.as_ref(), pinfo.net_dst.as_ref()) {
+        (Some(src), Some(dst)) => Some(convs.borrow_mut().udp_stream(src, src_port, dst, dst_port)),
+        _ => None,
+    };
+
+    let mut tree = ProtoTree::new();
+    {
+        let udp = tree.add(FieldNode::proto("udp", "User Datagram Protocol",
+                                            format!("Src Port: {}, Dst Port: {}", src_port, dst_port),
+                                            offset, UDP_HDR_LEN));
+        port_fields(udp, "udp", offset, src_port, dst_port);
+        if let Some(stream) = stream {
+            udp.add(FieldNode::generated("udp.stream", "Stream index", FieldValue::UInt(stream as u64)));
+        }
+    }
+    if data.len() > UDP_HDR_LEN {
+        tree.add(FieldNode::new("udp.payload", "UDP payload", FieldValue::Bytes(data[UDP_HDR_LEN..].to_vec()),
+                                offset + UDP_HDR_LEN, data.len() - UDP_HDR_LEN)
+                 .display(format!("{} bytes", data.len() - UDP_HDR_LEN)));
+    }
+
+    pinfo.info = Some(udp_val);
+    pinfo.src_port = Some(src_port);
+    pinfo.dst_port = Some(dst_port);
+
+    if data.len() > UDP_HDR_LEN {
+        let (dsctr, heuristics) = {
+            let tbl = pinfo.disct_tbl.borrow();
+            (port_dissector(&tbl, "udp.port", src_port, dst_port), tbl.enabled_heuristics("udp"))
+        };
+        if let Some(mut app) = dissect_app_payload(dsctr, &heuristics, &mut data[UDP_HDR_LEN..], pinfo) {
+            app.shift_offsets(offset + UDP_HDR_LEN);
+            tree.append(app);
+        }
+    }
+
+    out.append(tree);
+}
+
+fn icmp_type_and_code(typ: u8, cod: u8) -> (String, String) {
+    match typ {
+        ICMP_ECHO_REPLY => (String::from("Echo Reply(0)"), cod.to_string()),
+        ICMP_DST_UNREACH => {
+            let t = String::from("Destination Unreachable(3)");
+            match cod {
+                ICMP_NET_UNREACH => (t, String::from("net unreachable(0)")),
+                ICMP_HOST_UNREACH => (t, String::from("host unreachable(1)")),
+                ICMP_PROTO_UNREACH => (t, String::from("protocol unreachable(2)")),
+                ICMP_PORT_UNREACH => (t, String::from("port unreachable(3)")),
+                ICMP_FRAG_NEED => (t, String::from("fragmentation needed and DF set(4)")),
+                ICMP_SRC_RT_FAIL => (t, String::from("source route failed(5)")),
+                _ => (t, String::from("unknown code")),
+            }
+        },
+        ICMP_ECHO_REQUEST => (String::from("Echo Request(8)"), cod.to_string()),
+        _ => (String::from("Not implemented yet"), String::from("Sorry")),
+    }
+}
+
+struct Icmp;
+
+impl Dissector for Icmp {
+    fn name(&self) -> &str { "ICMP" }
+    fn dissect(&self, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+        dissect_icmp(tvb, pinfo, tree)
+    }
+}
+
+fn dissect_icmp(tvb: &mut Tvb, pinfo: &mut PacketInfo, out: &mut ProtoTree) {
+    let offset = tvb.offset();
+    let data = tvb.data();
+    if data.len() < ICMP_HDR_LEN {
+        return malformed("ICMP", offset, ICMP_HDR_LEN, data, pinfo, out);
+    }
+
+    let (type_val, code_val) = icmp_type_and_code(data[0], data[1]);
+    let icmp_val = format!("ICMP {}, {}", type_val, code_val);
+
+    let mut tree = ProtoTree::new();
+    {
+        let icmp = tree.add(FieldNode::proto("icmp", "Internet Control Message Protocol",
+                                             String::from("icmp"), offset, data.len()));
+        icmp.add(FieldNode::new("icmp.type", "Type", FieldValue::UInt(data[0] as u64), offset, 1)
+                 .display(type_val));
+        icmp.add(FieldNode::new("icmp.code", "Code", FieldValue::UInt(data[1] as u64), offset + 1, 1)
+                 .display(code_val));
+        icmp.add(FieldNode::new("data", "Data", FieldValue::Bytes(data[ICMP_HDR_LEN..].to_vec()),
+                                offset + ICMP_HDR_LEN, data.len() - ICMP_HDR_LEN)
+                 .display(String::from("...")));
+    }
+
+    pinfo.info = Some(icmp_val);
+
+    out.append(tree);
+}
+
+fn ipv4_addr(bytes: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])
+}
+
+const IPOPT_EOL: u8 = 0;
+const IPOPT_NOP: u8 = 1;
+const IPOPT_RR: u8 = 7;
+const IPOPT_TS: u8 = 68;
+const IPOPT_SEC: u8 = 130;
+const IPOPT_LSRR: u8 = 131;
+const IPOPT_SID: u8 = 136;
+const IPOPT_SSRR: u8 = 137;
+const IPOPT_RA: u8 = 148;
+
+fn ipopt_name(typ: u8) -> &'static str {
+    match typ {
+        IPOPT_EOL => "End of Options List (EOL)",
+        IPOPT_NOP => "No-Operation (NOP)",
+        IPOPT_RR => "Record Route",
+        IPOPT_TS => "Time Stamp",
+        IPOPT_SEC => "Security",
+        IPOPT_LSRR => "Loose Source Route",
+        IPOPT_SID => "Stream ID",
+        IPOPT_SSRR => "Strict Source Route",
+        IPOPT_RA => "Router Alert",
+        _ => "Unknown",
+    }
+}
+
+fn ipopt_type_node(typ: u8, offset: usize) -> FieldNode {
+    let mut t = FieldNode::new("ip.opt.type", "Type", FieldValue::UInt(typ as u64), offset, 1);
+    t.add(FieldNode::new("ip.opt.type.copy", "Copy on fragmentation",
+                         FieldValue::Bool(typ & 0x80 != 0), offset, 1)
+          .display(String::from(if typ & 0x80 != 0 { "Yes" } else { "No" })));
+    t.add(FieldNode::new("ip.opt.type.class", "Class", FieldValue::UInt(((typ >> 5) & 0x03) as u64), offset, 1));
+    t.add(FieldNode::new("ip.opt.type.number", "Number", FieldValue::UInt((typ & 0x1f) as u64), offset, 1));
+    t
+}
+
+fn ipopt_route(opt: &mut FieldNode, data: &[u8], offset: usize) {
+    opt.add(FieldNode::new("ip.opt.ptr", "Pointer", FieldValue::UInt(data[2] as u64), offset + 2, 1));
+    for (i, hop) in data[3..].chunks(4).enumerate() {
+        if hop.len() < 4 { break; }
+        let hop_off = offset + 3 + i * 4;
+        let mut node = FieldNode::new("ip.rec_rt", "Recorded Route", FieldValue::Ipv4(ipv4_addr(hop)), hop_off, 4);
+        if 3 + i * 4 + 1 == data[2] as usize {
+            node.value = format!("{} <- (next)", node.value);
+        }
+        opt.add(node);
+    }
+}
+
+fn ipopt_timestamp(opt: &mut FieldNode, data: &[u8], offset: usize) {
+    opt.add(FieldNode::new("ip.opt.ptr", "Pointer", FieldValue::UInt(data[2] as u64), offset + 2, 1));
+    if data.len() < 4 { return; }
+    let flag = data[3] & 0x0f;
+    opt.add(FieldNode::new("ip.opt.ts.ovfl", "Overflow", FieldValue::UInt((data[3] >> 4) as u64), offset + 3, 1));
+    opt.add(FieldNode::new("ip.opt.ts.flag", "Flag", FieldValue::UInt(flag as u64), offset + 3, 1)
+            .display(match flag {
+                0 => String::from("Time stamps only (0)"),
+                1 => String::from("Time stamp and address (1)"),
+                3 => String::from("Time stamps for prespecified addresses (3)"),
+                n => format!("Unknown ({})", n),
+            }));
+
+    let entry_len = if flag == 0 { 4 } else { 8 };
+    for (i, ent) in data[4..].chunks(entry_len).enumerate() {
+        if ent.len() < entry_len { break; }
+        let ent_off = offset + 4 + i * entry_len;
+        if flag != 0 {
+            opt.add(FieldNode::new("ip.opt.ts.addr", "Address", FieldValue::Ipv4(ipv4_addr(ent)), ent_off, 4));
+        }
+        let ts = BigEndian::read_u32(&ent[entry_len - 4..]);
+        opt.add(FieldNode::new("ip.opt.ts.stamp", "Time stamp", FieldValue::UInt(ts as u64),
+                               ent_off + entry_len - 4, 4));
+    }
+}
+
+fn dissect_ip_options(data: &[u8], offset: usize) -> FieldNode {
+    let mut opts = FieldNode::text("Options", format!("({} bytes)", data.len()));
+    opts.offset = Some(offset);
+    opts.len = data.len();
+
+    let mut i = 0;
+    while i < data.len() {
+        let typ = data[i];
+        let opt_off = offset + i;
+
+        if typ == IPOPT_EOL || typ == IPOPT_NOP {
+            let mut opt = FieldNode::new("ip.opt", ipopt_name(typ), FieldValue::None, opt_off, 1);
+            opt.add(ipopt_type_node(typ, opt_off));
+            opts.add(opt);
+            i += 1;
+            if typ == IPOPT_EOL { break; }
+            continue;
+        }
+
+        let len = match data.get(i + 1) {
+            Some(&l) if l >= 2 && i + l as usize <= data.len() => l as usize,
+            _ => {
+                opts.add(malformed_node("IPv4 option", opt_off, 2, data.len() - i)
+                         .display(format!("{} option has an invalid length", ipopt_name(typ))));
+                break;
+            },
+        };
+
+        let opt_data = &data[i..i + len];
+        let mut opt = FieldNode::new("ip.opt", ipopt_name(typ), FieldValue::None, opt_off, len)
+            .display(format!("({} bytes)", len));
+        opt.add(ipopt_type_node(typ, opt_off));
+        opt.add(FieldNode::new("ip.opt.len", "Length", FieldValue::UInt(len as u64), opt_off + 1, 1));
+        match typ {
+            IPOPT_RR | IPOPT_LSRR | IPOPT_SSRR if len >= 3 => ipopt_route(&mut opt, opt_data, opt_off),
+            IPOPT_TS if len >= 3 => ipopt_timestamp(&mut opt, opt_data, opt_off),
+            IPOPT_RA if len == 4 => {
+                let ra = BigEndian::read_u16(&opt_data[2..4]);
+                opt.add(FieldNode::new("ip.opt.ra", "Router Alert", FieldValue::UInt(ra as u64), opt_off + 2, 2)
+                        .display(if ra == 0 { String::from("Router shall examine packet (0)") } else { ra.to_string() }));
+            },
+            _ if len > 2 => {
+                opt.add(FieldNode::new("ip.opt.data", "Data", FieldValue::Bytes(opt_data[2..].to_vec()),
+                                       opt_off + 2, len - 2));
+            },
+            _ => {},
+        }
+        opts.add(opt);
+        i += len;
+    }
+
+    opts
+}
+
+fn ones_sum(data: &[u8], init: u32) -> u32 {
+    let mut sum = init;
+    for c in data.chunks(2) {
+        sum += if c.len() == 2 { (c[0] as u32) << 8 | c[1] as u32 } else { (c[0] as u32) << 8 };
+    }
+    sum
+}
+
+fn inet_checksum(sum: u32) -> u16 {
+    let mut sum = sum;
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn checksum_node(name: &str, label: &str, recv: u16, calc: u16, offset: usize) -> FieldNode {
+    let status = if recv == calc {
+        String::from("[correct]")
+    } else {
+        format!("[incorrect, should be 0x{:04x}]", calc)
+    };
+    FieldNode::new(name, label, FieldValue::UInt(recv as u64), offset, 2)
+        .display(format!("0x{:04x} {}", recv, status))
+}
+
+fn dscp_name(dscp: u8) -> String {
+    match dscp {
+        0 => String::from("CS0"),
+        8 | 16 | 24 | 32 | 40 | 48 | 56 => format!("CS{}", dscp / 8),
+        10 | 12 | 14 | 18 | 20 | 22 | 26 | 28 | 30 | 34 | 36 | 38 =>
+            format!("AF{}{}", dscp / 8, (dscp % 8) / 2),
+        44 => String::from("VOICE-ADMIT"),
+        46 => String::from("EF PHB"),
+        n => n.to_string(),
+    }
+}
+
+fn ecn_name(ecn: u8) -> &'static str {
+    match ecn {
+        0 => "Not-ECT",
+        1 => "ECT(1)",
+        2 => "ECT(0)",
+        _ => "CE",
+    }
+}
+
+// Hands `tvb` to whatever is registered under `key` in `table`. Returns
+// false, leaving `tree` alone, if nothing is.
+fn call_dissector_table(table: &str, key: u32, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) -> bool {
+    // The handle is cloned out so a dissector can look up the next one.
+    let dsctr = pinfo.disct_tbl.borrow().get(table, key);
+    match dsctr {
+        Some(dsctr) => {
+            dsctr.call(tvb, pinfo, tree);
+            true
+        },
+        None => false,
+    }
+}
+
+fn dissect_ip_payload(proto_num: u8, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+    if !call_dissector_table("ip.proto", proto_num as u32, tvb, pinfo, tree) {
+        tree.append(unknown_tree());
+    }
+}
+
+fn ipproto_name(proto_num: u8) -> String {
+    match proto_num {
+        IPPROTO_ICMP => String::from("ICMP"),
+        IPPROTO_TCP => String::from("TCP"),
+        IPPROTO_UDP => String::from("UDP"),
+        IPPROTO_ICMPV6 => String::from("ICMPv6"),
+        n => n.to_string(),
+    }
+}
+
+fn ipv4_fragments_node(frags: &[(u32, usize, usize)], total: usize) -> FieldNode {
+    let list: Vec<String> = frags.iter().map(|&(n, _, len)| format!("#{}({})", n, len)).collect();
+    let mut node = FieldNode::text(&format!("[{} IPv4 Fragments ({} bytes)", frags.len(), total),
+                                   format!("{}]", list.join(", ")));
+    for &(n, off, len) in frags {
+        node.add(FieldNode::generated("ip.fragment", "Frame", FieldValue::UInt(n as u64))
+                 .display(format!("{}, payload: {}-{} ({} bytes)", n, off, off + len.max(1) - 1, len)));
+    }
+    node.add(FieldNode::generated("ip.reassembled.length", "Reassembled IPv4 length", FieldValue::UInt(total as u64)));
+    node
+}
+
+// Fragments are dissected before their datagram is complete, so the
+// frame that finished it is recorded on them afterwards, Info included.
+pub fn mark_reassembled_in(tree: &mut ProtoTree, info: &mut Option<String>, frame: u32) {
+    let label = format!("Fragmented IP protocol (reassembled in #{})", frame);
+    if let Some(ip) = tree.find_mut("ip") {
+        ip.add(FieldNode::generated("ip.reassembled_in", "Reassembled IPv4 in frame", FieldValue::UInt(frame as u64)));
+    }
+    if let Some(node) = tree.find_mut("frame.info") {
+        node.value = label.clone();
+        node.typed = FieldValue::Str(label.clone());
+    }
+    *info = Some(label);
+}
+
+struct Ipv4;
+
+impl Dissector for Ipv4 {
+    fn name(&self) -> &str { "IPv4" }
+    fn dissect(&self, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+        dissect_ip(tvb, pinfo, tree)
+    }
+}
+
+fn dissect_ip(tvb: &mut Tvb, pinfo: &mut PacketInfo, out: &mut ProtoTree) {
+    let offset = tvb.offset();
+    let convs = pinfo.convs.clone();
+    let data = tvb.data();
+    if data.len() < IPV4_HDR_LEN {
+        return malformed("IPv4", offset, IPV4_HDR_LEN, data, pinfo, out);
+    }
+
+    let version = data[0] >> 4;
+    let ihl = ((data[0] & 0x0f) as usize) * 4;
+    if ihl < IPV4_HDR_LEN {
+        return malformed_msg("IPv4", offset, IPV4_HDR_LEN,
+                             format!("IPv4 header length {} is less than {}", ihl, IPV4_HDR_LEN),
+                             pinfo, out);
+    }
+    if data.len() < ihl {
+        return malformed("IPv4", offset, ihl, data, pinfo, out);
+    }
+
+    let tos = data[1];
+    let total_len = BigEndian::read_u16(&data[2..4]) as usize;
+    let ident = BigEndian::read_u16(&data[4..6]);
+    let flags_frag = BigEndian::read_u16(&data[6..8]);
+    let ttl_val = data[8];
+    let proto_num = data[9];
+    let cksum = BigEndian::read_u16(&data[10..12]);
+    let calc_cksum = inet_checksum(ones_sum(&data[..10], ones_sum(&data[12..ihl], 0)));
+    let src_addr = ipv4_addr(&data[12..16]);
+    let dst_addr = ipv4_addr(&data[16..20]);
+    let src_val = src_addr.to_string();
+    let dst_val = dst_addr.to_string();
+    let ip_val = format!("IP {} -> {}", src_val, dst_val);
+
+    // A zero total length is what TCP segmentation offload leaves behind;
+    // anything past the total length is link-layer padding.
+    let end = match total_len {
+        0 => data.len(),
+        n if n < ihl => {
+            return malformed_msg("IPv4", offset, ihl,
+                                 format!("IPv4 total length {} is less than header length {}", n, ihl),
+                                 pinfo, out);
+        },
+        n => ::std::cmp::min(n, data.len()),
+    };
+
+    // Transport dissectors key their conversation state on the addresses.
+    pinfo.net_src = Some(src_val);
+    pinfo.net_dst = Some(dst_val);
+
+    let df = flags_frag & 0x4000 != 0;
+    let mf = flags_frag & 0x2000 != 0;
+    let frag_off = ((flags_frag & 0x1fff) as usize) * 8;
+
+    pinfo.addr_sum = ones_sum(&data[12..20], 0);
+    let mut payload = ProtoTree::new();
+    if mf || frag_off > 0 {
+        let key = (src_addr, dst_addr, ident, proto_num);
+        let dgram = convs.borrow_mut().ipv4_fragment(key, pinfo.num, frag_off, mf, &data[ihl..end]);
+        match dgram {
+            Some(mut dgram) => {
+                payload.add(ipv4_fragments_node(&dgram.frags, dgram.data.len()));
+                let mut pl = ProtoTree::new();
+                dissect_ip_payload(proto_num, &mut Tvb::new(&mut dgram.data, 0), pinfo, &mut pl);
+                pl.clear_offsets();
+                payload.append(pl);
+            },
+            None => {
+                payload.add(FieldNode::new("data", "Fragment Data", FieldValue::Bytes(data[ihl..end].to_vec()),
+                                           offset + ihl, end - ihl)
+                            .display(format!("{} bytes", end - ihl)));
+                pinfo.info = Some(format!("Fragmented IP protocol (proto={} {}, off={}, ID={:04x})",
+                                          ipproto_name(proto_num), proto_num, frag_off, ident));
+            },
+        }
+    } else {
+        dissect_ip_payload(proto_num, &mut Tvb::new(&mut data[ihl..end], offset + ihl), pinfo, &mut payload);
+    }
+
+    if pinfo.info.is_none() { pinfo.info = Some(ip_val.clone()); }
+    let flags_val = match (df, mf) {
+        (true, true) => "Don't fragment, More fragments",
+        (true, false) => "Don't fragment",
+        (false, true) => "More fragments",
+        (false, false) => "",
+    };
+
+    let mut tree = ProtoTree::new();
+    {
+        let ip = tree.add(FieldNode::proto("ip", "Internet Protocol v4", ip_val, offset, ihl));
+        ip.add(FieldNode::new("ip.version", "Version", FieldValue::UInt(version as u64), offset, 1));
+        ip.add(FieldNode::new("ip.hdr_len", "Header Length", FieldValue::UInt(ihl as u64), offset, 1)
+               .display(format!("{} bytes ({})", ihl, ihl / 4)));
+        {
+            let ds = ip.add(FieldNode::new("ip.dsfield", "Differentiated Services Field",
+                                           FieldValue::UInt(tos as u64), offset + 1, 1)
+                            .display(format!("0x{:02x} (DSCP: {}, ECN: {})",
+                                             tos, dscp_name(tos >> 2), ecn_name(tos & 0x03))));
+            ds.add(FieldNode::new("ip.dsfield.dscp", "Differentiated Services Codepoint",
+                                  FieldValue::UInt((tos >> 2) as u64), offset + 1, 1)
+                   .display(format!("{} ({})", dscp_name(tos >> 2), tos >> 2)));
+            ds.add(FieldNode::new("ip.dsfield.ecn", "Explicit Congestion Notification",
+                                  FieldValue::UInt((tos & 0x03) as u64), offset + 1, 1)
+                   .display(format!("{} ({})", ecn_name(tos & 0x03), tos & 0x03)));
+        }
+        ip.add(FieldNode::new("ip.len", "Total Length", FieldValue::UInt(total_len as u64), offset + 2, 2));
+        ip.add(FieldNode::new("ip.id", "Identification", FieldValue::UInt(ident as u64), offset + 4, 2)
+               .display(format!("0x{:04x} ({})", ident, ident)));
+        {
+            let flags = ip.add(FieldNode::new("ip.flags", "Flags", FieldValue::UInt((flags_frag >> 13) as u64),
+                                              offset + 6, 1)
+                               .display(format!("0x{:x}{}", flags_frag >> 13,
+                                                if flags_val.is_empty() { String::new() }
+                                                else { format!(", {}", flags_val) })));
+            flags.add(FieldNode::new("ip.flags.rb", "Reserved bit", FieldValue::Bool(flags_frag & 0x8000 != 0),
+                                     offset + 6, 1)
+                      .display(String::from(if flags_frag & 0x8000 != 0 { "Set" } else { "Not set" })));
+            flags.add(FieldNode::new("ip.flags.df", "Don't fragment", FieldValue::Bool(df), offset + 6, 1)
+                      .display(String::from(if df { "Set" } else { "Not set" })));
+            flags.add(FieldNode::new("ip.flags.mf", "More fragments", FieldValue::Bool(mf), offset + 6, 1)
+                      .display(String::from(if mf { "Set" } else { "Not set" })));
+        }
+        ip.add(FieldNode::new("ip.frag_offset", "Fragment Offset", FieldValue::UInt(frag_off as u64), offset + 6, 2));
+        ip.add(FieldNode::new("ip.ttl", "Time to live", FieldValue::UInt(ttl_val as u64), offset + 8, 1));
+        ip.add(FieldNode::new("ip.proto", "Protocol", FieldValue::UInt(proto_num as u64), offset + 9, 1)
+               .display(format!("{} ({})", ipproto_name(proto_num), proto_num)));
+        ip.add(checksum_node("ip.checksum", "Header Checksum", cksum, calc_cksum, offset + 10));
+        ip.add(FieldNode::new("ip.src", "Source", FieldValue::Ipv4(src_addr), offset + 12, 4));
+        ip.add(FieldNode::new("ip.dst", "Destination", FieldValue::Ipv4(dst_addr), offset + 16, 4));
+        ip.add(FieldNode::new("ip.addr", "Source or Destination Address",
+                              FieldValue::Ipv4(src_addr), offset + 12, 4).hide());
+        ip.add(FieldNode::new("ip.addr", "Source or Destination Address",
+                              FieldValue::Ipv4(dst_addr), offset + 16, 4).hide());
+        if ihl > IPV4_HDR_LEN {
+            ip.add(dissect_ip_options(&data[IPV4_HDR_LEN..ihl], offset + IPV4_HDR_LEN));
+        }
+    }
+    tree.append(payload);
+
+    out.append(tree);
+}
+
+fn ipv6_addr(bytes: &[u8]) -> Ipv6Addr {
+    let mut a = [0u8; 16];
+    a.copy_from_slice(&bytes[..16]);
+    Ipv6Addr::from(a)
+}
+
+fn ipv6_ext_name(nxt: u8) -> &'static str {
+    match nxt {
+        IPPROTO_HOPOPTS => "Hop-by-Hop Options",
+        IPPROTO_ROUTING => "Routing Header",
+        IPPROTO_FRAGMENT => "Fragment Header",
+        IPPROTO_AH => "Authentication Header",
+        IPPROTO_DSTOPTS => "Destination Options",
+        _ => "Unknown Extension Header",
+    }
+}
+
+fn ipv6_options(node: &mut FieldNode, data: &[u8], offset: usize) {
+    let mut i = 0;
+    while i < data.len() {
+        let typ = data[i];
+        let opt_off = offset + i;
+        if typ == 0 {
+            node.add(FieldNode::new("ipv6.opt.type", "Pad1", FieldValue::UInt(0), opt_off, 1));
+            i += 1;
+            continue;
+        }
+        let len = match data.get(i + 1) {
+            Some(&l) if i + 2 + l as usize <= data.len() => l as usize,
+            _ => {
+                node.add(malformed_node("IPv6 option", opt_off, 2, data.len() - i));
+                return;
+            },
+        };
+        let val = &data[i + 2..i + 2 + len];
+        let (name, disp) = match typ {
+            1 => ("PadN", format!("{} bytes", len)),
+            5 if len == 2 => ("Router Alert", match BigEndian::read_u16(val) {
+                0 => String::from("MLD (0)"),
+                1 => String::from("RSVP (1)"),
+                2 => String::from("Active Networks (2)"),
+                n => n.to_string(),
+            }),
+            0xc2 if len == 4 => ("Jumbo Payload", BigEndian::read_u32(val).to_string()),
+            _ => ("Unknown Option", FieldValue::Bytes(val.to_vec()).to_string()),
+        };
+        let mut opt = FieldNode::new("ipv6.opt", name, FieldValue::Bytes(val.to_vec()), opt_off, len + 2)
+            .display(disp);
+        opt.add(FieldNode::new("ipv6.opt.type", "Type", FieldValue::UInt(typ as u64), opt_off, 1)
+                .display(format!("0x{:02x}", typ)));
+        opt.add(FieldNode::new("ipv6.opt.length", "Length", FieldValue::UInt(len as u64), opt_off + 1, 1));
+        node.add(opt);
+        i += len + 2;
+    }
+}
+
+struct Ipv6Ext {
+    node: FieldNode,
+    next: u8,
+    len: usize,
+    frag_off: Option<usize>,
+}
+
+fn dissect_ipv6_ext(nxt: u8, data: &[u8], offset: usize) -> Result<Ipv6Ext, FieldNode> {
+    let name = ipv6_ext_name(nxt);
+    if data.len() < 8 {
+        return Err(malformed_node(name, offset, 8, data.len()));
+    }
+
+    let len = match nxt {
+        IPPROTO_FRAGMENT => 8,
+        IPPROTO_AH => (data[1] as usize + 2) * 4,
+        _ => (data[1] as usize + 1) * 8,
+    };
+    if data.len() < len {
+        return Err(malformed_node(name, offset, len, data.len()));
+    }
+
+    let abbrev = match nxt {
+        IPPROTO_HOPOPTS => "ipv6.hopopts",
+        IPPROTO_ROUTING => "ipv6.routing",
+        IPPROTO_FRAGMENT => "ipv6.fragment",
+        IPPROTO_AH => "ah",
+        _ => "ipv6.dstopts",
+    };
+    let mut node = FieldNode::proto(abbrev, name, format!("({} bytes)", len), offset, len);
+    node.add(FieldNode::new(&format!("{}.nxt", abbrev), "Next Header", FieldValue::UInt(data[0] as u64), offset, 1));
+    let mut frag_off = None;
+
+    match nxt {
+        IPPROTO_HOPOPTS | IPPROTO_DSTOPTS => {
+            node.add(FieldNode::new(&format!("{}.len", abbrev), "Length", FieldValue::UInt(data[1] as u64), offset + 1, 1)
+                     .display(format!("{} ({} bytes)", data[1], len)));
+            ipv6_options(&mut node, &data[2..len], offset + 2);
+        },
+        IPPROTO_ROUTING => {
+            let rt_type = data[2];
+            node.add(FieldNode::new("ipv6.routing.len", "Length", FieldValue::UInt(data[1] as u64), offset + 1, 1)
+                     .display(format!("{} ({} bytes)", data[1], len)));
+            node.add(FieldNode::new("ipv6.routing.type", "Type", FieldValue::UInt(rt_type as u64), offset + 2, 1)
+                     .display(match rt_type {
+                         0 => String::from("Source Route (0)"),
+                         2 => String::from("Type 2 Routing (2)"),
+                         3 => String::from("RPL Source Route (3)"),
+                         4 => String::from("Segment Routing (4)"),
+                         n => n.to_string(),
+                     }));
+            node.add(FieldNode::new("ipv6.routing.segleft", "Segments Left", FieldValue::UInt(data[3] as u64), offset + 3, 1));
+            let addr_start = match rt_type {
+                0 | 2 => Some(8),
+                4 => {
+                    node.add(FieldNode::new("ipv6.routing.srh.last_entry", "Last Entry",
+                                            FieldValue::UInt(data[4] as u64), offset + 4, 1));
+                    node.add(FieldNode::new("ipv6.routing.srh.flags", "Flags",
+                                            FieldValue::UInt(data[5] as u64), offset + 5, 1));
+                    node.add(FieldNode::new("ipv6.routing.srh.tag", "Tag",
+                                            FieldValue::UInt(BigEndian::read_u16(&data[6..8]) as u64), offset + 6, 2));
+                    Some(8)
+                },
+                _ => None,
+            };
+            if let Some(start) = addr_start {
+                for (i, a) in data[start..len].chunks(16).enumerate() {
+                    if a.len() < 16 { break; }
+                    node.add(FieldNode::new("ipv6.routing.addr", "Address", FieldValue::Ipv6(ipv6_addr(a)),
+                                            offset + start + i * 16, 16));
+                }
+            }
+        },
+        IPPROTO_FRAGMENT => {
+            let off_flags = BigEndian::read_u16(&data[2..4]);
+            let off = ((off_flags >> 3) as usize) * 8;
+            let more = off_flags & 0x0001 != 0;
+            let ident = BigEndian::read_u32(&data[4..8]);
+            node.add(FieldNode::new("ipv6.fragment.offset", "Offset", FieldValue::UInt(off as u64), offset + 2, 2));
+            node.add(FieldNode::new("ipv6.fragment.more", "More Fragments", FieldValue::Bool(more), offset + 3, 1)
+                     .display(String::from(if more { "Yes" } else { "No" })));
+            node.add(FieldNode::new("ipv6.fragment.id", "Identification", FieldValue::UInt(ident as u64), offset + 4, 4)
+                     .display(format!("0x{:08x}", ident)));
+            frag_off = Some(off);
+        },
+        _ => {
+            node.add(FieldNode::new("ah.length", "Length", FieldValue::UInt(data[1] as u64), offset + 1, 1)
+                     .display(format!("{} ({} bytes)", data[1], len)));
+            node.add(FieldNode::new("ah.spi", "AH SPI", FieldValue::UInt(BigEndian::read_u32(&data[4..8]) as u64), offset + 4, 4)
+                     .display(format!("0x{:08x}", BigEndian::read_u32(&data[4..8]))));
+            if len >= 12 {
+                node.add(FieldNode::new("ah.sequence", "AH Sequence", FieldValue::UInt(BigEndian::read_u32(&data[8..12]) as u64),
+                                        offset + 8, 4));
+                node.add(FieldNode::new("ah.icv", "AH ICV", FieldValue::Bytes(data[12..len].to_vec()), offset + 12, len - 12));
+            }
+        },
+    }
+
+    Ok(Ipv6Ext { node: node, next: data[0], len: len, frag_off: frag_off })
+}
+
+struct Ipv6;
+
+impl Dissector for Ipv6 {
+    fn name(&self) -> &str { "IPv6" }
+    fn dissect(&self, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+        dissect_ipv6(tvb, pinfo, tree)
+    }
+}
+
+fn dissect_ipv6(tvb: &mut Tvb, pinfo: &mut PacketInfo, out: &mut ProtoTree) {
+    let offset = tvb.offset();
+    let data = tvb.data();
+    if data.len() < IPV6_HDR_LEN {
+        return malformed("IPv6", offset, IPV6_HDR_LEN, data, pinfo, out);
+    }
+
+    let vtf = BigEndian::read_u32(&data[0..4]);
+    let version = vtf >> 28;
+    let tclass = ((vtf >> 20) & 0xff) as u8;
+    let flow = vtf & 0x000f_ffff;
+    let plen = BigEndian::read_u16(&data[4..6]) as usize;
+    let mut nxt = data[6];
+    let hlim = data[7];
+    let src_addr = ipv6_addr(&data[8..24]);
+    let dst_addr = ipv6_addr(&data[24..40]);
+    let src_val = src_addr.to_string();
+    let dst_val = dst_addr.to_string();
+    let ip_val = format!("IPv6 {} -> {}", src_val, dst_val);
+
+    // As with IPv4, a zero payload length means jumbogram or offloaded
+    // segmentation; anything past the payload length is padding.
+    let end = if plen == 0 { data.len() } else { ::std::cmp::min(IPV6_HDR_LEN + plen, data.len()) };
+
+    let mut ip6 = FieldNode::proto("ipv6", "Internet Protocol Version 6", ip_val.clone(), offset, IPV6_HDR_LEN);
+    ip6.add(FieldNode::new("ipv6.version", "Version", FieldValue::UInt(version as u64), offset, 1));
+    ip6.add(FieldNode::new("ipv6.tclass", "Traffic Class", FieldValue::UInt(tclass as u64), offset, 2)
+            .display(format!("0x{:02x} (DSCP: {}, ECN: {})", tclass, dscp_name(tclass >> 2), ecn_name(tclass & 0x03))));
+    ip6.add(FieldNode::new("ipv6.flow", "Flow Label", FieldValue::UInt(flow as u64), offset + 1, 3)
+            .display(format!("0x{:05x}", flow)));
+    ip6.add(FieldNode::new("ipv6.plen", "Payload Length", FieldValue::UInt(plen as u64), offset + 4, 2));
+    ip6.add(FieldNode::new("ipv6.nxt", "Next Header", FieldValue::UInt(nxt as u64), offset + 6, 1));
+    ip6.add(FieldNode::new("ipv6.hlim", "Hop Limit", FieldValue::UInt(hlim as u64), offset + 7, 1));
+    ip6.add(FieldNode::new("ipv6.src", "Source", FieldValue::Ipv6(src_addr), offset + 8, 16));
+    ip6.add(FieldNode::new("ipv6.dst", "Destination", FieldValue::Ipv6(dst_addr), offset + 24, 16));
+    ip6.add(FieldNode::new("ipv6.addr", "Source or Destination Address",
+                           FieldValue::Ipv6(src_addr), offset + 8, 16).hide());
+    ip6.add(FieldNode::new("ipv6.addr", "Source or Destination Address",
+                           FieldValue::Ipv6(dst_addr), offset + 24, 16).hide());
+
+    let mut pos = IPV6_HDR_LEN;
+    let mut later_fragment = false;
+    let mut truncated = false;
+    while !truncated {
+        match nxt {
+            IPPROTO_HOPOPTS | IPPROTO_ROUTING | IPPROTO_FRAGMENT | IPPROTO_AH | IPPROTO_DSTOPTS => {
+                match dissect_ipv6_ext(nxt, &data[pos..end], offset + pos) {
+                    Ok(ext) => {
+                        if let Some(frag_off) = ext.frag_off {
+                            later_fragment = frag_off != 0;
+                        }
+                        ip6.add(ext.node);
+                        nxt = ext.next;
+                        pos += ext.len;
+                    },
+                    Err(node) => {
+                        ip6.add(node);
+                        truncated = true;
+                    },
+                }
+                if later_fragment { break; }
+            },
+            _ => break,
+        }
+    }
+    ip6.len = pos;
+
+    pinfo.net_src = Some(src_val);
+    pinfo.net_dst = Some(dst_val);
+
+    let mut tree = ProtoTree::new();
+    if truncated {
+        pinfo.info = Some(String::from("[Malformed Packet: IPv6]"));
+    } else if later_fragment {
+        tree.add(FieldNode::new("data", "Fragment Data", FieldValue::None, offset + pos, end - pos)
+                 .display(format!("{} bytes", end - pos)));
+        if pinfo.info.is_none() {
+            pinfo.info = Some(format!("IPv6 fragment (next header {})", nxt));
+        }
+    } else if nxt == IPPROTO_ESP {
+        pinfo.proto = Some(String::from("ESP"));
+    } else if nxt != IPPROTO_NONE {
+        pinfo.addr_sum = ones_sum(&data[8..40], 0);
+        dissect_ip_payload(nxt, &mut Tvb::new(&mut data[pos..end], offset + pos), pinfo, &mut tree);
+    }
+
+    if pinfo.info.is_none() { pinfo.info = Some(ip_val); }
+
+    out.add(ip6);
+    out.append(tree);
+}
+
+fn flag_node(name: &str, label: &str, set: bool, offset: usize) -> FieldNode {
+    FieldNode::new(name, label, FieldValue::Bool(set), offset, 1)
+        .display(String::from(if set { "Set" } else { "Not set" }))
+}
+
+fn icmpv6_type_and_code(typ: u8, cod: u8) -> (String, String) {
+    let t = match typ {
+        ICMP6_DST_UNREACH => "Destination Unreachable",
+        ICMP6_PACKET_TOO_BIG => "Packet Too Big",
+        ICMP6_TIME_EXCEEDED => "Time Exceeded",
+        ICMP6_PARAM_PROB => "Parameter Problem",
+        ICMP6_ECHO_REQUEST => "Echo (ping) request",
+        ICMP6_ECHO_REPLY => "Echo (ping) reply",
+        ND_ROUTER_SOLICIT => "Router Solicitation",
+        ND_ROUTER_ADVERT => "Router Advertisement",
+        ND_NEIGHBOR_SOLICIT => "Neighbor Solicitation",
+        ND_NEIGHBOR_ADVERT => "Neighbor Advertisement",
+        ND_REDIRECT => "Redirect",
+        _ => "Unknown",
+    };
+    let c = match (typ, cod) {
+        (ICMP6_DST_UNREACH, 0) => "no route to destination",
+        (ICMP6_DST_UNREACH, 1) => "communication with destination administratively prohibited",
+        (ICMP6_DST_UNREACH, 2) => "beyond scope of source address",
+        (ICMP6_DST_UNREACH, 3) => "address unreachable",
+        (ICMP6_DST_UNREACH, 4) => "port unreachable",
+        (ICMP6_DST_UNREACH, 5) => "source address failed ingress/egress policy",
+        (ICMP6_DST_UNREACH, 6) => "reject route to destination",
+        (ICMP6_TIME_EXCEEDED, 0) => "hop limit exceeded in transit",
+        (ICMP6_TIME_EXCEEDED, 1) => "fragment reassembly time exceeded",
+        (ICMP6_PARAM_PROB, 0) => "erroneous header field encountered",
+        (ICMP6_PARAM_PROB, 1) => "unrecognized Next Header type encountered",
+        (ICMP6_PARAM_PROB, 2) => "unrecognized IPv6 option encountered",
+        (ICMP6_DST_UNREACH, _) | (ICMP6_TIME_EXCEEDED, _) | (ICMP6_PARAM_PROB, _) => "unknown code",
+        _ => "",
+    };
+    let code_val = if c.is_empty() { cod.to_string() } else { format!("{} ({})", c, cod) };
+    (format!("{} ({})", t, typ), code_val)
+}
+
+fn icmpv6_hdr_len(typ: u8) -> usize {
+    match typ {
+        ND_ROUTER_ADVERT => 16,
+        ND_NEIGHBOR_SOLICIT | ND_NEIGHBOR_ADVERT => 24,
+        ND_REDIRECT => 40,
+        ICMP6_DST_UNREACH | ICMP6_PACKET_TOO_BIG | ICMP6_TIME_EXCEEDED | ICMP6_PARAM_PROB |
+        ICMP6_ECHO_REQUEST | ICMP6_ECHO_REPLY | ND_ROUTER_SOLICIT => 8,
+        _ => ICMP_HDR_LEN,
+    }
+}
+
+fn nd_opt_name(typ: u8) -> &'static str {
+    match typ {
+        ND_OPT_SOURCE_LINKADDR => "Source link-layer address",
+        ND_OPT_TARGET_LINKADDR => "Target link-layer address",
+        ND_OPT_PREFIX_INFORMATION => "Prefix information",
+        ND_OPT_REDIRECTED_HEADER => "Redirected header",
+        ND_OPT_MTU => "MTU",
+        _ => "Unknown option",
+    }
+}
+
+// Returns the link-layer address carried in a source/target link-layer
+// address option, for the Info column.
+fn dissect_nd_options(node: &mut FieldNode, data: &[u8], offset: usize) -> Option<[u8; 6]> {
+    let mut lladdr = None;
+    let mut i = 0;
+    while i < data.len() {
+        let opt_off = offset + i;
+        // The length is in units of 8 octets and zero is invalid.
+        let len = match data.get(i + 1) {
+            Some(&l) if l != 0 && i + l as usize * 8 <= data.len() => l as usize * 8,
+            _ => {
+                node.add(malformed_node("ICMPv6 option", opt_off, 8, data.len() - i));
+                return lladdr;
+            },
+        };
+        let typ = data[i];
+        let val = &data[i..i + len];
+        let mut opt = FieldNode::new("icmpv6.opt", "ICMPv6 Option", FieldValue::UInt(typ as u64), opt_off, len)
+            .display(format!("{} ({})", nd_opt_name(typ), typ));
+        opt.add(FieldNode::new("icmpv6.opt.type", "Type", FieldValue::UInt(typ as u64), opt_off, 1)
+                .display(format!("{} ({})", nd_opt_name(typ), typ)));
+        opt.add(FieldNode::new("icmpv6.opt.length", "Length", FieldValue::UInt((len / 8) as u64), opt_off + 1, 1)
+                .display(format!("{} ({} bytes)", len / 8, len)));
+        match typ {
+            ND_OPT_SOURCE_LINKADDR | ND_OPT_TARGET_LINKADDR if len == 8 => {
+                let addr = ether_addr(&val[2..8]);
+                let name = if typ == ND_OPT_SOURCE_LINKADDR { "icmpv6.opt.src_linkaddr" } else { "icmpv6.opt.target_linkaddr" };
+                opt.add(FieldNode::new(name, "Link-layer address", FieldValue::Ether(addr), opt_off + 2, 6));
+                lladdr = Some(addr);
+            },
+            ND_OPT_PREFIX_INFORMATION if len == 32 => {
+                let plen = val[2];
+                let prefix = ipv6_addr(&val[16..32]);
+                opt.value = format!("{}/{}", prefix, plen);
+                opt.add(FieldNode::new("icmpv6.opt.prefix.length", "Prefix Length",
+                                       FieldValue::UInt(plen as u64), opt_off + 2, 1));
+                {
+                    let flags = opt.add(FieldNode::new("icmpv6.opt.prefix.flag", "Flag", FieldValue::UInt(val[3] as u64),
+                                                       opt_off + 3, 1)
+                                        .display(format!("0x{:02x}", val[3])));
+                    flags.add(flag_node("icmpv6.opt.prefix.flag.l", "On-link flag (L)", val[3] & 0x80 != 0, opt_off + 3));
+                    flags.add(flag_node("icmpv6.opt.prefix.flag.a", "Autonomous address-configuration flag (A)",
+                                        val[3] & 0x40 != 0, opt_off + 3));
+                }
+                let valid = BigEndian::read_u32(&val[4..8]);
+                let preferred = BigEndian::read_u32(&val[8..12]);
+                opt.add(FieldNode::new("icmpv6.opt.prefix.valid_lifetime", "Valid Lifetime",
+                                       FieldValue::UInt(valid as u64), opt_off + 4, 4)
+                        .display(if valid == 0xffff_ffff { String::from("Infinity") } else { format!("{} s", valid) }));
+                opt.add(FieldNode::new("icmpv6.opt.prefix.preferred_lifetime", "Preferred Lifetime",
+                                       FieldValue::UInt(preferred as u64), opt_off + 8, 4)
+                        .display(if preferred == 0xffff_ffff { String::from("Infinity") } else { format!("{} s", preferred) }));
+                opt.add(FieldNode::new("icmpv6.opt.prefix", "Prefix", FieldValue::Ipv6(prefix), opt_off + 16, 16));
+            },
+            ND_OPT_MTU if len == 8 => {
+                let mtu = BigEndian::read_u32(&val[4..8]);
+                opt.value = mtu.to_string();
+                opt.add(FieldNode::new("icmpv6.opt.mtu", "MTU", FieldValue::UInt(mtu as u64), opt_off + 4, 4));
+            },
+            ND_OPT_REDIRECTED_HEADER => {
+                opt.add(FieldNode::new("data", "Redirected Packet", FieldValue::Bytes(val[8..].to_vec()),
+                                       opt_off + 8, len - 8)
+                        .display(format!("{} bytes", len - 8)));
+            },
+            _ => {
+                opt.add(FieldNode::new("icmpv6.opt.data", "Data", FieldValue::Bytes(val[2..].to_vec()),
+                                       opt_off + 2, len - 2));
+            },
+        }
+        node.add(opt);
+        i += len;
+    }
+    lladdr
+}
+
+struct Icmpv6;
+
+impl Dissector for Icmpv6 {
+    fn name(&self) -> &str { "ICMPv6" }
+    fn dissect(&self, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+        dissect_icmpv6(tvb, pinfo, tree)
+    }
+}
+
+fn dissect_icmpv6(tvb: &mut Tvb, pinfo: &mut PacketInfo, out: &mut ProtoTree) {
+    let offset = tvb.offset();
+    let addr_sum = pinfo.addr_sum;
+    let data = tvb.data();
+    if data.len() < ICMP_HDR_LEN {
+        return malformed("ICMPv6", offset, ICMP_HDR_LEN, data, pinfo, out);
+    }
+
+    let typ = data[0];
+    let (type_val, code_val) = icmpv6_type_and_code(typ, data[1]);
+    let cksum = BigEndian::read_u16(&data[2..4]);
+    // Pseudo-header: addresses, upper-layer length and next header.
+    let pseudo = addr_sum + (data.len() as u32 >> 16) + (data.len() as u32 & 0xffff) + IPPROTO_ICMPV6 as u32;
+    let calc_cksum = inet_checksum(ones_sum(&data[4..], ones_sum(&data[..2], pseudo)));
+
+    let mut tree = ProtoTree::new();
+    let info;
+    {
+        let icmp = tree.add(FieldNode::proto("icmpv6", "Internet Control Message Protocol v6",
+                                             type_val.clone(), offset, data.len()));
+        icmp.add(FieldNode::new("icmpv6.type", "Type", FieldValue::UInt(typ as u64), offset, 1)
+                 .display(type_val.clone()));
+        icmp.add(FieldNode::new("icmpv6.code", "Code", FieldValue::UInt(data[1] as u64), offset + 1, 1)
+                 .display(code_val.clone()));
+        icmp.add(checksum_node("icmpv6.checksum", "Checksum", cksum, calc_cksum, offset + 2));
+
+        let hdr_len = icmpv6_hdr_len(typ);
+        if data.len() < hdr_len {
+            icmp.add(malformed_node("ICMPv6", offset, hdr_len, data.len()));
+            pinfo.info = Some(String::from("[Malformed Packet: ICMPv6]"));
+            out.append(tree);
+            return;
+        }
+
+        let target = |i: usize| ipv6_addr(&data[i..i + 16]);
+        info = match typ {
+            ICMP6_ECHO_REQUEST | ICMP6_ECHO_REPLY => {
+                let id = BigEndian::read_u16(&data[4..6]);
+                let seq = BigEndian::read_u16(&data[6..8]);
+                icmp.add(FieldNode::new("icmpv6.echo.identifier", "Identifier", FieldValue::UInt(id as u64), offset + 4, 2)
+                         .display(format!("0x{:04x}", id)));
+                icmp.add(FieldNode::new("icmpv6.echo.sequence_number", "Sequence", FieldValue::UInt(seq as u64),
+                                        offset + 6, 2));
+                format!("{} id=0x{:04x}, seq={}", type_val, id, seq)
+            },
+            ICMP6_DST_UNREACH | ICMP6_PACKET_TOO_BIG | ICMP6_TIME_EXCEEDED | ICMP6_PARAM_PROB => {
+                let val = BigEndian::read_u32(&data[4..8]);
+                let inf = match typ {
+                    ICMP6_PACKET_TOO_BIG => {
+                        icmp.add(FieldNode::new("icmpv6.mtu", "MTU", FieldValue::UInt(val as u64), offset + 4, 4));
+                        format!("{}, MTU {}", type_val, val)
+                    },
+                    ICMP6_PARAM_PROB => {
+                        icmp.add(FieldNode::new("icmpv6.pointer", "Pointer", FieldValue::UInt(val as u64), offset + 4, 4));
+                        format!("{}, {}", type_val, code_val)
+                    },
+                    _ => {
+                        icmp.add(FieldNode::new("icmpv6.reserved", "Reserved", FieldValue::UInt(val as u64), offset + 4, 4)
+                                 .display(format!("{:08x}", val)));
+                        format!("{}, {}", type_val, code_val)
+                    },
+                };
+                icmp.add(FieldNode::new("data", "Invoking Packet", FieldValue::Bytes(data[8..].to_vec()),
+                                        offset + 8, data.len() - 8)
+                         .display(format!("{} bytes", data.len() - 8)));
+                inf
+            },
+            ND_ROUTER_SOLICIT => {
+                let ll = dissect_nd_options(icmp, &data[8..], offset + 8);
+                match ll {
+                    Some(a) => format!("Router Solicitation from {}", FieldValue::Ether(a)),
+                    None => String::from("Router Solicitation"),
+                }
+            },
+            ND_ROUTER_ADVERT => {
+                let flags = data[5];
+                let lifetime = BigEndian::read_u16(&data[6..8]);
+                let reachable = BigEndian::read_u32(&data[8..12]);
+                let retrans = BigEndian::read_u32(&data[12..16]);
+                icmp.add(FieldNode::new("icmpv6.nd.ra.cur_hop_limit", "Cur hop limit", FieldValue::UInt(data[4] as u64),
+                                        offset + 4, 1));
+                {
+                    let f = icmp.add(FieldNode::new("icmpv6.nd.ra.flag", "Flags", FieldValue::UInt(flags as u64), offset + 5, 1)
+                                     .display(format!("0x{:02x}", flags)));
+                    f.add(flag_node("icmpv6.nd.ra.flag.m", "Managed address configuration (M)", flags & 0x80 != 0, offset + 5));
+                    f.add(flag_node("icmpv6.nd.ra.flag.o", "Other configuration (O)", flags & 0x40 != 0, offset + 5));
+                }
+                icmp.add(FieldNode::new("icmpv6.nd.ra.router_lifetime", "Router lifetime", FieldValue::UInt(lifetime as u64),
+                                        offset + 6, 2)
+                         .display(format!("{} s", lifetime)));
+                icmp.add(FieldNode::new("icmpv6.nd.ra.reachable_time", "Reachable time", FieldValue::UInt(reachable as u64),
+                                        offset + 8, 4)
+                         .display(format!("{} ms", reachable)));
+                icmp.add(FieldNode::new("icmpv6.nd.ra.retrans_timer", "Retrans timer", FieldValue::UInt(retrans as u64),
+                                        offset + 12, 4)
+                         .display(format!("{} ms", retrans)));
+                let ll = dissect_nd_options(icmp, &data[16..], offset + 16);
+                match ll {
+                    Some(a) => format!("Router Advertisement from {}", FieldValue::Ether(a)),
+                    None => String::from("Router Advertisement"),
+                }
+            },
+            ND_NEIGHBOR_SOLICIT => {
+                icmp.add(FieldNode::new("icmpv6.nd.ns.target_address", "Target Address", FieldValue::Ipv6(target(8)),
+                                        offset + 8, 16));
+                let ll = dissect_nd_options(icmp, &data[24..], offset + 24);
+                match ll {
+                    Some(a) => format!("Neighbor Solicitation for {} from {}", target(8), FieldValue::Ether(a)),
+                    None => format!("Neighbor Solicitation for {}", target(8)),
+                }
+            },
+            ND_NEIGHBOR_ADVERT => {
+                let flags = data[4];
+                {
+                    let f = icmp.add(FieldNode::new("icmpv6.nd.na.flag", "Flags", FieldValue::UInt(flags as u64), offset + 4, 1)
+                                     .display(format!("0x{:02x}", flags)));
+                    f.add(flag_node("icmpv6.nd.na.flag.r", "Router", flags & 0x80 != 0, offset + 4));
+                    f.add(flag_node("icmpv6.nd.na.flag.s", "Solicited", flags & 0x40 != 0, offset + 4));
+                    f.add(flag_node("icmpv6.nd.na.flag.o", "Override", flags & 0x20 != 0, offset + 4));
+                }
+                icmp.add(FieldNode::new("icmpv6.nd.na.target_address", "Target Address", FieldValue::Ipv6(target(8)),
+                                        offset + 8, 16));
+                let ll = dissect_nd_options(icmp, &data[24..], offset + 24);
+                let names: Vec<&str> = [(0x80, "rtr"), (0x40, "sol"), (0x20, "ovr")].iter()
+                    .filter(|&&(bit, _)| flags & bit != 0)
+                    .map(|&(_, n)| n)
+                    .collect();
+                let mut inf = format!("Neighbor Advertisement {}", target(8));
+                if !names.is_empty() {
+                    inf.push_str(&format!(" ({})", names.join(", ")));
+                }
+                if let Some(a) = ll {
+                    inf.push_str(&format!(" is at {}", FieldValue::Ether(a)));
+                }
+                inf
+            },
+            ND_REDIRECT => {
+                icmp.add(FieldNode::new("icmpv6.nd.rd.target_address", "Target Address", FieldValue::Ipv6(target(8)),
+                                        offset + 8, 16));
+                icmp.add(FieldNode::new("icmpv6.nd.rd.destination_address", "Destination Address",
+                                        FieldValue::Ipv6(target(24)), offset + 24, 16));
+                dissect_nd_options(icmp, &data[40..], offset + 40);
+                format!("Redirect for {} via {}", target(24), target(8))
+            },
+            _ => {
+                icmp.add(FieldNode::new("data", "Data", FieldValue::Bytes(data[ICMP_HDR_LEN..].to_vec()),
+                                        offset + ICMP_HDR_LEN, data.len() - ICMP_HDR_LEN)
+                         .display(format!("{} bytes", data.len() - ICMP_HDR_LEN)));
+                format!("{}, {}", type_val, code_val)
+            },
+        };
+    }
+
+    pinfo.info = Some(info);
+
+    out.append(tree);
+}
+
+fn ether_addr(bytes: &[u8]) -> [u8; 6] {
+    [bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5]]
+}
+
+fn unknown_tree() -> ProtoTree {
+    let mut tree = ProtoTree::new();
+    tree.add(FieldNode::text("UNKNOWN", String::new()));
+    tree
+}
+
+fn ethertype_name(typ: u16) -> String {
+    match typ {
+        ETHERTYPE_IPV4 => String::from("IPv4"),
+        ETHERTYPE_IPV6 => String::from("IPv6"),
+        n => n.to_string(),
+    }
+}
+
+struct Ethernet;
+
+impl Dissector for Ethernet {
+    fn name(&self) -> &str { "Ethernet" }
+    fn dissect(&self, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+        dissect_ethernet(tvb, pinfo, tree)
+    }
+}
+
+fn dissect_ethernet(tvb: &mut Tvb, pinfo: &mut PacketInfo, out: &mut ProtoTree) {
+    let offset = tvb.offset();
+    if tvb.len() < ETHER_HDR_LEN {
+        return malformed("Ethernet", offset, ETHER_HDR_LEN, tvb.data(), pinfo, out);
+    }
+    let data = tvb.data();
+
+    let dst_addr = ether_addr(&data[0..6]);
+    let src_addr = ether_addr(&data[6..12]);
+    let dst_val = FieldValue::Ether(dst_addr).to_string();
+    let src_val = FieldValue::Ether(src_addr).to_string();
+    let eth_val = format!("Ethernet {} -> {}", src_val, dst_val);
+
+    let typ;
+    {
+        let mut rdr = Cursor::new(&data[12..14]);
+        typ = rdr.read_u16::<BigEndian>().unwrap();
+    }
+
+    let mut payload = ProtoTree::new();
+    let len = tvb.len();
+    let mut next = tvb.subset(ETHER_HDR_LEN, len);
+    if !call_dissector_table("ethertype", typ as u32, &mut next, pinfo, &mut payload) {
+        payload = unknown_tree();
+    }
+
+    if pinfo.net_src.is_none() { pinfo.net_src = Some(src_val.clone()); }
+    if pinfo.net_dst.is_none() { pinfo.net_dst = Some(dst_val.clone()); }
+    if pinfo.info.is_none() { pinfo.info = Some(eth_val.clone()); }
+
+    let mut tree = ProtoTree::new();
+    {
+        let eth = tree.add(FieldNode::proto("eth", "Ethernet", eth_val, offset, ETHER_HDR_LEN));
+        eth.add(FieldNode::new("eth.dst", "Destination", FieldValue::Ether(dst_addr), offset, 6));
+        eth.add(FieldNode::new("eth.src", "Source", FieldValue::Ether(src_addr), offset + 6, 6));
+        eth.add(FieldNode::new("eth.type", "Type", FieldValue::UInt(typ as u64), offset + 12, 2)
+                .display(ethertype_name(typ)));
+        eth.add(FieldNode::new("eth.addr", "Source or Destination Address",
+                               FieldValue::Ether(dst_addr), offset, 6).hide());
+        eth.add(FieldNode::new("eth.addr", "Source or Destination Address",
+                               FieldValue::Ether(src_addr), offset + 6, 6).hide());
+    }
+    tree.append(payload);
+
+    out.append(tree);
+}
+
+fn frame_tree(n: u32, hdr: &FrameHeader, data: &[u8], pinfo: &PacketInfo) -> ProtoTree {
+    let mut tree = ProtoTree::new();
+    {
+        let frame = tree.add(FieldNode::proto("frame", &format!("Frame {}", n),
+                                              format!("{} bytes on wire, {} bytes captured", hdr.len, hdr.caplen),
+                                              0, data.len()));
+        frame.add(FieldNode::text("Arrival Time",
+                                  time::strftime("%F %T", &time::at(pinfo.time)).unwrap()));
+        frame.add(FieldNode::generated("frame.number", "Frame Number", FieldValue::UInt(n as u64)));
+        frame.add(FieldNode::generated("frame.len", "Frame Length", FieldValue::UInt(hdr.len as u64)));
+        frame.add(FieldNode::generated("frame.cap_len", "Capture Length", FieldValue::UInt(hdr.caplen as u64)));
+    }
+    tree
+}
+
+// The Protocol and Info columns, as hidden fields so display filters
+// can match them.
+fn column_fields(tree: &mut ProtoTree, pinfo: &PacketInfo) {
+    let frame = &mut tree.nodes[0];
+    if let Some(ref proto) = pinfo.proto {
+        frame.add(FieldNode::generated("frame.protocol", "Protocol", FieldValue::Str(proto.clone())).hide());
+    }
+    if let Some(ref info) = pinfo.info {
+        frame.add(FieldNode::generated("frame.info", "Info", FieldValue::Str(info.clone())).hide());
+    }
+}
+
+// Frames must be passed in capture order, numbered from 1, with the same
+// ConversationTable; TCP analysis and reassembly depend on it.
+pub fn dissect(n: u32, hdr: FrameHeader, mut data: Vec<u8>, disct_tbl: Rc<RefCell<DissectorTable>>, convs: Rc<RefCell<ConversationTable>>) -> (ProtoTree, PacketInfo) {
+    let mut pinfo = PacketInfo {
+        num: n,
+        time: hdr.ts,
+        len: hdr.len,
+        net_src: None, net_dst: None,
+        src_port: None, dst_port: None,
+        proto: None, info: None,
+        desegment_len: None,
+        addr_sum: 0,
+        app_nodes: Vec::new(),
+        scheme_data: None,
+        warnings: Vec::new(),
+        disct_tbl: disct_tbl,
+        convs: convs,
+    };
+
+    let mut tree = frame_tree(n, &hdr, &data, &pinfo);
+    Ethernet.dissect(&mut Tvb::new(&mut data, 0), &mut pinfo, &mut tree);
+    column_fields(&mut tree, &pinfo);
+    (tree, pinfo)
+}
+
+// For output that handles each frame once, in capture order, such as the
+// CLI and its exports. A fragment is held, with every frame after it,
+// until the frame completing its datagram has been dissected or it is
+// given up on, so it can be marked with `mark_reassembled_in` first.
+pub struct FrameQueue<T> {
+    convs: Rc<RefCell<ConversationTable>>,
+    held: VecDeque<(ProtoTree, PacketInfo, T)>,
+}
+
+impl<T> FrameQueue<T> {
+    pub fn new(convs: Rc<RefCell<ConversationTable>>) -> FrameQueue<T> {
+        FrameQueue { convs: convs, held: VecDeque::new() }
+    }
+
+    // Takes a frame just dissected with the queue's ConversationTable,
+    // along with whatever the caller keeps with it, and returns the frames
+    // that are now final.
+    pub fn push(&mut self, tree: ProtoTree, pinfo: PacketInfo, extra: T) -> Vec<(ProtoTree, PacketInfo, T)> {
+        let n = pinfo.num;
+        self.held.push_back((tree, pinfo, extra));
+        let first = self.held[0].1.num;
+        let mut convs = self.convs.borrow_mut();
+        for (frag, reasm) in convs.take_reassembled() {
+            if let Some(&mut (ref mut tree, ref mut pinfo, _)) = self.held.get_mut(frag.wrapping_sub(first) as usize) {
+                mark_reassembled_in(tree, &mut pinfo.info, reasm);
+            }
+        }
+
+        let pending = convs.first_pending_fragment(n);
+        let mut ready = Vec::new();
+        while self.held.front().map_or(false, |f| pending.map_or(true, |p| f.1.num < p)) {
+            ready.push(self.held.pop_front().unwrap());
+        }
+        ready
+    }
+
+    // The frames still held, at the end of the capture.
+    pub fn finish(&mut self) -> Vec<(ProtoTree, PacketInfo, T)> {
+        self.held.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dfilter::Filter;
+
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn bytes(&mut self, max_len: usize) -> Vec<u8> {
+            let len = (self.next() % (max_len as u64 + 1)) as usize;
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+    }
+
+    fn header(len: usize) -> FrameHeader {
+        FrameHeader { ts: Timespec::new(0, 0), caplen: len as u32, len: len as u32 }
+    }
+
+    fn run(n: u32, data: Vec<u8>) -> (ProtoTree, PacketInfo) {
+        let tbl = Rc::new(RefCell::new(DissectorTable::new()));
+        let convs = Rc::new(RefCell::new(ConversationTable::new()));
+        dissect(n, header(data.len()), data, tbl, convs)
+    }
+
+    fn tcp_frame() -> Vec<u8> {
+        let mut f = vec![0u8; ETHER_HDR_LEN + IPV4_HDR_LEN + TCP_HDR_LEN];
+        f[12] = 0x08;
+        f[14] = 0x45;
+        f[14 + 3] = (IPV4_HDR_LEN + TCP_HDR_LEN) as u8;
+        f[14 + 9] = IPPROTO_TCP;
+        f[14 + 20] = 0x01;
+        f[14 + 20 + 1] = 0xbb;
+        f[14 + 20 + 12] = 0x50;
+        f
+    }
+
+    fn tcp_frame_with_options(opts: &[u8]) -> Vec<u8> {
+        let mut f = tcp_frame();
+        let hdr_end = f.len();
+        f.splice(hdr_end..hdr_end, opts.iter().cloned());
+        f[14 + 3] += opts.len() as u8;
+        f[14 + 20 + 12] = (((TCP_HDR_LEN + opts.len()) / 4) << 4) as u8;
+        f
+    }
+
+    fn run_with(tbl: DissectorTable, data: Vec<u8>) -> (ProtoTree, PacketInfo) {
+        let convs = Rc::new(RefCell::new(ConversationTable::new()));
+        dissect(1, header(data.len()), data, Rc::new(RefCell::new(tbl)), convs)
+    }
+
+    struct Marker;
+
+    impl Dissector for Marker {
+        fn name(&self) -> &str { "MARK" }
+        fn dissect(&self, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+            tree.add(FieldNode::new("mark", "Marker", FieldValue::UInt(tvb.len() as u64), tvb.offset(), tvb.len()));
+            pinfo.info = Some(String::from("marked"));
+        }
+    }
+
+    // A PDU of a two byte length and that many bytes of data, which asks
+    // TCP for the rest when it is split across segments.
+    struct LengthPrefixed;
+
+    impl Dissector for LengthPrefixed {
+        fn name(&self) -> &str { "LP" }
+        fn dissect(&self, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+            let data = tvb.data();
+            let need = if data.len() < 2 { 2 } else { 2 + BigEndian::read_u16(data) as usize };
+            if data.len() < need {
+                pinfo.desegment_len = Some(need - data.len());
+                return;
+            }
+            tree.add(FieldNode::new("lp.len", "Length", FieldValue::UInt(need as u64 - 2), tvb.offset(), 2));
+            pinfo.info = Some(String::from("PDU"));
+        }
+    }
+
+    fn tcp_segment(seq: u32, payload: &[u8]) -> Vec<u8> {
+        let mut f = tcp_frame();
+        BigEndian::write_u32(&mut f[14 + 20 + 4..14 + 20 + 8], seq);
+        f[14 + 20 + 14] = 0x10;
+        f[14 + 3] += payload.len() as u8;
+        f.extend(payload);
+        f
+    }
+
+    // Dissects `frames` in order, as one capture.
+    fn run_capture(tbl: DissectorTable, frames: Vec<Vec<u8>>) -> Vec<(ProtoTree, PacketInfo)> {
+        let tbl = Rc::new(RefCell::new(tbl));
+        let convs = Rc::new(RefCell::new(ConversationTable::new()));
+        frames.into_iter().enumerate()
+            .map(|(i, f)| dissect(i as u32 + 1, header(f.len()), f, tbl.clone(), convs.clone()))
+            .collect()
+    }
+
+    fn lp_table() -> DissectorTable {
+        let mut tbl = DissectorTable::new();
+        tbl.register("tcp.port", 443, DissectorHandle::Native(Rc::new(LengthPrefixed)));
+        tbl
+    }
+
+    fn is_segment_of_pdu(tree: &ProtoTree, pinfo: &PacketInfo) -> bool {
+        tree.nodes.iter().any(|n| n.label == "[TCP segment of a reassembled PDU]") &&
+            pinfo.info.as_ref().unwrap().ends_with("[TCP segment of a reassembled PDU]")
+    }
+
+    // Does what a Scheme dissector calling `call-next-dissector` on all
+    // but a 4 byte header of its payload would.
+    struct Tunnel;
+
+    impl Dissector for Tunnel {
+        fn name(&self) -> &str { "TUN" }
+        fn dissect(&self, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+            let mark = pinfo.app_nodes.len();
+            let offset = tvb.offset();
+            let data = tvb.data();
+            assert!(call_next(pinfo, "ethertype", ETHERTYPE_IPV4 as u32, &mut data[4..], 4));
+            let mut app = pinfo.take_app_tree(mark);
+            app.shift_offsets(offset);
+            tree.append(app);
+        }
+    }
+
+    fn has_malformed(tree: &ProtoTree) -> bool {
+        tree.fields().iter().any(|&(ref n, _)| n == "_ws.malformed")
+    }
+
+    fn values(tree: &ProtoTree, name: &str) -> Vec<String> {
+        tree.fields().into_iter().filter(|&(ref n, _)| n == name).map(|(_, v)| v).collect()
+    }
+
+    // An Ethernet/IPv4 frame from 10.0.0.1 to 10.0.0.2 carrying `opts`
+    // and then an empty UDP datagram.
+    fn ipv4_frame(opts: &[u8]) -> Vec<u8> {
+        let ihl = IPV4_HDR_LEN + opts.len();
+        let total = ihl + UDP_HDR_LEN;
+        let mut f = vec![0u8; ETHER_HDR_LEN];
+        f[12] = 0x08;
+        f.extend(&[0x40 | (ihl / 4) as u8, 0, 0, total as u8, 0, 0, 0, 0, 64, IPPROTO_UDP, 0, 0,
+                   10, 0, 0, 1, 10, 0, 0, 2]);
+        f.extend(opts);
+        f.extend(&[0, 53, 0, 53, 0, UDP_HDR_LEN as u8, 0, 0]);
+        f
+    }
+
+    // An Ethernet/IPv4 fragment of UDP datagram `id` from 10.0.0.1 to
+    // 10.0.0.2, `off` bytes into it.
+    fn ipv4_fragment_frame(id: u8, off: usize, more: bool, payload: &[u8]) -> Vec<u8> {
+        let flags_off = (off / 8) as u16 | if more { 0x2000 } else { 0 };
+        let mut f = vec![0u8; ETHER_HDR_LEN];
+        f[12] = 0x08;
+        f.extend(&[0x45, 0, 0, (IPV4_HDR_LEN + payload.len()) as u8, 0, id, (flags_off >> 8) as u8, flags_off as u8,
+                   64, IPPROTO_UDP, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        f.extend(payload);
+        f
+    }
+
+    // An Ethernet/IPv6 frame from 2001:db8::1 to 2001:db8::2 whose
+    // payload, starting with any extension headers, follows next header
+    // `nxt`.
+    fn ipv6_frame(nxt: u8, payload: &[u8]) -> Vec<u8> {
+        let mut f = vec![0u8; ETHER_HDR_LEN + IPV6_HDR_LEN];
+        f[12] = 0x86;
+        f[13] = 0xdd;
+        f[14] = 0x60;
+        f[14 + 5] = payload.len() as u8;
+        f[14 + 6] = nxt;
+        f[14 + 7] = 64;
+        for &(start, last) in &[(14 + 8, 1), (14 + 24, 2)] {
+            f[start] = 0x20;
+            f[start + 1] = 0x01;
+            f[start + 2] = 0x0d;
+            f[start + 3] = 0xb8;
+            f[start + 15] = last;
+        }
+        f.extend(payload);
+        f
+    }
+
+    #[test]
+    fn random_bytes_never_panic() {
+        let mut rng = XorShift(0x2545f4914f6cdd1d);
+        for n in 0..20000 {
+            let data = rng.bytes(96);
+            run(n, data);
+        }
+    }
+
+    #[test]
+    fn random_ipv4_payloads_never_panic() {
+        let mut rng = XorShift(0x9e3779b97f4a7c15);
+        for n in 0..20000 {
+            let mut data = tcp_frame();
+            data.truncate(ETHER_HDR_LEN);
+            data.extend(rng.bytes(80));
+            if data.len() > ETHER_HDR_LEN + 9 {
+                data[ETHER_HDR_LEN + 9] = [IPPROTO_TCP, IPPROTO_UDP, IPPROTO_ICMP, IPPROTO_ICMPV6][n % 4];
+            }
+            run(n as u32, data);
+        }
+    }
+
+    #[test]
+    fn every_truncation_is_marked_malformed() {
+        let frame = tcp_frame();
+        for len in 0..frame.len() {
+            let (tree, pinfo) = run(1, frame[..len].to_vec());
+            assert!(has_malformed(&tree), "no malformed node at length {}", len);
+            assert!(pinfo.info.unwrap().starts_with("[Malformed Packet"));
+        }
+
+        let (tree, pinfo) = run(1, frame);
+        assert!(!has_malformed(&tree));
+        assert_eq!(pinfo.src_port, Some(443));
+    }
+
+    #[test]
+    fn port_table_reaches_rust_dissectors() {
+        let mut tbl = DissectorTable::new();
+        assert!(tbl.register("tcp.port", 443, DissectorHandle::Native(Rc::new(Marker))));
+        let mut frame = tcp_frame();
+        frame.extend(b"data");
+        frame[14 + 3] += 4;
+        let (tree, pinfo) = run_with(tbl, frame);
+        let mark = tree.find("mark").expect("no marker node");
+        assert_eq!(mark.offset, Some(ETHER_HDR_LEN + IPV4_HDR_LEN + TCP_HDR_LEN));
+        assert_eq!(pinfo.proto, Some(String::from("MARK")));
+    }
+
+    #[test]
+    fn builtins_can_be_overridden() {
+        let mut tbl = DissectorTable::new();
+        tbl.register("ip.proto", IPPROTO_TCP as u32, DissectorHandle::Native(Rc::new(Marker)));
+        let (tree, pinfo) = run_with(tbl, tcp_frame());
+        assert!(tree.find("tcp").is_none());
+        assert!(tree.find("mark").is_some());
+        assert_eq!(pinfo.info, Some(String::from("marked")));
+
+        let mut tbl = DissectorTable::new();
+        assert!(!tbl.register("no.such.table", 1, DissectorHandle::Native(Rc::new(Marker))));
+        tbl.clear();
+        let (tree, _) = run_with(tbl, tcp_frame());
+        assert!(tree.find("tcp").is_some());
+    }
+
+    #[test]
+    fn pdus_split_across_segments_are_reassembled() {
+        let frames = vec![
+            tcp_segment(100, &[0, 10, b'a', b'b', b'c']),
+            tcp_segment(105, b"defg"),
+            tcp_segment(109, b"hij"),
+        ];
+        let res = run_capture(lp_table(), frames);
+        for &(ref tree, ref pinfo) in &res[..2] {
+            assert!(is_segment_of_pdu(tree, pinfo));
+            assert!(tree.find("lp.len").is_none());
+            assert_eq!(pinfo.proto, Some(String::from("TCP")));
+        }
+
+        let (ref tree, ref pinfo) = res[2];
+        assert!(!is_segment_of_pdu(tree, pinfo));
+        assert_eq!(values(tree, "lp.len"), vec!["10"]);
+        assert_eq!(tree.find("lp.len").unwrap().offset, None);
+        assert_eq!(values(tree, "tcp.segment"), vec!["1", "2", "3"]);
+        assert_eq!(values(tree, "tcp.reassembled.length"), vec!["12"]);
+        assert_eq!(pinfo.proto, Some(String::from("LP")));
+        assert_eq!(pinfo.info, Some(String::from("PDU")));
+
+        // A PDU in a single segment keeps its frame offsets.
+        let res = run_capture(lp_table(), vec![tcp_segment(100, &[0, 1, b'a'])]);
+        let lp = res[0].0.find("lp.len").unwrap();
+        assert_eq!(lp.offset, Some(ETHER_HDR_LEN + IPV4_HDR_LEN + TCP_HDR_LEN));
+        assert!(res[0].0.find("tcp.segment").is_none());
+    }
+
+    #[test]
+    fn retransmissions_are_left_out_of_reassembly() {
+        let frames = vec![
+            tcp_segment(100, &[0, 6, b'a', b'b', b'c']),
+            tcp_segment(100, &[0, 6, b'a', b'b', b'c']),
+            tcp_segment(105, b"def"),
+        ];
+        let res = run_capture(lp_table(), frames);
+        assert!(is_segment_of_pdu(&res[0].0, &res[0].1));
+
+        let (ref tree, ref pinfo) = res[1];
+        assert!(tree.find("tcp.analysis.retransmission").is_some());
+        assert!(!is_segment_of_pdu(tree, pinfo));
+        assert!(pinfo.info.as_ref().unwrap().starts_with("[TCP Retransmission]"));
+
+        let (ref tree, _) = res[2];
+        assert_eq!(values(tree, "lp.len"), vec!["6"]);
+        assert_eq!(values(tree, "tcp.segment"), vec!["1", "3"]);
+        assert_eq!(values(tree, "tcp.reassembled.length"), vec!["8"]);
+    }
+
+    // Hands all but a 4 byte header to TCP without setting any network
+    // addresses, as a Scheme dissector might.
+    struct BareTcp;
+
+    impl Dissector for BareTcp {
+        fn name(&self) -> &str { "BARE" }
+        fn dissect(&self, tvb: &mut Tvb, pinfo: &mut PacketInfo, tree: &mut ProtoTree) {
+            let mark = pinfo.app_nodes.len();
+            let offset = tvb.offset();
+            let data = tvb.data();
+            assert!(call_next(pinfo, "ip.proto", IPPROTO_TCP as u32, &mut data[4..], 4));
+            let mut app = pinfo.take_app_tree(mark);
+            app.shift_offsets(offset);
+            tree.append(app);
+        }
+    }
+
+    #[test]
+    fn reassembles_without_network_addresses() {
+        let bare = |seq: u32, payload: &[u8]| {
+            let seg = tcp_segment(seq, payload);
+            let mut f = seg[..ETHER_HDR_LEN].to_vec();
+            f[12] = 0x88;
+            f[13] = 0xb5;
+            f.extend(&[0, 0, 0, 0]);
+            f.extend(&seg[ETHER_HDR_LEN + IPV4_HDR_LEN..]);
+            f
+        };
+        let mut tbl = lp_table();
+        tbl.register("ethertype", 0x88b5, DissectorHandle::Native(Rc::new(BareTcp)));
+        let res = run_capture(tbl, vec![bare(100, &[0, 4, b'a']), bare(103, b"bcd")]);
+
+        let (ref tree, ref pinfo) = res[0];
+        assert!(tree.find("ip").is_none());
+        assert!(is_segment_of_pdu(tree, pinfo));
+
+        let (ref tree, ref pinfo) = res[1];
+        assert_eq!(values(tree, "lp.len"), vec!["4"]);
+        assert_eq!(values(tree, "tcp.segment"), vec!["1", "2"]);
+        assert_eq!(pinfo.info, Some(String::from("PDU")));
+    }
+
+    #[test]
+    fn stacked_ethernet_keeps_frame_offsets() {
+        let mut tbl = DissectorTable::new();
+        tbl.register("ethertype", 0x6558, DissectorHandle::Native(Rc::new(Ethernet)));
+        let mut frame = vec![0u8; ETHER_HDR_LEN];
+        frame[12] = 0x65;
+        frame[13] = 0x58;
+        frame.extend(tcp_frame());
+
+        let (tree, _) = run_with(tbl, frame);
+        assert!(!has_malformed(&tree));
+        let eths: Vec<&FieldNode> = tree.nodes.iter().filter(|n| n.name == "eth").collect();
+        assert_eq!(eths.len(), 2);
+        let inner = eths[1];
+        assert_eq!((inner.offset, inner.len), (Some(ETHER_HDR_LEN), ETHER_HDR_LEN));
+        let offsets: Vec<(&str, usize)> = inner.children.iter().map(|n| (n.name.as_str(), n.offset.unwrap())).collect();
+        assert_eq!(offsets, vec![("eth.dst", 14), ("eth.src", 20), ("eth.type", 26), ("eth.addr", 14), ("eth.addr", 20)]);
+        assert_eq!(tree.find("ip").unwrap().offset, Some(2 * ETHER_HDR_LEN));
+    }
+
+    #[test]
+    fn nested_dissectors_keep_frame_offsets() {
+        let mut tbl = DissectorTable::new();
+        tbl.register("tcp.port", 443, DissectorHandle::Native(Rc::new(Tunnel)));
+        let mut inner = vec![0x45, 0, 0, 28, 0, 0, 0, 0, 64, IPPROTO_UDP, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+        inner.extend(&[0, 53, 0, 53, 0, 8, 0, 0]);
+        let mut frame = tcp_frame();
+        frame.extend(&[0, 0, 0, 0]);
+        frame.extend(&inner);
+        frame[14 + 3] += 4 + inner.len() as u8;
+
+        let (tree, pinfo) = run_with(tbl, frame);
+        assert!(!has_malformed(&tree));
+        let payload = ETHER_HDR_LEN + IPV4_HDR_LEN + TCP_HDR_LEN;
+        let ips: Vec<Option<usize>> = tree.nodes.iter().filter(|n| n.name == "ip").map(|n| n.offset).collect();
+        assert_eq!(ips, vec![Some(ETHER_HDR_LEN), Some(payload + 4)]);
+        let udp = tree.find("udp").unwrap();
+        assert_eq!(udp.offset, Some(payload + 4 + IPV4_HDR_LEN));
+        assert_eq!(tree.find("udp.dstport").unwrap().offset, Some(payload + 4 + IPV4_HDR_LEN + 2));
+        assert_eq!(pinfo.dst_port, Some(53));
+    }
+
+    #[test]
+    fn bytevector_views_are_placed_in_their_parent() {
+        let buf = [0u8; 16];
+        let outer = Some((buf.as_ptr() as usize, buf.len()));
+        let other = [0u8; 6];
+        let start = buf.as_ptr() as usize;
+        assert_eq!(view_offset(outer, start + 4, 6), Some(4));
+        assert_eq!(view_offset(outer, start, 16), Some(0));
+        assert_eq!(view_offset(outer, start + 12, 6), None);
+        assert_eq!(view_offset(outer, other.as_ptr() as usize, 6), None);
+        assert_eq!(view_offset(Some((start + 4, 8)), start, 16), None);
+        assert_eq!(view_offset(None, start + 4, 6), None);
+    }
+
+    #[test]
+    fn heuristics_take_port_table_names() {
+        let names: Vec<Option<&str>> = ["tcp", "tcp.port", "udp", "udp.port", "ethertype", "transport", "sctp"].iter()
+            .map(|t| heuristic_table_name(t))
+            .collect();
+        assert_eq!(names, vec![Some("tcp"), Some("tcp"), Some("udp"), Some("udp"), None, None, None]);
+    }
+
+    #[test]
+    fn table_keys_are_range_checked() {
+        let max: Vec<u32> = ["net", "transport", "tcp", "udp.port"].iter()
+            .map(|t| table_key_max(table_name(t).unwrap()))
+            .collect();
+        assert_eq!(max, vec![0xffff, 0xff, 0xffff, 0xffff]);
+    }
+
+    #[test]
+    fn ipv4_options_are_decoded() {
+        let opts = [
+            IPOPT_NOP,
+            IPOPT_RR, 11, 8, 192, 0, 2, 1, 192, 0, 2, 2,
+            IPOPT_RA, 4, 0, 0,
+        ];
+        let (tree, pinfo) = run(1, ipv4_frame(&opts));
+        assert!(!has_malformed(&tree));
+        assert_eq!(values(&tree, "ip.hdr_len"), vec!["36"]);
+        assert_eq!(values(&tree, "ip.src"), vec!["10.0.0.1"]);
+        assert_eq!(pinfo.src_port, Some(53));
+
+        let node = tree.find("ip").unwrap().children.iter()
+            .find(|n| n.label == "Options").expect("no options node");
+        assert_eq!(node.offset, Some(ETHER_HDR_LEN + IPV4_HDR_LEN));
+        assert_eq!(node.children.len(), 3);
+        assert_eq!(values(&tree, "ip.opt.type").len(), 3);
+        assert_eq!(values(&tree, "ip.opt.len"), vec!["11", "4"]);
+        assert_eq!(values(&tree, "ip.opt.ptr"), vec!["8"]);
+        assert_eq!(values(&tree, "ip.rec_rt"), vec!["192.0.2.1", "192.0.2.2"]);
+
+        let ra = tree.find("ip.opt.ra").expect("no router alert");
+        assert_eq!(ra.offset, Some(ETHER_HDR_LEN + IPV4_HDR_LEN + 14));
+        assert_eq!(ra.value, "Router shall examine packet (0)");
+    }
+
+    #[test]
+    fn ipv4_timestamp_option_is_decoded() {
+        let opts = [IPOPT_TS, 12, 13, 0x11, 192, 0, 2, 1, 0, 0, 0x01, 0x00];
+        let (tree, _) = run(1, ipv4_frame(&opts));
+        assert!(!has_malformed(&tree));
+        assert_eq!(values(&tree, "ip.opt.ts.ovfl"), vec!["1"]);
+        assert_eq!(values(&tree, "ip.opt.ts.flag"), vec!["1"]);
+        assert_eq!(values(&tree, "ip.opt.ts.addr"), vec!["192.0.2.1"]);
+        assert_eq!(values(&tree, "ip.opt.ts.stamp"), vec!["256"]);
+    }
+
+    #[test]
+    fn bad_ipv4_options_are_malformed() {
+        // The option claims more bytes than the header holds.
+        let (tree, _) = run(1, ipv4_frame(&[IPOPT_RR, 9, 4, 0]));
+        assert!(has_malformed(&tree));
+        assert!(tree.find("ip.rec_rt").is_none());
+        assert_eq!(values(&tree, "ip.dst"), vec!["10.0.0.2"]);
+
+        // A length below 2 would never advance.
+        let (tree, _) = run(1, ipv4_frame(&[IPOPT_NOP, IPOPT_RA, 1, 0]));
+        assert!(has_malformed(&tree));
+        assert_eq!(values(&tree, "ip.opt.type").len(), 1);
+
+        // The frame ends inside the options.
+        let mut frame = ipv4_frame(&[IPOPT_RA, 4, 0, 0]);
+        frame.truncate(ETHER_HDR_LEN + IPV4_HDR_LEN + 2);
+        let (tree, pinfo) = run(1, frame);
+        assert!(has_malformed(&tree));
+        assert!(tree.find("ip.opt").is_none());
+        assert_eq!(pinfo.info, Some(String::from("[Malformed Packet: IPv4]")));
+
+        let mut frame = ipv4_frame(&[]);
+        frame[ETHER_HDR_LEN] = 0x44;
+        let (tree, _) = run(1, frame);
+        assert!(has_malformed(&tree));
+    }
+
+    #[test]
+    fn ipv6_extension_headers_are_walked() {
+        let mut payload = vec![
+            // Hop-by-Hop: Router Alert (MLD) and a PadN.
+            IPPROTO_ROUTING, 0, 5, 2, 0, 0, 1, 0,
+            // Type 2 routing header with one address.
+            IPPROTO_FRAGMENT, 2, 2, 1, 0, 0, 0, 0,
+            0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3,
+            // Atomic fragment.
+            IPPROTO_AH, 0, 0, 0, 0x12, 0x34, 0x56, 0x78,
+            // AH with a 12 byte ICV.
+            IPPROTO_DSTOPTS, 4, 0, 0, 0, 0, 1, 0, 0, 0, 0, 7,
+            0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa,
+            // Destination Options: Pad1 and a PadN.
+            IPPROTO_UDP, 0, 0, 1, 3, 0, 0, 0,
+        ];
+        payload.extend(&[0, 53, 0, 53, 0, UDP_HDR_LEN as u8, 0, 0]);
+        let (tree, pinfo) = run(1, ipv6_frame(IPPROTO_HOPOPTS, &payload));
+        assert!(!has_malformed(&tree));
+        assert_eq!(pinfo.net_src, Some(String::from("2001:db8::1")));
+        assert_eq!(pinfo.src_port, Some(53));
+
+        assert_eq!(values(&tree, "ipv6.hopopts.nxt"), vec![IPPROTO_ROUTING.to_string()]);
+        let ra = tree.find("ipv6.opt").expect("no router alert");
+        assert_eq!(ra.label, "Router Alert");
+        assert_eq!(ra.value, "MLD (0)");
+        assert_eq!(ra.offset, Some(ETHER_HDR_LEN + IPV6_HDR_LEN + 2));
+
+        assert_eq!(values(&tree, "ipv6.routing.type"), vec!["2"]);
+        assert_eq!(values(&tree, "ipv6.routing.segleft"), vec!["1"]);
+        assert_eq!(values(&tree, "ipv6.routing.addr"), vec!["2001:db8::3"]);
+        assert_eq!(values(&tree, "ipv6.fragment.offset"), vec!["0"]);
+        assert_eq!(values(&tree, "ipv6.fragment.id"), vec![0x12345678.to_string()]);
+        assert_eq!(values(&tree, "ah.spi"), vec!["256"]);
+        assert_eq!(values(&tree, "ah.sequence"), vec!["7"]);
+        assert_eq!(values(&tree, "ipv6.dstopts.nxt"), vec![IPPROTO_UDP.to_string()]);
+        assert_eq!(tree.find("ipv6").unwrap().len, IPV6_HDR_LEN + 8 + 24 + 8 + 24 + 8);
+    }
+
+    #[test]
+    fn later_ipv6_fragments_stop_the_walk() {
+        let mut payload = vec![IPPROTO_UDP, 0, 0, 0x10, 0, 0, 0, 1];
+        payload.extend(&[0u8; 16]);
+        let (tree, pinfo) = run(1, ipv6_frame(IPPROTO_FRAGMENT, &payload));
+        assert!(!has_malformed(&tree));
+        assert_eq!(values(&tree, "ipv6.fragment.offset"), vec!["16"]);
+        assert!(tree.find("udp").is_none());
+        assert_eq!(tree.find("data").unwrap().len, 16);
+        assert_eq!(pinfo.info, Some(String::from("IPv6 fragment (next header 17)")));
+    }
+
+    #[test]
+    fn bad_ipv6_extension_headers_are_malformed() {
+        // The routing header claims 24 bytes but only 16 are in the payload.
+        let mut payload = vec![IPPROTO_NONE, 2, 2, 1, 0, 0, 0, 0];
+        payload.extend(&[0u8; 8]);
+        let (tree, pinfo) = run(1, ipv6_frame(IPPROTO_ROUTING, &payload));
+        assert!(has_malformed(&tree));
+        assert_eq!(pinfo.info, Some(String::from("[Malformed Packet: IPv6]")));
+        assert_eq!(values(&tree, "ipv6.dst"), vec!["2001:db8::2"]);
+
+        // Fewer than the 8 bytes every extension header has.
+        let (tree, _) = run(1, ipv6_frame(IPPROTO_DSTOPTS, &[IPPROTO_NONE, 0, 1, 0]));
+        assert!(has_malformed(&tree));
+
+        // An option running past the end of its header.
+        let (tree, _) = run(1, ipv6_frame(IPPROTO_HOPOPTS, &[IPPROTO_NONE, 0, 1, 9, 0, 0, 0, 0]));
+        assert!(has_malformed(&tree));
+        assert!(tree.find("ipv6.hopopts").is_some());
+        assert!(tree.find("ipv6.opt").is_none());
+    }
+
+    #[test]
+    fn tcp_options_are_decoded() {
+        let opts = [
+            TCPOPT_MSS, 4, 0x05, 0xb4,
+            TCPOPT_SACK_PERM, 2,
+            TCPOPT_TIMESTAMP, 10, 0, 0, 0, 1, 0, 0, 0, 2,
+            TCPOPT_NOP,
+            TCPOPT_WSCALE, 3, 7,
+        ];
+        let (tree, pinfo) = run(1, tcp_frame_with_options(&opts));
+        assert!(!has_malformed(&tree));
+        assert_eq!(values(&tree, "tcp.hdr_len"), vec!["40"]);
+        assert_eq!(values(&tree, "tcp.option_kind").len(), 4);
+        assert_eq!(values(&tree, "tcp.options.mss_val"), vec!["1460"]);
+        assert_eq!(values(&tree, "tcp.options.timestamp.tsval"), vec!["1"]);
+        assert_eq!(values(&tree, "tcp.options.timestamp.tsecr"), vec!["2"]);
+        assert_eq!(values(&tree, "tcp.options.wscale.shift"), vec!["7"]);
+        assert_eq!(values(&tree, "tcp.options.wscale.multiplier"), vec!["128"]);
+        let ts = tree.find("tcp.options.timestamp.tsval").unwrap();
+        assert_eq!(ts.offset, Some(ETHER_HDR_LEN + IPV4_HDR_LEN + TCP_HDR_LEN + 8));
+        assert!(pinfo.info.unwrap().ends_with(" MSS=1460 SACK_PERM TSval=1 TSecr=2 WS=128"));
+
+        let opts = [TCPOPT_NOP, TCPOPT_NOP, TCPOPT_SACK, 10, 0, 0, 0, 10, 0, 0, 0, 20];
+        let (tree, _) = run(1, tcp_frame_with_options(&opts));
+        assert!(!has_malformed(&tree));
+        assert_eq!(values(&tree, "tcp.options.sack_le"), vec!["10"]);
+        assert_eq!(values(&tree, "tcp.options.sack_re"), vec!["20"]);
+    }
+
+    #[test]
+    fn bad_tcp_options_are_malformed() {
+        // A known option with the wrong length.
+        let (tree, pinfo) = run(1, tcp_frame_with_options(&[TCPOPT_MSS, 3, 0, TCPOPT_NOP]));
+        assert!(has_malformed(&tree));
+        assert!(tree.find("tcp.options.mss_val").is_none());
+        assert_eq!(pinfo.src_port, Some(443));
+
+        // An option running past the end of the header.
+        let (tree, _) = run(1, tcp_frame_with_options(&[TCPOPT_NOP, TCPOPT_TIMESTAMP, 10, 0]));
+        assert!(has_malformed(&tree));
+        assert!(tree.find("tcp.options.timestamp.tsval").is_none());
+        assert_eq!(values(&tree, "tcp.option_kind").len(), 0);
+
+        // A length below 2 would never advance.
+        let (tree, _) = run(1, tcp_frame_with_options(&[TCPOPT_WSCALE, 0, 0, 0]));
+        assert!(has_malformed(&tree));
+
+        // The frame ends inside the options.
+        let mut frame = tcp_frame_with_options(&[TCPOPT_MSS, 4, 0x05, 0xb4]);
+        frame.truncate(frame.len() - 2);
+        frame[14 + 3] -= 2;
+        let (tree, pinfo) = run(1, frame);
+        assert!(has_malformed(&tree));
+        assert!(tree.find("tcp.options").is_none());
+        assert_eq!(pinfo.info, Some(String::from("[Malformed Packet: TCP]")));
+    }
+
+    fn icmpv6_frame(msg: &[u8]) -> Vec<u8> {
+        ipv6_frame(IPPROTO_ICMPV6, msg)
+    }
+
+    #[test]
+    fn icmpv6_echo_is_decoded() {
+        let (tree, pinfo) = run(1, icmpv6_frame(&[ICMP6_ECHO_REQUEST, 0, 0, 0, 0x12, 0x34, 0, 5, 0xde, 0xad]));
+        assert!(!has_malformed(&tree));
+        assert_eq!(values(&tree, "icmpv6.type"), vec!["128"]);
+        assert_eq!(values(&tree, "icmpv6.echo.identifier"), vec![0x1234.to_string()]);
+        assert_eq!(values(&tree, "icmpv6.echo.sequence_number"), vec!["5"]);
+        assert!(tree.find("icmpv6.checksum").unwrap().value.contains("incorrect"));
+        assert_eq!(pinfo.info, Some(String::from("Echo (ping) request (128) id=0x1234, seq=5")));
+    }
+
+    #[test]
+    fn icmpv6_neighbor_discovery_is_decoded() {
+        let mut na = vec![ND_NEIGHBOR_ADVERT, 0, 0, 0, 0x60, 0, 0, 0,
+                          0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+        na.extend(&[ND_OPT_TARGET_LINKADDR, 1, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let (tree, pinfo) = run(1, icmpv6_frame(&na));
+        assert!(!has_malformed(&tree));
+        assert_eq!(values(&tree, "icmpv6.nd.na.flag.r"), vec!["0"]);
+        assert_eq!(values(&tree, "icmpv6.nd.na.flag.s"), vec!["1"]);
+        assert_eq!(values(&tree, "icmpv6.nd.na.target_address"), vec!["2001:db8::2"]);
+        let ll = tree.find("icmpv6.opt.target_linkaddr").expect("no link-layer address");
+        assert_eq!(ll.offset, Some(ETHER_HDR_LEN + IPV6_HDR_LEN + 26));
+        assert_eq!(pinfo.info, Some(format!("Neighbor Advertisement 2001:db8::2 (sol, ovr) is at {}", ll.typed)));
+
+        let mut ra = vec![ND_ROUTER_ADVERT, 0, 0, 0, 64, 0x80, 0x07, 0x08, 0, 0, 0, 0, 0, 0, 0, 0];
+        ra.extend(&[ND_OPT_MTU, 1, 0, 0, 0, 0, 0x05, 0xdc]);
+        ra.extend(&[ND_OPT_PREFIX_INFORMATION, 4, 64, 0xc0, 0xff, 0xff, 0xff, 0xff, 0, 0, 0x0e, 0x10, 0, 0, 0, 0,
+                    0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let (tree, pinfo) = run(1, icmpv6_frame(&ra));
+        assert!(!has_malformed(&tree));
+        assert_eq!(values(&tree, "icmpv6.nd.ra.cur_hop_limit"), vec!["64"]);
+        assert_eq!(values(&tree, "icmpv6.nd.ra.flag.m"), vec!["1"]);
+        assert_eq!(values(&tree, "icmpv6.nd.ra.router_lifetime"), vec!["1800"]);
+        assert_eq!(values(&tree, "icmpv6.opt.mtu"), vec!["1500"]);
+        assert_eq!(values(&tree, "icmpv6.opt.prefix"), vec!["2001:db8::"]);
+        assert_eq!(values(&tree, "icmpv6.opt.prefix.length"), vec!["64"]);
+        assert_eq!(tree.find("icmpv6.opt.prefix.valid_lifetime").unwrap().value, "Infinity");
+        assert_eq!(tree.find("icmpv6.opt.prefix.preferred_lifetime").unwrap().value, "3600 s");
+        assert_eq!(pinfo.info, Some(String::from("Router Advertisement")));
+    }
+
+    #[test]
+    fn bad_icmpv6_messages_are_malformed() {
+        let (tree, pinfo) = run(1, icmpv6_frame(&[ICMP6_ECHO_REQUEST, 0]));
+        assert!(has_malformed(&tree));
+        assert_eq!(pinfo.info, Some(String::from("[Malformed Packet: ICMPv6]")));
+
+        // A Neighbor Solicitation cut off inside its target address.
+        let mut ns = vec![ND_NEIGHBOR_SOLICIT, 0, 0, 0, 0, 0, 0, 0];
+        ns.extend(&[0u8; 12]);
+        let (tree, pinfo) = run(1, icmpv6_frame(&ns));
+        assert!(has_malformed(&tree));
+        assert!(tree.find("icmpv6.nd.ns.target_address").is_none());
+        assert_eq!(pinfo.info, Some(String::from("[Malformed Packet: ICMPv6]")));
+
+        // Zero-length options are invalid and would never advance; the
+        // options before them are still shown.
+        let mut rs = vec![ND_ROUTER_SOLICIT, 0, 0, 0, 0, 0, 0, 0];
+        rs.extend(&[ND_OPT_SOURCE_LINKADDR, 1, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        rs.extend(&[ND_OPT_MTU, 0, 0, 0, 0, 0, 0, 0]);
+        let (tree, _) = run(1, icmpv6_frame(&rs));
+        assert!(has_malformed(&tree));
+        assert_eq!(values(&tree, "icmpv6.opt.type"), vec![ND_OPT_SOURCE_LINKADDR.to_string()]);
+
+        // An option running past the end of the message.
+        let mut rs = vec![ND_ROUTER_SOLICIT, 0, 0, 0, 0, 0, 0, 0];
+        rs.extend(&[ND_OPT_SOURCE_LINKADDR, 2, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let (tree, _) = run(1, icmpv6_frame(&rs));
+        assert!(has_malformed(&tree));
+        assert!(tree.find("icmpv6.opt").is_none());
+    }
+
+    fn filter_matches(filter: &str, tree: &ProtoTree) -> bool {
+        Filter::parse(filter).unwrap().matches(tree)
+    }
+
+    #[test]
+    fn display_filter_on_dissected_frames() {
+        let (tree, _) = run(7, ipv4_frame(&[]));
+        assert!(filter_matches("ip.src == 10.0.0.0/8 && ip.dst == 10.0.0.2", &tree));
+        assert!(filter_matches("ip.ttl >= 64 && udp.dstport == 53", &tree));
+        assert!(!filter_matches("ip.src == 10.0.0.2 || udp.port > 53", &tree));
+        assert!(!filter_matches("ip.ttl == 10.0.0.1", &tree));
+        assert!(filter_matches("frame.number == 7 && frame.len == 42", &tree));
+
+        let (tree, pinfo) = run(1, ipv6_frame(IPPROTO_UDP, &[0, 53, 0, 53, 0, UDP_HDR_LEN as u8, 0, 0]));
+        assert!(filter_matches("ipv6.src == 2001:db8::1 && ipv6.dst > 2001:db8::1", &tree));
+        assert!(!filter_matches("ipv6.addr == 2001:db8::3", &tree));
+        assert!(filter_matches(&format!("frame.protocol == \"{}\"", pinfo.proto.unwrap()), &tree));
+        assert!(filter_matches(&format!("frame.info == \"{}\"", pinfo.info.unwrap()), &tree));
+    }
+
+    #[test]
+    fn sexp_trees_can_be_filtered() {
+        let (_, mut pinfo) = run(1, tcp_frame());
+        pinfo.proto = Some(String::from("ARP"));
+        let tree = scheme_tree("((\"Address Resolution Protocol\" \"request\" 0 28) \
+                                 ((\"Opcode\" 1 6 2) () ((\"Sender IP address\" \"10.0.0.1\" 14 4) () ())) ())",
+                               &mut pinfo, 0);
+        assert_eq!(tree.nodes[0].name, "arp");
+        assert!(filter_matches("arp.opcode == 1 && arp.opcode < 2", &tree));
+        assert!(filter_matches("arp.sender_ip_address == 10.0.0.0/24", &tree));
+        assert!(!filter_matches("arp.opcode == 2", &tree));
+    }
+
+    #[test]
+    fn sexp_results_keep_nodes_from_next_dissectors() {
+        let (_, mut pinfo) = run(1, tcp_frame());
+        pinfo.proto = Some(String::from("Tunnel"));
+        let mark = pinfo.app_nodes.len();
+        // As left by call-next-dissector.
+        pinfo.app_nodes.push((None, FieldNode::proto("inner", "Inner Protocol", String::new(), 8, 4)));
+        let tree = scheme_tree("((\"Tunnel Protocol\" \"\" 0 8) ((\"Key\" 7 4 4) () ()) ())", &mut pinfo, mark);
+        let names: Vec<&str> = tree.nodes.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, vec!["tunnel", "inner"]);
+        assert_eq!(tree.nodes[0].children[0].name, "tunnel.key");
+        assert_eq!(pinfo.app_nodes.len(), mark);
+
+        // Nothing returned but the nodes added.
+        pinfo.app_nodes.push((None, FieldNode::proto("inner", "Inner Protocol", String::new(), 8, 4)));
+        let tree = scheme_tree("#<unspecified>", &mut pinfo, mark);
+        assert_eq!(tree.nodes.len(), 1);
+        assert_eq!(tree.nodes[0].name, "inner");
+    }
+
+    #[test]
+    fn queue_labels_fragments_before_handing_them_on() {
+        let tbl = Rc::new(RefCell::new(DissectorTable::new()));
+        let convs = Rc::new(RefCell::new(ConversationTable::new()));
+        let mut queue = FrameQueue::new(convs.clone());
+        let frames = vec![
+            ipv4_fragment_frame(7, 0, true, &[0, 53, 0, 53, 0, 16, 0, 0]),
+            ipv4_frame(&[]),
+            ipv4_fragment_frame(7, 8, false, b"abcdefgh"),
+            ipv4_fragment_frame(8, 0, true, &[0, 53, 0, 53, 0, 16, 0, 0]),
+        ];
+        let mut ready = Vec::new();
+        for (i, f) in frames.into_iter().enumerate() {
+            let (tree, pinfo) = dissect(i as u32 + 1, header(f.len()), f, tbl.clone(), convs.clone());
+            ready.push(queue.push(tree, pinfo, i).into_iter().map(|(_, _, i)| i).collect::<Vec<usize>>());
+        }
+        // The unfragmented second frame waits behind the first.
+        assert_eq!(ready, vec![vec![], vec![], vec![0, 1, 2], vec![]]);
+
+        let rest = queue.finish();
+        assert_eq!(rest.len(), 1);
+        assert!(rest[0].1.info.as_ref().unwrap().starts_with("Fragmented IP protocol (proto="));
+    }
+
+    #[test]
+    fn fragments_are_labelled_with_the_reassembling_frame() {
+        let frames = vec![
+            ipv4_fragment_frame(7, 0, true, &[0, 53, 0, 53, 0, 16, 0, 0]),
+            ipv4_fragment_frame(7, 8, false, b"abcdefgh"),
+        ];
+        let tbl = Rc::new(RefCell::new(DissectorTable::new()));
+        let convs = Rc::new(RefCell::new(ConversationTable::new()));
+        let mut queue = FrameQueue::new(convs.clone());
+        let mut out = Vec::new();
+        for (i, f) in frames.into_iter().enumerate() {
+            let (tree, pinfo) = dissect(i as u32 + 1, header(f.len()), f, tbl.clone(), convs.clone());
+            out.extend(queue.push(tree, pinfo, ()));
+        }
+
+        let (ref tree, ref pinfo, _) = out[0];
+        assert_eq!(pinfo.info, Some(String::from("Fragmented IP protocol (reassembled in #2)")));
+        assert_eq!(values(tree, "ip.reassembled_in"), vec!["2"]);
+        assert!(filter_matches("frame.info contains \"reassembled in #2\"", tree));
+        assert_eq!(values(&out[1].0, "ip.fragment"), vec!["1", "2"]);
+    }
+}
@@ -0,0 +1,74 @@
+(use-modules (rnrs bytevectors))
+(use-modules (system foreign))
+(use-modules (ice-9 format))
+;; Registration. tbl is 'ethertype, 'ip.proto, 'tcp.port or 'udp.port
+;; ('net, 'transport, 'tcp and 'udp still work). A built-in dissector
+;; registered under the same key is replaced.
+(define (register-dissector tbl num dsctr)
+  (set-dissector dissector-table tbl num dsctr))
+
+;; Heuristic dissectors are tried on TCP or UDP payloads no port
+;; dissector claimed, so tbl is 'tcp.port or 'udp.port ('tcp, 'udp).
+(define (register-heuristic-dissector tbl name priority dsctr)
+  (set-heuristic-dissector dissector-table tbl name priority dsctr))
+
+
+;; Tree builders. Both return a handle that can be passed as the parent
+;; of later nodes; use #f as the parent for a top-level node. Leave out
+;; offset and len for generated fields.
+(define* (add-field pinfo parent name label value #:optional offset len)
+  (%add-field pinfo parent name label
+              (if (or (string? value) (integer? value)) value (object->string value))
+              offset len))
+
+(define* (add-subtree pinfo parent name label #:optional (summary "") offset len)
+  (%add-subtree pinfo parent name label summary offset len))
+
+;; Typed readers.
+(define (uint8 bv offset)
+  (bytevector-u8-ref bv offset))
+
+(define (uint16-be bv offset)
+  (bytevector-u16-ref bv offset (endianness big)))
+
+(define (uint32-be bv offset)
+  (bytevector-u32-ref bv offset (endianness big)))
+
+(define (ipv4-addr bv offset)
+  (inet-ntop AF_INET (bytevector-u32-ref bv offset (endianness big))))
+
+(define (mac-addr bv offset)
+  (string-join
+    (map (lambda (i) (format #f "~2,'0x" (bytevector-u8-ref bv (+ offset i))))
+         (iota 6))
+    ":"))
+
+(define (string-z bv offset)
+  (let loop ((end offset))
+    (if (or (= end (bytevector-length bv))
+            (zero? (bytevector-u8-ref bv end)))
+        (let ((s (make-bytevector (- end offset))))
+          (bytevector-copy! bv offset s 0 (- end offset))
+          (utf8->string s))
+        (loop (+ end 1)))))
+
+;; The len bytes of bv from offset, sharing bv's storage rather than
+;; copying it. They must all lie within bv.
+(define (sub-bytevector bv offset len)
+  (if (and (>= offset 0) (>= len 0)
+           (<= (+ offset len) (bytevector-length bv)))
+      (pointer->bytevector (bytevector->pointer bv offset) len)
+      (error "sub-bytevector: range out of bounds" offset len
+             (bytevector-length bv))))
+
+;; Dissects bv with whatever is registered under key in tbl, built-in
+;; dissectors included. Returns #f if nothing is registered. offset is
+;; where bv starts in the bytes this dissector was handed; it can be
+;; left out for a sub-bytevector of them, but a copy needs it.
+(define* (call-next-dissector tbl key bv pinfo #:optional offset)
+  (not (zero? (%call-next-dissector tbl key bv pinfo offset))))
+
+;; Used by the host to report errors caught in plugin code.
+(define (%exception->string key args)
+  (call-with-output-string
+    (lambda (port) (print-exception port #f key args))))
@@ -0,0 +1,299 @@
+// Packet list export as CSV and dissection export as JSON or PDML, the
+// XML format read by tools built around Wireshark's `tshark -T pdml`.
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+
+use time;
+
+use disctr::PacketInfo;
+use proto_tree::{ProtoTree, FieldNode, FieldValue};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Csv,
+    Json,
+    Pdml,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "csv" => Some(Format::Csv),
+            "json" => Some(Format::Json),
+            "pdml" => Some(Format::Pdml),
+            _ => None,
+        }
+    }
+}
+
+// One row of the packet list.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Summary {
+    pub num: u32,
+    pub time: String,
+    pub source: String,
+    pub destination: String,
+    pub protocol: String,
+    pub length: u32,
+    pub info: String,
+}
+
+fn col(s: &Option<String>) -> String {
+    s.clone().unwrap_or_default()
+}
+
+impl<'a> From<&'a PacketInfo> for Summary {
+    fn from(pinfo: &PacketInfo) -> Summary {
+        Summary {
+            num: pinfo.num,
+            time: time::strftime("%F %T", &time::at(pinfo.time)).unwrap(),
+            source: col(&pinfo.net_src),
+            destination: col(&pinfo.net_dst),
+            protocol: col(&pinfo.proto),
+            length: pinfo.len,
+            info: col(&pinfo.info),
+        }
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { write!(out, "\\u{:04x}", c as u32).unwrap(); },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn xml_attr(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\n' => out.push_str("&#10;"),
+            '\t' => out.push_str("&#9;"),
+            // Not representable in XML 1.0 at all.
+            c if (c as u32) < 0x20 => out.push_str("\u{fffd}"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// The bytes a field covers, as plain hex. Fields without a position in
+// the frame, such as generated ones, have none.
+fn raw_hex(node: &FieldNode, data: &[u8]) -> Option<String> {
+    let off = node.offset?;
+    if node.len == 0 || off + node.len > data.len() {
+        return None;
+    }
+    let mut hex = String::with_capacity(node.len * 2);
+    for b in &data[off..off + node.len] {
+        write!(hex, "{:02x}", b).unwrap();
+    }
+    Some(hex)
+}
+
+fn json_value(v: &FieldValue) -> String {
+    match *v {
+        FieldValue::None => String::from("null"),
+        FieldValue::UInt(n) => n.to_string(),
+        FieldValue::Int(n) => n.to_string(),
+        _ => json_str(&v.to_string()),
+    }
+}
+
+fn json_nodes(nodes: &[FieldNode], data: &[u8], out: &mut String) {
+    out.push('[');
+    for (i, n) in nodes.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write!(out, "{{\"name\":{},\"label\":{},\"display\":{},\"value\":{},\"raw\":{},\"offset\":{},\"length\":{},\"hidden\":{},\"fields\":",
+               json_str(&n.name), json_str(&n.label), json_str(&n.value), json_value(&n.typed),
+               raw_hex(n, data).map_or(String::from("null"), |h| json_str(&h)),
+               n.offset.map_or(String::from("null"), |o| o.to_string()),
+               n.len, n.hidden).unwrap();
+        json_nodes(&n.children, data, out);
+        out.push('}');
+    }
+    out.push(']');
+}
+
+fn pdml_nodes(nodes: &[FieldNode], data: &[u8], depth: usize, out: &mut String) {
+    for n in nodes {
+        let tag = if depth == 1 { "proto" } else { "field" };
+        let show = match n.typed {
+            FieldValue::None => n.value.clone(),
+            ref v => v.to_string(),
+        };
+        write!(out, "{:indent$}<{} name=\"{}\" showname=\"{}\" size=\"{}\" pos=\"{}\" show=\"{}\"",
+               "", tag, xml_attr(&n.name), xml_attr(&n.display_text()), n.len,
+               n.offset.unwrap_or(0), xml_attr(&show), indent = depth * 2).unwrap();
+        if let Some(hex) = raw_hex(n, data) {
+            write!(out, " value=\"{}\"", hex).unwrap();
+        }
+        if n.hidden {
+            out.push_str(" hide=\"yes\"");
+        }
+        if n.children.is_empty() {
+            out.push_str("/>\n");
+        } else {
+            out.push_str(">\n");
+            pdml_nodes(&n.children, data, depth + 1, out);
+            write!(out, "{:indent$}</{}>\n", "", tag, indent = depth * 2).unwrap();
+        }
+    }
+}
+
+// Writes packets one at a time, so a live capture can be exported as it
+// arrives. CSV only uses the summary; JSON and PDML only the tree and
+// the frame bytes the tree's offsets refer to.
+pub struct Exporter<W: Write> {
+    w: W,
+    format: Format,
+    count: usize,
+}
+
+impl<W: Write> Exporter<W> {
+    pub fn new(mut w: W, format: Format) -> io::Result<Exporter<W>> {
+        match format {
+            Format::Csv => writeln!(w, "\"No.\",\"Time\",\"Source\",\"Destination\",\"Protocol\",\"Length\",\"Info\"")?,
+            Format::Json => writeln!(w, "[")?,
+            Format::Pdml => {
+                writeln!(w, "<?xml version=\"1.0\" encoding=\"utf-8\"?>")?;
+                writeln!(w, "<pdml version=\"0\" creator=\"wire_shake/{}\">", env!("CARGO_PKG_VERSION"))?;
+            },
+        }
+        Ok(Exporter { w: w, format: format, count: 0 })
+    }
+
+    pub fn packet(&mut self, summary: &Summary, tree: &ProtoTree, data: &[u8]) -> io::Result<()> {
+        match self.format {
+            Format::Csv => {
+                writeln!(self.w, "{},{},{},{},{},{},{}",
+                         csv_field(&summary.num.to_string()), csv_field(&summary.time),
+                         csv_field(&summary.source), csv_field(&summary.destination),
+                         csv_field(&summary.protocol), csv_field(&summary.length.to_string()),
+                         csv_field(&summary.info))?;
+            },
+            Format::Json => {
+                let mut out = String::new();
+                if self.count > 0 {
+                    out.push_str(",\n");
+                }
+                write!(out, "{{\"number\":{},\"layers\":", summary.num).unwrap();
+                json_nodes(&tree.nodes, data, &mut out);
+                out.push('}');
+                self.w.write_all(out.as_bytes())?;
+            },
+            Format::Pdml => {
+                let mut out = String::from("<packet>\n");
+                pdml_nodes(&tree.nodes, data, 1, &mut out);
+                out.push_str("</packet>\n");
+                self.w.write_all(out.as_bytes())?;
+            },
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.format {
+            Format::Csv => {},
+            Format::Json => {
+                if self.count > 0 {
+                    writeln!(self.w)?;
+                }
+                writeln!(self.w, "]")?;
+            },
+            Format::Pdml => writeln!(self.w, "</pdml>")?,
+        }
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary() -> Summary {
+        Summary {
+            num: 1,
+            time: String::from("2017-12-01 10:00:00"),
+            source: String::from("10.0.0.1"),
+            destination: String::from("10.0.0.2"),
+            protocol: String::from("HTTP"),
+            length: 60,
+            info: String::from("GET /a,b \"c\""),
+        }
+    }
+
+    fn tree() -> ProtoTree {
+        let mut tree = ProtoTree::new();
+        {
+            let ip = tree.add(FieldNode::proto("ip", "Internet Protocol Version 4",
+                                               String::from("Src: 10.0.0.1"), 0, 4));
+            ip.add(FieldNode::new("ip.src", "Source", FieldValue::Ipv4("10.0.0.1".parse().unwrap()), 0, 4));
+            ip.add(FieldNode::generated("ip.len", "Length", FieldValue::UInt(4)));
+            ip.add(FieldNode::text("Note", String::from("<a & b>")));
+        }
+        tree
+    }
+
+    fn export(format: Format) -> String {
+        let mut e = Exporter::new(Vec::new(), format).unwrap();
+        e.packet(&summary(), &tree(), &[10, 0, 0, 1]).unwrap();
+        String::from_utf8(e.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn csv_quotes_every_field() {
+        let out = export(Format::Csv);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1], "\"1\",\"2017-12-01 10:00:00\",\"10.0.0.1\",\"10.0.0.2\",\"HTTP\",\"60\",\"GET /a,b \"\"c\"\"\"");
+    }
+
+    #[test]
+    fn json_has_nested_fields_with_raw_bytes() {
+        let out = export(Format::Json);
+        assert!(out.starts_with("[\n{\"number\":1,\"layers\":[{\"name\":\"ip\""));
+        assert!(out.contains("{\"name\":\"ip.src\",\"label\":\"Source\",\"display\":\"10.0.0.1\",\"value\":\"10.0.0.1\",\"raw\":\"0a000001\",\"offset\":0,\"length\":4,\"hidden\":false,\"fields\":[]}"));
+        assert!(out.contains("{\"name\":\"ip.len\",\"label\":\"Length\",\"display\":\"4\",\"value\":4,\"raw\":null,\"offset\":null,"));
+        assert!(out.ends_with("}]}]}\n]\n"));
+    }
+
+    #[test]
+    fn empty_json_export_is_an_empty_array() {
+        let e = Exporter::new(Vec::new(), Format::Json).unwrap();
+        assert_eq!(String::from_utf8(e.finish().unwrap()).unwrap(), "[\n]\n");
+    }
+
+    #[test]
+    fn pdml_nests_fields_under_protos() {
+        let out = export(Format::Pdml);
+        assert!(out.contains("<packet>\n  <proto name=\"ip\" showname=\"Internet Protocol Version 4: Src: 10.0.0.1\" size=\"4\" pos=\"0\" show=\"Src: 10.0.0.1\" value=\"0a000001\">\n"));
+        assert!(out.contains("    <field name=\"ip.src\" showname=\"Source: 10.0.0.1\" size=\"4\" pos=\"0\" show=\"10.0.0.1\" value=\"0a000001\"/>\n"));
+        assert!(out.contains("    <field name=\"\" showname=\"Note: &lt;a &amp; b&gt;\" size=\"0\" pos=\"0\" show=\"&lt;a &amp; b&gt;\"/>\n"));
+        assert!(out.ends_with("  </proto>\n</packet>\n</pdml>\n"));
+    }
+}
@@ -0,0 +1,35 @@
+// The dissection engine behind wire_shake: capture file reading and
+// writing, the dissectors and their tables, the protocol tree, the
+// display filter and CSV/JSON/PDML export. Nothing here depends on GTK.
+//
+// A batch job only needs a DissectorTable and a ConversationTable:
+//
+//     let tbl = Rc::new(RefCell::new(DissectorTable::new()));
+//     let convs = Rc::new(RefCell::new(ConversationTable::new()));
+//     for (n, rec) in pcapfile::open(path)?.enumerate() {
+//         let (hdr, data) = rec?;
+//         let (tree, pinfo) = dissect(n as u32 + 1, hdr, data, tbl.clone(), convs.clone());
+//     }
+//
+// Scheme dissector plugins additionally need `scheme::init` and
+// `plugin::load_plugins`.
+
+extern crate libc;
+extern crate byteorder;
+extern crate time;
+extern crate sexp;
+extern crate guile_sys;
+
+pub mod disctr;
+pub mod pcapfile;
+pub mod dfilter;
+pub mod proto_tree;
+pub mod conversation;
+pub mod scheme;
+pub mod plugin;
+pub mod export;
+
+pub use disctr::{dissect, Dissector, DissectorHandle, DissectorTable, PacketInfo, Tvb};
+pub use pcapfile::FrameHeader;
+pub use proto_tree::{ProtoTree, FieldNode, FieldValue};
+pub use conversation::ConversationTable;
@@ -0,0 +1,635 @@
+use std::fs::File;
+use std::io::{self, Read, Write, BufReader, BufWriter};
+use std::path::Path;
+use byteorder::{ByteOrder, WriteBytesExt, BigEndian, LittleEndian};
+use time::Timespec;
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NSEC: u32 = 0xa1b23c4d;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const PCAP_HDR_LEN: usize = 24;
+const PCAP_REC_HDR_LEN: usize = 16;
+
+const PCAPNG_SHB: u32 = 0x0a0d0d0a;
+const PCAPNG_IDB: u32 = 0x00000001;
+const PCAPNG_PB: u32 = 0x00000002;
+const PCAPNG_SPB: u32 = 0x00000003;
+const PCAPNG_EPB: u32 = 0x00000006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const PCAPNG_OPT_ENDOFOPT: u16 = 0;
+const PCAPNG_IF_TSRESOL: u16 = 9;
+
+pub const LINKTYPE_ETHERNET: u32 = 1;
+const SNAPLEN: u32 = 65535;
+
+// Anything bigger is taken to mean the file is corrupt, rather than
+// allocating whatever a bad length field asks for.
+const MAX_BLOCK_LEN: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Pcap,
+    Pcapng,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("pcapng") | Some("ntar") => Format::Pcapng,
+            _ => Format::Pcap,
+        }
+    }
+}
+
+// The link-layer type and timestamp resolution of a capture, which the
+// writer keeps when saving it again.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Link {
+    pub linktype: u32,
+    // Timestamp ticks per second.
+    pub ts_units: u64,
+}
+
+pub const ETHERNET: Link = Link { linktype: LINKTYPE_ETHERNET, ts_units: 1_000_000 };
+
+impl Link {
+    // Anything finer than microseconds is written in nanoseconds, which is
+    // all a Timespec holds.
+    fn nsec(&self) -> bool {
+        self.ts_units > 1_000_000
+    }
+}
+
+// The per-frame record header, whichever format it came from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FrameHeader {
+    pub ts: Timespec,
+    pub caplen: u32,
+    pub len: u32,
+}
+
+fn pad4(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+fn ts_frac(ts: &Timespec, nsec: bool) -> u64 {
+    if nsec { ts.nsec as u64 } else { (ts.nsec / 1000) as u64 }
+}
+
+fn write_pcap_header<W: Write>(w: &mut W, link: &Link) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(if link.nsec() { PCAP_MAGIC_NSEC } else { PCAP_MAGIC })?;
+    w.write_u16::<LittleEndian>(PCAP_VERSION_MAJOR)?;
+    w.write_u16::<LittleEndian>(PCAP_VERSION_MINOR)?;
+    w.write_i32::<LittleEndian>(0)?; // thiszone
+    w.write_u32::<LittleEndian>(0)?; // sigfigs
+    w.write_u32::<LittleEndian>(SNAPLEN)?;
+    w.write_u32::<LittleEndian>(link.linktype)
+}
+
+fn write_pcap_record<W: Write>(w: &mut W, link: &Link, hdr: &FrameHeader, data: &[u8]) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(hdr.ts.sec as u32)?;
+    w.write_u32::<LittleEndian>(ts_frac(&hdr.ts, link.nsec()) as u32)?;
+    w.write_u32::<LittleEndian>(data.len() as u32)?;
+    w.write_u32::<LittleEndian>(hdr.len)?;
+    w.write_all(data)
+}
+
+fn write_pcapng_header<W: Write>(w: &mut W, link: &Link) -> io::Result<()> {
+    // The Interface Description Block has only 16 bits for it.
+    if link.linktype > 0xffff {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("link-layer type {} cannot be saved as pcapng", link.linktype)));
+    }
+
+    // Section Header Block, no options
+    w.write_u32::<LittleEndian>(PCAPNG_SHB)?;
+    w.write_u32::<LittleEndian>(28)?;
+    w.write_u32::<LittleEndian>(PCAPNG_BYTE_ORDER_MAGIC)?;
+    w.write_u16::<LittleEndian>(1)?;
+    w.write_u16::<LittleEndian>(0)?;
+    w.write_i64::<LittleEndian>(-1)?; // section length unknown
+    w.write_u32::<LittleEndian>(28)?;
+
+    // Interface Description Block, microsecond resolution unless an
+    // if_tsresol option says otherwise
+    let blk_len = if link.nsec() { 32 } else { 20 };
+    w.write_u32::<LittleEndian>(PCAPNG_IDB)?;
+    w.write_u32::<LittleEndian>(blk_len)?;
+    w.write_u16::<LittleEndian>(link.linktype as u16)?;
+    w.write_u16::<LittleEndian>(0)?;
+    w.write_u32::<LittleEndian>(SNAPLEN)?;
+    if link.nsec() {
+        w.write_u16::<LittleEndian>(PCAPNG_IF_TSRESOL)?;
+        w.write_u16::<LittleEndian>(1)?;
+        w.write_all(&[9, 0, 0, 0])?;
+        w.write_u16::<LittleEndian>(PCAPNG_OPT_ENDOFOPT)?;
+        w.write_u16::<LittleEndian>(0)?;
+    }
+    w.write_u32::<LittleEndian>(blk_len)
+}
+
+fn write_pcapng_record<W: Write>(w: &mut W, link: &Link, hdr: &FrameHeader, data: &[u8]) -> io::Result<()> {
+    let pad = pad4(data.len());
+    let blk_len = (32 + data.len() + pad) as u32;
+    let units = if link.nsec() { 1_000_000_000 } else { 1_000_000 };
+    let ts = (hdr.ts.sec as u64) * units + ts_frac(&hdr.ts, link.nsec());
+
+    w.write_u32::<LittleEndian>(PCAPNG_EPB)?;
+    w.write_u32::<LittleEndian>(blk_len)?;
+    w.write_u32::<LittleEndian>(0)?; // interface id
+    w.write_u32::<LittleEndian>((ts >> 32) as u32)?;
+    w.write_u32::<LittleEndian>(ts as u32)?;
+    w.write_u32::<LittleEndian>(data.len() as u32)?;
+    w.write_u32::<LittleEndian>(hdr.len)?;
+    w.write_all(data)?;
+    w.write_all(&[0u8; 3][..pad])?;
+    w.write_u32::<LittleEndian>(blk_len)
+}
+
+pub fn write_to<'a, W, I>(w: &mut W, format: Format, link: &Link, frames: I) -> io::Result<()>
+    where W: Write, I: Iterator<Item = (&'a FrameHeader, &'a [u8])>
+{
+    match format {
+        Format::Pcap => {
+            write_pcap_header(w, link)?;
+            for (hdr, data) in frames {
+                write_pcap_record(w, link, hdr, data)?;
+            }
+        },
+        Format::Pcapng => {
+            write_pcapng_header(w, link)?;
+            for (hdr, data) in frames {
+                write_pcapng_record(w, link, hdr, data)?;
+            }
+        },
+    }
+    Ok(())
+}
+
+pub fn write<'a, I>(path: &Path, format: Format, link: &Link, frames: I) -> io::Result<()>
+    where I: Iterator<Item = (&'a FrameHeader, &'a [u8])>
+{
+    let mut w = BufWriter::new(File::create(path)?);
+    write_to(&mut w, format, link, frames)?;
+    w.flush()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Like read_exact, except that running out of input before the first
+// byte is a clean end of file and returns false.
+fn read_or_eof<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut got = 0;
+    while got < buf.len() {
+        match r.read(&mut buf[got..]) {
+            Ok(0) if got == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated record")),
+            Ok(n) => got += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+// `units` is timestamp ticks per second.
+fn timespec(sec: u64, frac: u64, units: u64) -> Timespec {
+    let sec = sec + frac / units;
+    let nsec = ((frac % units) as u128 * 1_000_000_000 / units as u128) as i32;
+    Timespec::new(sec as i64, nsec)
+}
+
+#[derive(Clone, Copy)]
+struct Interface {
+    linktype: u32,
+    ts_units: u64,
+}
+
+// Reads pcap (either byte order, micro- or nanosecond) and pcapng files.
+// Iteration stops after the first error.
+pub struct Reader<R> {
+    r: R,
+    format: Format,
+    big_endian: bool,
+    // A pcap file has exactly one; a pcapng section has one per
+    // Interface Description Block.
+    interfaces: Vec<Interface>,
+    done: bool,
+}
+
+pub fn open(path: &Path) -> io::Result<Reader<BufReader<File>>> {
+    Reader::new(BufReader::new(File::open(path)?))
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut r: R) -> io::Result<Reader<R>> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        let magic = LittleEndian::read_u32(&magic);
+        if magic == PCAPNG_SHB {
+            let mut rdr = Reader { r: r, format: Format::Pcapng, big_endian: false, interfaces: Vec::new(), done: false };
+            rdr.read_block_body(PCAPNG_SHB)?;
+            rdr.read_first_interface()?;
+            return Ok(rdr);
+        }
+
+        let (big_endian, ts_units) = match magic {
+            PCAP_MAGIC => (false, 1_000_000),
+            PCAP_MAGIC_NSEC => (false, 1_000_000_000),
+            m if m == PCAP_MAGIC.swap_bytes() => (true, 1_000_000),
+            m if m == PCAP_MAGIC_NSEC.swap_bytes() => (true, 1_000_000_000),
+            _ => return Err(invalid("not a pcap or pcapng file")),
+        };
+        let mut hdr = [0u8; PCAP_HDR_LEN - 4];
+        r.read_exact(&mut hdr)?;
+        let mut rdr = Reader { r: r, format: Format::Pcap, big_endian: big_endian, interfaces: Vec::new(), done: false };
+        let linktype = rdr.u32(&hdr[16..20]);
+        rdr.interfaces.push(Interface { linktype: linktype, ts_units: ts_units });
+        Ok(rdr)
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    // The link-layer type of the first interface; None for a pcapng file
+    // that describes no interfaces.
+    pub fn linktype(&self) -> Option<u32> {
+        self.interfaces.first().map(|i| i.linktype)
+    }
+
+    // The link-layer type and timestamp resolution of the first
+    // interface, to save the frames with.
+    pub fn link(&self) -> Option<Link> {
+        self.interfaces.first().map(|i| Link { linktype: i.linktype, ts_units: i.ts_units })
+    }
+
+    fn u16(&self, b: &[u8]) -> u16 {
+        if self.big_endian { BigEndian::read_u16(b) } else { LittleEndian::read_u16(b) }
+    }
+
+    fn u32(&self, b: &[u8]) -> u32 {
+        if self.big_endian { BigEndian::read_u32(b) } else { LittleEndian::read_u32(b) }
+    }
+
+    // Reads the rest of a pcapng block whose type has been read and
+    // returns its body, without the length fields. A Section Header Block
+    // sets the byte order for the blocks after it.
+    fn read_block_body(&mut self, typ: u32) -> io::Result<Vec<u8>> {
+        let mut len = [0u8; 4];
+        self.r.read_exact(&mut len)?;
+        let mut fixed = 12;
+        if typ == PCAPNG_SHB {
+            let mut bom = [0u8; 4];
+            self.r.read_exact(&mut bom)?;
+            self.big_endian = match LittleEndian::read_u32(&bom) {
+                PCAPNG_BYTE_ORDER_MAGIC => false,
+                m if m == PCAPNG_BYTE_ORDER_MAGIC.swap_bytes() => true,
+                _ => return Err(invalid("bad pcapng byte-order magic")),
+            };
+            self.interfaces.clear();
+            fixed = 16;
+        }
+        let len = self.u32(&len) as usize;
+        if len < fixed || len % 4 != 0 || len > MAX_BLOCK_LEN {
+            return Err(invalid("bad pcapng block length"));
+        }
+        let mut body = vec![0u8; len - fixed];
+        self.r.read_exact(&mut body)?;
+        let mut trailer = [0u8; 4];
+        self.r.read_exact(&mut trailer)?;
+        Ok(body)
+    }
+
+    fn next_block(&mut self) -> io::Result<Option<(u32, Vec<u8>)>> {
+        let mut typ = [0u8; 4];
+        if !read_or_eof(&mut self.r, &mut typ)? {
+            return Ok(None);
+        }
+        let typ = self.u32(&typ);
+        let body = self.read_block_body(typ)?;
+        Ok(Some((typ, body)))
+    }
+
+    // Reads up to the first Interface Description Block so `linktype` is
+    // known before any packet is read.
+    fn read_first_interface(&mut self) -> io::Result<()> {
+        while self.interfaces.is_empty() {
+            match self.next_block()? {
+                Some((PCAPNG_IDB, body)) => self.add_interface(&body)?,
+                Some((PCAPNG_EPB, _)) | Some((PCAPNG_SPB, _)) | Some((PCAPNG_PB, _)) => {
+                    return Err(invalid("pcapng packet before any interface description"));
+                },
+                Some(_) => {},
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn add_interface(&mut self, body: &[u8]) -> io::Result<()> {
+        if body.len() < 8 {
+            return Err(invalid("short pcapng interface description"));
+        }
+        let mut iface = Interface { linktype: self.u16(&body[0..2]) as u32, ts_units: 1_000_000 };
+        let mut i = 8;
+        while i + 4 <= body.len() {
+            let code = self.u16(&body[i..i + 2]);
+            let len = self.u16(&body[i + 2..i + 4]) as usize;
+            if code == PCAPNG_OPT_ENDOFOPT || i + 4 + len > body.len() {
+                break;
+            }
+            if code == PCAPNG_IF_TSRESOL && len >= 1 {
+                // High bit set: a power of two, otherwise a power of ten.
+                let res = body[i + 4];
+                let units = if res & 0x80 != 0 {
+                    2u64.checked_pow((res & 0x7f) as u32)
+                } else {
+                    10u64.checked_pow(res as u32)
+                };
+                iface.ts_units = units.ok_or_else(|| invalid("unsupported pcapng timestamp resolution"))?;
+            }
+            i += 4 + len + pad4(len);
+        }
+        self.interfaces.push(iface);
+        Ok(())
+    }
+
+    fn interface(&self, id: usize) -> io::Result<Interface> {
+        self.interfaces.get(id).cloned().ok_or_else(|| invalid("pcapng packet on an undescribed interface"))
+    }
+
+    fn pcapng_packet(&self, typ: u32, body: &[u8]) -> io::Result<(FrameHeader, Vec<u8>)> {
+        if typ == PCAPNG_SPB {
+            // No timestamp and no captured length; the data runs to the
+            // end of the block, padding included.
+            if body.len() < 4 {
+                return Err(invalid("short pcapng simple packet block"));
+            }
+            self.interface(0)?;
+            let len = self.u32(&body[0..4]);
+            let caplen = ::std::cmp::min(len as usize, body.len() - 4);
+            let hdr = FrameHeader { ts: Timespec::new(0, 0), caplen: caplen as u32, len: len };
+            return Ok((hdr, body[4..4 + caplen].to_vec()));
+        }
+
+        if body.len() < 20 {
+            return Err(invalid("short pcapng packet block"));
+        }
+        let id = if typ == PCAPNG_PB { self.u16(&body[0..2]) as u32 } else { self.u32(&body[0..4]) };
+        let iface = self.interface(id as usize)?;
+        let ts = (self.u32(&body[4..8]) as u64) << 32 | self.u32(&body[8..12]) as u64;
+        let caplen = self.u32(&body[12..16]);
+        let len = self.u32(&body[16..20]);
+        if 20 + caplen as usize > body.len() {
+            return Err(invalid("pcapng captured length exceeds its block"));
+        }
+        let hdr = FrameHeader { ts: timespec(ts / iface.ts_units, ts % iface.ts_units, iface.ts_units), caplen: caplen, len: len };
+        Ok((hdr, body[20..20 + caplen as usize].to_vec()))
+    }
+
+    fn next_pcapng(&mut self) -> io::Result<Option<(FrameHeader, Vec<u8>)>> {
+        loop {
+            match self.next_block()? {
+                Some((PCAPNG_IDB, body)) => self.add_interface(&body)?,
+                Some((typ, body)) if typ == PCAPNG_EPB || typ == PCAPNG_SPB || typ == PCAPNG_PB => {
+                    return self.pcapng_packet(typ, &body).map(Some);
+                },
+                Some(_) => {},
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn next_pcap(&mut self) -> io::Result<Option<(FrameHeader, Vec<u8>)>> {
+        let mut rec = [0u8; PCAP_REC_HDR_LEN];
+        if !read_or_eof(&mut self.r, &mut rec)? {
+            return Ok(None);
+        }
+        let ts_units = self.interfaces[0].ts_units;
+        let caplen = self.u32(&rec[8..12]);
+        if caplen as usize > MAX_BLOCK_LEN {
+            return Err(invalid("bad pcap record length"));
+        }
+        let hdr = FrameHeader {
+            ts: timespec(self.u32(&rec[0..4]) as u64, self.u32(&rec[4..8]) as u64, ts_units),
+            caplen: caplen,
+            len: self.u32(&rec[12..16]),
+        };
+        let mut data = vec![0u8; caplen as usize];
+        self.r.read_exact(&mut data)?;
+        Ok(Some((hdr, data)))
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<(FrameHeader, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = match self.format {
+            Format::Pcap => self.next_pcap(),
+            Format::Pcapng => self.next_pcapng(),
+        };
+        match res {
+            Ok(Some(frame)) => Some(Ok(frame)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn header() -> FrameHeader {
+        FrameHeader { ts: Timespec::new(1_500_000_000, 250_000_000), caplen: 5, len: 60 }
+    }
+
+    #[test]
+    fn pcap_layout() {
+        let mut buf = Vec::new();
+        write_pcap_header(&mut buf, &ETHERNET).unwrap();
+        write_pcap_record(&mut buf, &ETHERNET, &header(), &[1, 2, 3, 4, 5]).unwrap();
+
+        assert_eq!(buf.len(), 24 + 16 + 5);
+        assert_eq!(&buf[0..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+        assert_eq!(&buf[20..24], &[1, 0, 0, 0]);
+        assert_eq!(&buf[28..32], &[0x90, 0xd0, 0x03, 0x00]);
+        assert_eq!(&buf[32..36], &[5, 0, 0, 0]);
+        assert_eq!(&buf[36..40], &[60, 0, 0, 0]);
+        assert_eq!(&buf[40..], &[1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn pcapng_layout() {
+        let mut buf = Vec::new();
+        write_pcapng_header(&mut buf, &ETHERNET).unwrap();
+        assert_eq!(buf.len(), 28 + 20);
+        write_pcapng_record(&mut buf, &ETHERNET, &header(), &[1, 2, 3, 4, 5]).unwrap();
+
+        let epb = &buf[48..];
+        assert_eq!(epb.len(), 40);
+        assert_eq!(&epb[0..4], &[6, 0, 0, 0]);
+        assert_eq!(&epb[4..8], &[40, 0, 0, 0]);
+        // Microseconds since the epoch, high word first.
+        assert_eq!(&epb[12..16], &[0x3d, 0x54, 0x05, 0x00]);
+        assert_eq!(&epb[16..20], &[0x90, 0x90, 0x2d, 0xf7]);
+        assert_eq!(&epb[28..36], &[1, 2, 3, 4, 5, 0, 0, 0]);
+        assert_eq!(&epb[36..40], &[40, 0, 0, 0]);
+    }
+
+    fn frames() -> Vec<(FrameHeader, Vec<u8>)> {
+        vec![
+            (FrameHeader { ts: Timespec::new(1_500_000_000, 123_456_000), caplen: 3, len: 60 }, vec![1, 2, 3]),
+            (FrameHeader { ts: Timespec::new(1_500_000_001, 0), caplen: 5, len: 5 }, vec![4, 5, 6, 7, 8]),
+        ]
+    }
+
+    fn roundtrip(format: Format) {
+        let frames = frames();
+        let mut buf = Vec::new();
+        write_to(&mut buf, format, &ETHERNET, frames.iter().map(|&(ref h, ref d)| (h, &d[..]))).unwrap();
+
+        let rdr = Reader::new(Cursor::new(buf)).unwrap();
+        assert_eq!(rdr.format(), format);
+        assert_eq!(rdr.linktype(), Some(LINKTYPE_ETHERNET));
+        let read: Vec<(FrameHeader, Vec<u8>)> = rdr.map(|r| r.unwrap()).collect();
+        assert_eq!(read, frames);
+    }
+
+    #[test]
+    fn pcap_roundtrip() {
+        roundtrip(Format::Pcap);
+    }
+
+    #[test]
+    fn pcapng_roundtrip() {
+        roundtrip(Format::Pcapng);
+    }
+
+    fn nsec_roundtrip(format: Format) {
+        let link = Link { linktype: 113, ts_units: 1_000_000_000 };
+        let hdr = FrameHeader { ts: Timespec::new(1_500_000_000, 123_456_789), caplen: 3, len: 3 };
+        let data = vec![1, 2, 3];
+        let mut buf = Vec::new();
+        write_to(&mut buf, format, &link, Some((&hdr, &data[..])).into_iter()).unwrap();
+
+        let mut rdr = Reader::new(Cursor::new(buf)).unwrap();
+        assert_eq!(rdr.link(), Some(link));
+        assert_eq!(rdr.next().unwrap().unwrap(), (hdr, data));
+    }
+
+    #[test]
+    fn pcap_keeps_link_and_nanoseconds() {
+        nsec_roundtrip(Format::Pcap);
+    }
+
+    #[test]
+    fn pcapng_keeps_link_and_nanoseconds() {
+        nsec_roundtrip(Format::Pcapng);
+    }
+
+    #[test]
+    fn pcapng_refuses_wide_linktype() {
+        let link = Link { linktype: 0x10000, ts_units: 1_000_000 };
+        let frames = frames();
+        let mut buf = Vec::new();
+        assert!(write_to(&mut buf, Format::Pcapng, &link, frames.iter().map(|&(ref h, ref d)| (h, &d[..]))).is_err());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn big_endian_nanosecond_pcap() {
+        let mut buf = Vec::new();
+        buf.write_u32::<BigEndian>(PCAP_MAGIC_NSEC).unwrap();
+        buf.write_u16::<BigEndian>(2).unwrap();
+        buf.write_u16::<BigEndian>(4).unwrap();
+        buf.write_u32::<BigEndian>(0).unwrap();
+        buf.write_u32::<BigEndian>(0).unwrap();
+        buf.write_u32::<BigEndian>(SNAPLEN).unwrap();
+        buf.write_u32::<BigEndian>(LINKTYPE_ETHERNET).unwrap();
+        buf.write_u32::<BigEndian>(7).unwrap();
+        buf.write_u32::<BigEndian>(999_999_999).unwrap();
+        buf.write_u32::<BigEndian>(2).unwrap();
+        buf.write_u32::<BigEndian>(64).unwrap();
+        buf.extend(&[0xaa, 0xbb]);
+
+        let mut rdr = Reader::new(Cursor::new(buf)).unwrap();
+        let (hdr, data) = rdr.next().unwrap().unwrap();
+        assert_eq!(hdr, FrameHeader { ts: Timespec::new(7, 999_999_999), caplen: 2, len: 64 });
+        assert_eq!(data, vec![0xaa, 0xbb]);
+        assert!(rdr.next().is_none());
+    }
+
+    #[test]
+    fn pcapng_timestamp_resolution() {
+        let mut buf = Vec::new();
+        buf.write_u32::<LittleEndian>(PCAPNG_SHB).unwrap();
+        buf.write_u32::<LittleEndian>(28).unwrap();
+        buf.write_u32::<LittleEndian>(PCAPNG_BYTE_ORDER_MAGIC).unwrap();
+        buf.write_u16::<LittleEndian>(1).unwrap();
+        buf.write_u16::<LittleEndian>(0).unwrap();
+        buf.write_i64::<LittleEndian>(-1).unwrap();
+        buf.write_u32::<LittleEndian>(28).unwrap();
+        // Interface Description Block with if_tsresol = 10^-9.
+        buf.write_u32::<LittleEndian>(PCAPNG_IDB).unwrap();
+        buf.write_u32::<LittleEndian>(32).unwrap();
+        buf.write_u16::<LittleEndian>(LINKTYPE_ETHERNET as u16).unwrap();
+        buf.write_u16::<LittleEndian>(0).unwrap();
+        buf.write_u32::<LittleEndian>(SNAPLEN).unwrap();
+        buf.write_u16::<LittleEndian>(PCAPNG_IF_TSRESOL).unwrap();
+        buf.write_u16::<LittleEndian>(1).unwrap();
+        buf.extend(&[9, 0, 0, 0]);
+        buf.write_u32::<LittleEndian>(0).unwrap();
+        buf.write_u32::<LittleEndian>(32).unwrap();
+        // Enhanced Packet Block at 2.5 seconds.
+        let ts: u64 = 2_500_000_000;
+        buf.write_u32::<LittleEndian>(PCAPNG_EPB).unwrap();
+        buf.write_u32::<LittleEndian>(36).unwrap();
+        buf.write_u32::<LittleEndian>(0).unwrap();
+        buf.write_u32::<LittleEndian>((ts >> 32) as u32).unwrap();
+        buf.write_u32::<LittleEndian>(ts as u32).unwrap();
+        buf.write_u32::<LittleEndian>(1).unwrap();
+        buf.write_u32::<LittleEndian>(1).unwrap();
+        buf.extend(&[0x42, 0, 0, 0]);
+        buf.write_u32::<LittleEndian>(36).unwrap();
+
+        let mut rdr = Reader::new(Cursor::new(buf)).unwrap();
+        let (hdr, data) = rdr.next().unwrap().unwrap();
+        assert_eq!(hdr.ts, Timespec::new(2, 500_000_000));
+        assert_eq!(data, vec![0x42]);
+    }
+
+    #[test]
+    fn truncated_record_is_an_error() {
+        let frames = frames();
+        let mut buf = Vec::new();
+        write_to(&mut buf, Format::Pcap, &ETHERNET, frames.iter().map(|&(ref h, ref d)| (h, &d[..]))).unwrap();
+        let len = buf.len();
+        buf.truncate(len - 1);
+
+        let mut rdr = Reader::new(Cursor::new(buf)).unwrap();
+        assert!(rdr.next().unwrap().is_ok());
+        assert!(rdr.next().unwrap().is_err());
+        assert!(rdr.next().is_none());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(Reader::new(Cursor::new(b"GIF89a not a capture".to_vec())).is_err());
+    }
+}
@@ -0,0 +1,49 @@
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use guile_sys::scm_c_primitive_load;
+
+use scheme::catch_all;
+
+const SYSTEM_PLUGIN_DIR: &str = "/usr/share/wire_shake/dissectors";
+
+fn user_plugin_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".config")))
+        .map(|d| d.join("wire_shake").join("dissectors"))
+}
+
+// Later directories win when two plugins register the same key.
+pub fn plugin_dirs(extra: &[PathBuf]) -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from(SYSTEM_PLUGIN_DIR)];
+    dirs.extend(user_plugin_dir());
+    dirs.extend(extra.iter().cloned());
+    dirs
+}
+
+fn scm_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path())
+            .filter(|p| p.is_file() && p.extension().map_or(false, |e| e == "scm"))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    files
+}
+
+// Loads every `.scm` file in `dirs`, in order. A file that fails to load
+// is skipped and its error returned; the rest still load.
+pub fn load_plugins(dirs: &[PathBuf]) -> Vec<String> {
+    let mut errors = Vec::new();
+    for dir in dirs {
+        for file in scm_files(dir) {
+            let path = CString::new(file.to_string_lossy().into_owned()).unwrap();
+            if let Err(e) = catch_all(|| unsafe { scm_c_primitive_load(path.as_ptr()) }) {
+                errors.push(format!("{}: {}", file.display(), e));
+            }
+        }
+    }
+    errors
+}
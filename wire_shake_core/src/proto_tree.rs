@@ -0,0 +1,323 @@
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use sexp;
+use sexp::{Sexp, Atom};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    None,
+    UInt(u64),
+    Int(i64),
+    Bool(bool),
+    Str(String),
+    Bytes(Vec<u8>),
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    Ether([u8; 6]),
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FieldValue::None => Ok(()),
+            FieldValue::UInt(v) => write!(f, "{}", v),
+            FieldValue::Int(v) => write!(f, "{}", v),
+            FieldValue::Bool(v) => write!(f, "{}", if v { 1 } else { 0 }),
+            FieldValue::Str(ref v) => write!(f, "{}", v),
+            FieldValue::Bytes(ref v) => {
+                for (i, b) in v.iter().enumerate() {
+                    if i > 0 { write!(f, ":")?; }
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            },
+            FieldValue::Ipv4(ref v) => write!(f, "{}", v),
+            FieldValue::Ipv6(ref v) => write!(f, "{}", v),
+            FieldValue::Ether(ref v) => write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+                                               v[0], v[1], v[2], v[3], v[4], v[5]),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldNode {
+    pub name: String,
+    pub label: String,
+    pub value: String,
+    pub typed: FieldValue,
+    pub offset: Option<usize>,
+    pub len: usize,
+    pub hidden: bool,
+    pub children: Vec<FieldNode>,
+}
+
+impl FieldNode {
+    pub fn new(name: &str, label: &str, typed: FieldValue, offset: usize, len: usize) -> FieldNode {
+        FieldNode {
+            name: name.to_string(),
+            label: label.to_string(),
+            value: typed.to_string(),
+            typed: typed,
+            offset: Some(offset),
+            len: len,
+            hidden: false,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn proto(name: &str, label: &str, summary: String, offset: usize, len: usize) -> FieldNode {
+        FieldNode::new(name, label, FieldValue::None, offset, len).display(summary)
+    }
+
+    pub fn generated(name: &str, label: &str, typed: FieldValue) -> FieldNode {
+        let mut node = FieldNode::new(name, label, typed, 0, 0);
+        node.offset = None;
+        node
+    }
+
+    pub fn text(label: &str, value: String) -> FieldNode {
+        FieldNode {
+            name: String::new(),
+            label: label.to_string(),
+            value: value,
+            typed: FieldValue::None,
+            offset: None,
+            len: 0,
+            hidden: false,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn display(mut self, value: String) -> FieldNode {
+        self.value = value;
+        self
+    }
+
+    pub fn hide(mut self) -> FieldNode {
+        self.hidden = true;
+        self
+    }
+
+    pub fn add(&mut self, child: FieldNode) -> &mut FieldNode {
+        self.children.push(child);
+        self.children.last_mut().unwrap()
+    }
+
+    pub fn display_text(&self) -> String {
+        if self.value.is_empty() {
+            self.label.clone()
+        } else {
+            format!("{}: {}", self.label, self.value)
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<&FieldNode> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children.iter().filter_map(|c| c.find(name)).next()
+    }
+
+    fn find_mut(&mut self, name: &str) -> Option<&mut FieldNode> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children.iter_mut().filter_map(|c| c.find_mut(name)).next()
+    }
+
+    fn shift_offsets(&mut self, by: usize) {
+        self.offset = self.offset.map(|off| off + by);
+        for c in &mut self.children {
+            c.shift_offsets(by);
+        }
+    }
+
+    fn clear_offsets(&mut self) {
+        self.offset = None;
+        self.len = 0;
+        for c in &mut self.children {
+            c.clear_offsets();
+        }
+    }
+
+    fn any_named<F: FnMut(&FieldNode) -> bool>(&self, name: &str, f: &mut F) -> bool {
+        (self.name == name && f(self)) || self.children.iter().any(|c| c.any_named(name, f))
+    }
+
+    fn collect_fields(&self, out: &mut Vec<(String, String)>) {
+        if !self.name.is_empty() {
+            out.push((self.name.clone(), self.typed.to_string()));
+        }
+        for c in &self.children {
+            c.collect_fields(out);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProtoTree {
+    pub nodes: Vec<FieldNode>,
+}
+
+impl ProtoTree {
+    pub fn new() -> ProtoTree {
+        ProtoTree { nodes: Vec::new() }
+    }
+
+    pub fn add(&mut self, node: FieldNode) -> &mut FieldNode {
+        self.nodes.push(node);
+        self.nodes.last_mut().unwrap()
+    }
+
+    pub fn append(&mut self, mut other: ProtoTree) {
+        self.nodes.append(&mut other.nodes);
+    }
+
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut FieldNode> {
+        self.nodes.iter_mut().filter_map(|n| n.find_mut(name)).next()
+    }
+
+    // Scheme dissectors count offsets from the start of the bytes they
+    // were handed; this makes them relative to the frame.
+    pub fn shift_offsets(&mut self, by: usize) {
+        for n in &mut self.nodes {
+            n.shift_offsets(by);
+        }
+    }
+
+    // For trees built from reassembled data, which has no position in the
+    // frame's bytes.
+    pub fn clear_offsets(&mut self) {
+        for n in &mut self.nodes {
+            n.clear_offsets();
+        }
+    }
+
+    pub fn fields(&self) -> Vec<(String, String)> {
+        let mut out = Vec::new();
+        for n in &self.nodes {
+            n.collect_fields(&mut out);
+        }
+        out
+    }
+
+    // Whether some field called `name` satisfies `f`; unlike `fields` it
+    // allocates nothing.
+    pub fn any_field<F: FnMut(&FieldNode) -> bool>(&self, name: &str, mut f: F) -> bool {
+        self.nodes.iter().any(|n| n.any_named(name, &mut f))
+    }
+
+    pub fn find(&self, name: &str) -> Option<&FieldNode> {
+        self.nodes.iter().filter_map(|n| n.find(name)).next()
+    }
+
+    //<node>    := (<key-val> <child> <next>)
+    //<key-val> := (<string> <string>) | (<string> <string> <offset> <length>)
+    //<child>   := <node> | ()
+    //<next>    := <node> | ()
+    pub fn from_sexp(s: &str) -> Result<ProtoTree, String> {
+        let sxp = sexp::parse(s).map_err(|e| e.to_string())?;
+        let mut tree = ProtoTree::new();
+        nodes_from_sexp(&sxp, &mut tree.nodes)?;
+        Ok(tree)
+    }
+
+    pub fn to_sexp(&self) -> String {
+        nodes_to_sexp(&self.nodes)
+    }
+}
+
+fn atom_str(sxp: &Sexp) -> Option<String> {
+    match *sxp {
+        Sexp::Atom(Atom::S(ref s)) => Some(s.clone()),
+        Sexp::Atom(Atom::I(i)) => Some(i.to_string()),
+        Sexp::Atom(Atom::F(f)) => Some(f.to_string()),
+        _ => None,
+    }
+}
+
+fn node_from_key_val(sxp: &Sexp) -> Result<FieldNode, String> {
+    if let Sexp::List(ref kv) = *sxp {
+        if kv.len() == 2 || kv.len() == 4 {
+            if let (Some(lbl), Some(val)) = (atom_str(&kv[0]), atom_str(&kv[1])) {
+                // Kept typed so display filters compare it as a number.
+                let typed = match kv[1] {
+                    Sexp::Atom(Atom::I(i)) if i >= 0 => FieldValue::UInt(i as u64),
+                    Sexp::Atom(Atom::I(i)) => FieldValue::Int(i),
+                    _ => FieldValue::Str(val.clone()),
+                };
+                let mut node = FieldNode::text(&lbl, val);
+                node.typed = typed;
+                if kv.len() == 4 {
+                    if let (&Sexp::Atom(Atom::I(off)), &Sexp::Atom(Atom::I(len))) = (&kv[2], &kv[3]) {
+                        node.offset = Some(off as usize);
+                        node.len = len as usize;
+                    }
+                }
+                return Ok(node);
+            }
+        }
+    }
+    Err(format!("malformed key-value: {}", sxp))
+}
+
+fn nodes_from_sexp(sxp: &Sexp, out: &mut Vec<FieldNode>) -> Result<(), String> {
+    match *sxp {
+        Sexp::List(ref lst) if lst.is_empty() => Ok(()),
+        Sexp::List(ref lst) if lst.len() == 3 => {
+            let mut node = node_from_key_val(&lst[0])?;
+            nodes_from_sexp(&lst[1], &mut node.children)?;
+            out.push(node);
+            nodes_from_sexp(&lst[2], out)
+        },
+        _ => Err(format!("malformed node: {}", sxp)),
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn nodes_to_sexp(nodes: &[FieldNode]) -> String {
+    match nodes.split_first() {
+        None => String::from("()"),
+        Some((n, rest)) => {
+            let kv = match n.offset {
+                Some(off) => format!("({} {} {} {})", quote(&n.label), quote(&n.value), off, n.len),
+                None => format!("({} {})", quote(&n.label), quote(&n.value)),
+            };
+            format!("({} {} {})", kv, nodes_to_sexp(&n.children), nodes_to_sexp(rest))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sexp_roundtrip() {
+        let mut tree = ProtoTree::new();
+        {
+            let n = tree.add(FieldNode::text("Greeting", String::from("say hi")));
+            n.offset = Some(4);
+            n.len = 2;
+            n.add(FieldNode::text("Inner", String::from("1")));
+        }
+        tree.add(FieldNode::text("Trailer", String::from("x")));
+
+        let back = ProtoTree::from_sexp(&tree.to_sexp()).unwrap();
+        assert_eq!(back.nodes.len(), 2);
+        assert_eq!(back.nodes[0].value, "say hi");
+        assert_eq!(back.nodes[0].offset, Some(4));
+        assert_eq!(back.nodes[0].len, 2);
+        assert_eq!(back.nodes[0].children[0].label, "Inner");
+        assert_eq!(back.nodes[1].offset, None);
+    }
+
+    #[test]
+    fn rejects_malformed_sexp() {
+        assert!(ProtoTree::from_sexp("((\"a\") () ())").is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::rc::Rc;
+use libc::c_void;
+use guile_sys::{
+    SCM, scm_internal_catch, scm_c_eval_string, scm_call_2,
+    scm_variable_ref, scm_c_lookup, scm_to_locale_string,
+    scm_c_define_gsubr, scm_from_pointer, scm_c_define, scm_with_guile
+};
+
+use disctr::{
+    DissectorTable, set_dissector, set_info,
+    set_proto, set_desegment_len,
+    set_heuristic_dissector, add_field, add_subtree,
+    set_src, set_dst, set_ports, call_next_dissector,
+};
+
+const DISSECTOR_API: &str = include_str!("dissector_api.scm");
+
+unsafe extern "C" fn catch_body<F: FnMut() -> SCM>(data: *mut c_void) -> SCM {
+    let body = &mut *(data as *mut F);
+    body()
+}
+
+unsafe extern "C" fn catch_handler(data: *mut c_void, key: SCM, args: SCM) -> SCM {
+    let err = &mut *(data as *mut Option<String>);
+    let to_string = scm_variable_ref(scm_c_lookup(CString::new("%exception->string").unwrap().as_ptr()));
+    let msg = CString::from_raw(scm_to_locale_string(scm_call_2(to_string, key, args))).into_string().unwrap();
+    *err = Some(msg.trim_right().to_string());
+    key
+}
+
+// Runs `body` under a catch-all handler so a Scheme error comes back as
+// its printed message instead of unwinding through Rust frames.
+pub fn catch_all<F: FnMut() -> SCM>(mut body: F) -> Result<SCM, String> {
+    let mut err: Option<String> = None;
+    let res = unsafe {
+        let tag = scm_c_eval_string(CString::new("#t").unwrap().as_ptr());
+        scm_internal_catch(tag,
+                           Some(catch_body::<F>), &mut body as *mut F as *mut c_void,
+                           Some(catch_handler), &mut err as *mut _ as *mut c_void)
+    };
+    match err {
+        Some(e) => Err(e),
+        None => Ok(res),
+    }
+}
+
+unsafe extern "C" fn init_guile(dissector_tbl: *mut c_void) -> *mut c_void {
+    let prc = set_dissector as *mut fn(SCM, SCM, SCM, SCM) -> SCM as *mut c_void;
+    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"set-dissector\0").unwrap().as_ptr(), 4, 0, 0, prc);
+
+    let prc = set_heuristic_dissector as *mut fn(SCM, SCM, SCM, SCM, SCM) -> SCM as *mut c_void;
+    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"set-heuristic-dissector\0").unwrap().as_ptr(), 5, 0, 0, prc);
+
+    let prc = set_proto as *mut fn(SCM, SCM) -> SCM as *mut c_void;
+    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"set-proto\0").unwrap().as_ptr(), 2, 0, 0, prc);
+
+    let prc = set_info as *mut fn(SCM, SCM) -> SCM as *mut c_void;
+    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"set-info\0").unwrap().as_ptr(), 2, 0, 0, prc);
+
+    let prc = set_desegment_len as *mut fn(SCM, SCM) -> SCM as *mut c_void;
+    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"set-desegment-len\0").unwrap().as_ptr(), 2, 0, 0, prc);
+
+    let prc = add_field as *mut fn(SCM, SCM, SCM, SCM, SCM, SCM, SCM) -> SCM as *mut c_void;
+    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"%add-field\0").unwrap().as_ptr(), 7, 0, 0, prc);
+
+    let prc = add_subtree as *mut fn(SCM, SCM, SCM, SCM, SCM, SCM, SCM) -> SCM as *mut c_void;
+    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"%add-subtree\0").unwrap().as_ptr(), 7, 0, 0, prc);
+
+    let prc = set_src as *mut fn(SCM, SCM) -> SCM as *mut c_void;
+    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"set-src\0").unwrap().as_ptr(), 2, 0, 0, prc);
+
+    let prc = set_dst as *mut fn(SCM, SCM) -> SCM as *mut c_void;
+    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"set-dst\0").unwrap().as_ptr(), 2, 0, 0, prc);
+
+    let prc = set_ports as *mut fn(SCM, SCM, SCM) -> SCM as *mut c_void;
+    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"set-ports\0").unwrap().as_ptr(), 3, 0, 0, prc);
+
+    let prc = call_next_dissector as *mut fn(SCM, SCM, SCM, SCM, SCM) -> SCM as *mut c_void;
+    scm_c_define_gsubr(CStr::from_bytes_with_nul(b"%call-next-dissector\0").unwrap().as_ptr(), 5, 0, 0, prc);
+
+    let dsctr_tbl = scm_from_pointer(dissector_tbl, None);
+    scm_c_define(CStr::from_bytes_with_nul(b"dissector-table\0").unwrap().as_ptr(), dsctr_tbl);
+
+    scm_c_eval_string(CString::new(DISSECTOR_API).unwrap().as_ptr());
+
+    dissector_tbl
+}
+
+// Starts Guile and defines the procedures dissector plugins use, with
+// `register-dissector` adding to `disct_tbl`. Call it once, before
+// loading plugins. Scheme keeps a raw pointer to the table, so it must
+// stay in this Rc for as long as plugins may run.
+pub fn init(disct_tbl: &Rc<RefCell<DissectorTable>>) {
+    unsafe {
+        scm_with_guile(Some(init_guile), disct_tbl.as_ptr() as *mut c_void);
+    }
+}